@@ -0,0 +1,342 @@
+use aes::Aes128;
+use cmac::{Cmac, Mac};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use std::path::Path;
+
+use crate::core::extension;
+use crate::core::ntp::NtpError;
+
+const DEFAULT_KEYS_FILES: [&str; 3] = ["/etc/ntp.keys", "/etc/ntp/keys", "/etc/chrony.keys"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyAlgorithm {
+    Md5,
+    Sha1,
+    AesCmac,
+}
+
+impl KeyAlgorithm {
+    fn digest_len(self) -> usize {
+        match self {
+            KeyAlgorithm::Md5 => 16,
+            KeyAlgorithm::Sha1 => 20,
+            KeyAlgorithm::AesCmac => 16,
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "M" | "MD5" => Some(KeyAlgorithm::Md5),
+            "SHA1" | "SHA-1" => Some(KeyAlgorithm::Sha1),
+            "AES128CMAC" | "AES-128-CMAC" | "CMAC" | "AES128" => Some(KeyAlgorithm::AesCmac),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NtpKey {
+    pub id: u32,
+    pub algorithm: KeyAlgorithm,
+    pub key: Vec<u8>,
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// 解析金鑰內容，支援 chrony 的 HEX:/ASCII: 前綴與 ntpd 的長度判斷規則
+fn decode_key(text: &str) -> Option<Vec<u8>> {
+    if let Some(hex) = text.strip_prefix("HEX:") {
+        return decode_hex(hex);
+    }
+    if let Some(ascii) = text.strip_prefix("ASCII:") {
+        return Some(ascii.as_bytes().to_vec());
+    }
+    if text.len() > 20 {
+        if let Some(bytes) = decode_hex(text) {
+            return Some(bytes);
+        }
+    }
+    Some(text.as_bytes().to_vec())
+}
+
+/// 讀取 ntpd/chrony 格式的 ntp.keys，每行為 `keyid [type] key`
+pub fn parse_keys(content: &str) -> Vec<NtpKey> {
+    let mut keys = Vec::new();
+
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        let (id, algorithm, key) = match fields.as_slice() {
+            [id, kind, key, ..] => match KeyAlgorithm::parse(kind) {
+                Some(algorithm) => (id, algorithm, key),
+                None => continue,
+            },
+            // chrony 省略類型時預設為 MD5
+            [id, key] => (id, KeyAlgorithm::Md5, key),
+            _ => continue,
+        };
+
+        let (Ok(id), Some(key)) = (id.parse::<u32>(), decode_key(key)) else {
+            continue;
+        };
+        if id == 0 || key.is_empty() {
+            continue;
+        }
+        if algorithm == KeyAlgorithm::AesCmac && key.len() != 16 {
+            continue;
+        }
+
+        keys.push(NtpKey { id, algorithm, key });
+    }
+
+    keys
+}
+
+pub fn load_keys_file(path: &Path) -> Result<Vec<NtpKey>, NtpError> {
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        NtpError::new(
+            "AUTH_KEY_NOT_FOUND",
+            format!("無法讀取金鑰檔 {}: {}", path.display(), e),
+        )
+    })?;
+    Ok(parse_keys(&content))
+}
+
+pub fn find_key(keys_file: Option<&str>, key_id: u32) -> Result<NtpKey, NtpError> {
+    let candidates: Vec<&str> = match keys_file {
+        Some(path) => vec![path],
+        None => DEFAULT_KEYS_FILES
+            .iter()
+            .copied()
+            .filter(|p| Path::new(p).exists())
+            .collect(),
+    };
+
+    for path in candidates {
+        if let Some(key) = load_keys_file(Path::new(path))?
+            .into_iter()
+            .find(|k| k.id == key_id)
+        {
+            return Ok(key);
+        }
+    }
+
+    Err(NtpError::new(
+        "AUTH_KEY_NOT_FOUND",
+        format!("找不到金鑰 ID {}", key_id),
+    ))
+}
+
+fn compute_digest(key: &NtpKey, data: &[u8]) -> Vec<u8> {
+    match key.algorithm {
+        KeyAlgorithm::Md5 => {
+            let mut hasher = Md5::new();
+            hasher.update(&key.key);
+            hasher.update(data);
+            hasher.finalize().to_vec()
+        }
+        KeyAlgorithm::Sha1 => {
+            let mut hasher = Sha1::new();
+            hasher.update(&key.key);
+            hasher.update(data);
+            hasher.finalize().to_vec()
+        }
+        KeyAlgorithm::AesCmac => {
+            let mut mac =
+                <Cmac<Aes128> as Mac>::new_from_slice(&key.key).expect("CMAC key length");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }
+    }
+}
+
+/// 在封包後附加 key ID 與 MAC
+pub fn sign_packet(packet: &mut Vec<u8>, key: &NtpKey) {
+    let digest = compute_digest(key, packet);
+    packet.extend_from_slice(&key.id.to_be_bytes());
+    packet.extend_from_slice(&digest);
+}
+
+/// 驗證回應尾端的 MAC，設定金鑰時未認證的回應一律拒絕
+pub fn verify_packet(response: &[u8], key: &NtpKey) -> Result<(), NtpError> {
+    let mac = extension::parse(response)?
+        .mac
+        .ok_or_else(|| NtpError::new("AUTH_FAILED", "回應未包含 MAC"))?;
+
+    if mac.len() == 4 {
+        return Err(NtpError::new("AUTH_FAILED", "伺服器回應 crypto-NAK，金鑰不被接受"));
+    }
+    if mac.len() != 4 + key.algorithm.digest_len() {
+        return Err(NtpError::new("AUTH_FAILED", "回應的 MAC 長度與金鑰演算法不符"));
+    }
+
+    let data = &response[..response.len() - mac.len()];
+    let key_id = u32::from_be_bytes([mac[0], mac[1], mac[2], mac[3]]);
+    if key_id != key.id {
+        return Err(NtpError::new(
+            "AUTH_FAILED",
+            format!("回應的金鑰 ID 不符 (expected={}, got={})", key.id, key_id),
+        ));
+    }
+
+    let expected = compute_digest(key, data);
+    let diff = expected
+        .iter()
+        .zip(mac[4..].iter())
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+    if diff != 0 {
+        return Err(NtpError::new("AUTH_FAILED", "回應 MAC 驗證失敗"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: u32, algorithm: KeyAlgorithm, hex: &str) -> NtpKey {
+        NtpKey {
+            id,
+            algorithm,
+            key: decode_hex(hex).unwrap(),
+        }
+    }
+
+    fn header() -> Vec<u8> {
+        let mut packet = vec![0u8; 48];
+        packet[0] = 0x23;
+        packet[40..48].copy_from_slice(&[0xE8, 0x00, 0x00, 0x00, 0x12, 0x34, 0x56, 0x78]);
+        packet
+    }
+
+    #[test]
+    fn known_answer_digests() {
+        // ntpd 的 MAC 為 H(key || data)，金鑰 "a" 加上 "bc" 即 RFC 1321/FIPS 180 的 "abc" 測試向量
+        let md5 = key(1, KeyAlgorithm::Md5, "61");
+        assert_eq!(
+            compute_digest(&md5, b"bc"),
+            decode_hex("900150983cd24fb0d6963f7d28e17f72").unwrap()
+        );
+        let sha1 = key(2, KeyAlgorithm::Sha1, "61");
+        assert_eq!(
+            compute_digest(&sha1, b"bc"),
+            decode_hex("a9993e364706816aba3e25717850c26c9cd0d89d").unwrap()
+        );
+
+        // RFC 4493 §4 AES-CMAC 範例 1、2
+        let cmac = key(3, KeyAlgorithm::AesCmac, "2b7e151628aed2a6abf7158809cf4f3c");
+        assert_eq!(
+            compute_digest(&cmac, b""),
+            decode_hex("bb1d6929e95937287fa37d129b756746").unwrap()
+        );
+        assert_eq!(
+            compute_digest(
+                &cmac,
+                &decode_hex("6bc1bee22e409f96e93d7e117393172a").unwrap()
+            ),
+            decode_hex("070a16b46b4d4144f79bdd9dd04a287c").unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_chrony_and_ntpd_keys() {
+        let keys = parse_keys(
+            "# chrony.keys\n\
+             1 MD5 HEX:0102030405\n\
+             2 SHA1 ASCII:secret # comment\n\
+             3 AES128 HEX:2b7e151628aed2a6abf7158809cf4f3c\n\
+             4 plaintext\n\
+             5 SHA1 0123456789abcdef0123456789abcdef01234567\n\
+             6 AES128 HEX:0102\n\
+             7 UNKNOWN HEX:01\n\
+             0 MD5 zero\n",
+        );
+        let ids: Vec<u32> = keys.iter().map(|k| k.id).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+        assert_eq!(keys[0].key, vec![1, 2, 3, 4, 5]);
+        assert_eq!(keys[1].algorithm, KeyAlgorithm::Sha1);
+        assert_eq!(keys[1].key, b"secret".to_vec());
+        assert_eq!(keys[2].algorithm, KeyAlgorithm::AesCmac);
+        assert_eq!(keys[2].key.len(), 16);
+        assert_eq!(keys[3].algorithm, KeyAlgorithm::Md5);
+        assert_eq!(keys[3].key, b"plaintext".to_vec());
+        assert_eq!(keys[4].key.len(), 20);
+    }
+
+    #[test]
+    fn signed_packet_verifies() {
+        for algorithm in [KeyAlgorithm::Md5, KeyAlgorithm::Sha1, KeyAlgorithm::AesCmac] {
+            let key = key(42, algorithm, "2b7e151628aed2a6abf7158809cf4f3c");
+            let mut packet = header();
+            sign_packet(&mut packet, &key);
+            assert_eq!(packet.len(), 48 + 4 + algorithm.digest_len());
+            verify_packet(&packet, &key).unwrap();
+
+            packet[47] ^= 1;
+            assert_eq!(
+                verify_packet(&packet, &key).unwrap_err().code,
+                "AUTH_FAILED"
+            );
+        }
+    }
+
+    #[test]
+    fn wrong_key_id_is_rejected() {
+        let signer = key(1, KeyAlgorithm::Sha1, "0102030405");
+        let mut packet = header();
+        sign_packet(&mut packet, &signer);
+
+        let verifier = NtpKey { id: 2, ..signer };
+        let error = verify_packet(&packet, &verifier).unwrap_err();
+        assert_eq!(error.code, "AUTH_FAILED");
+        assert!(error.error.contains("金鑰 ID"));
+    }
+
+    #[test]
+    fn truncated_mac_is_rejected() {
+        let sha1 = key(1, KeyAlgorithm::Sha1, "0102030405");
+        let mut packet = header();
+        sign_packet(&mut packet, &sha1);
+        // 24 位元組的 SHA1 MAC 截成 20 位元組時長度仍合法，但與演算法不符
+        packet.truncate(packet.len() - 4);
+        let error = verify_packet(&packet, &sha1).unwrap_err();
+        assert!(error.error.contains("長度"));
+
+        let md5 = key(1, KeyAlgorithm::Md5, "0102030405");
+        let mut packet = header();
+        sign_packet(&mut packet, &md5);
+        packet.truncate(packet.len() - 4);
+        assert!(verify_packet(&packet, &md5).is_err());
+
+        assert!(verify_packet(&header(), &md5).is_err());
+    }
+
+    #[test]
+    fn mac_after_extension_fields() {
+        let key = key(7, KeyAlgorithm::Md5, "0102030405");
+        let mut packet = header();
+        extension::write_field(&mut packet, 0x0104, &[0xAB; 32]);
+        extension::write_field(&mut packet, 0x0204, &[0xCD; 16]);
+        sign_packet(&mut packet, &key);
+
+        let parsed = extension::parse(&packet).unwrap();
+        assert_eq!(parsed.fields.len(), 2);
+        verify_packet(&packet, &key).unwrap();
+
+        // MAC 涵蓋擴充欄位，修改欄位內容即驗證失敗
+        packet[60] ^= 1;
+        assert!(verify_packet(&packet, &key).is_err());
+    }
+}
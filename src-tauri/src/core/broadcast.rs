@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::core::ntp::{
+    self, Exchange, NtpError, NtpResult, QueryOptions, SampleMode, ServerSpec,
+    NTP_MAX_RESPONSE_SIZE, NTP_PORT,
+};
+use crate::core::packet::NtpPacket;
+use crate::core::timestamp::{NtpTimestamp, DEFAULT_PIVOT_UNIX_SECS};
+use crate::core::timestamping::{self, TimestampSource};
+
+/// IANA 指定的 NTP IPv4 multicast 群組
+const NTP_MULTICAST_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 1, 1);
+/// 超過此時間重新以 client/server 交換校準單程延遲
+const CALIBRATION_INTERVAL: Duration = Duration::from_secs(3600);
+/// 接收失敗後重試前的等待時間，避免持續性錯誤讓執行緒空轉
+const RECV_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// 一個廣播封包與本機接收時間
+#[derive(Clone)]
+struct BroadcastSample {
+    packet: Vec<u8>,
+    header: NtpPacket,
+    peer: SocketAddr,
+    t4: NtpTimestamp,
+    t4_source: TimestampSource,
+    received_at: Instant,
+}
+
+impl BroadcastSample {
+    /// 超過兩個廣播週期沒有更新即視為過期
+    fn is_fresh(&self) -> bool {
+        let poll = self.header.poll.clamp(4, 10);
+        self.received_at.elapsed() < Duration::from_secs(2u64 << poll)
+    }
+}
+
+/// 常駐的廣播接收執行緒，保留最近一次收到的封包
+struct Listener {
+    latest: Mutex<Option<BroadcastSample>>,
+    updated: Condvar,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Calibration {
+    /// 廣播封包從伺服器到本機的單程延遲
+    one_way_delay_ns: i128,
+    calibrated_at: Instant,
+}
+
+lazy_static::lazy_static! {
+    static ref LISTENERS: Mutex<HashMap<SocketAddr, Arc<Listener>>> = Mutex::new(HashMap::new());
+    static ref CALIBRATIONS: Mutex<HashMap<IpAddr, Calibration>> = Mutex::new(HashMap::new());
+}
+
+pub fn is_broadcast_url(server: &str) -> bool {
+    let server = server.trim();
+    server
+        .get(..12)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("broadcast://"))
+}
+
+/// `broadcast://[address][:port]`，address 可為 multicast 群組或子網路廣播位址，預設 224.0.1.1
+fn parse_listen_addr(server: &str) -> Result<SocketAddr, NtpError> {
+    let rest = server.trim()[12..].trim_end_matches('/');
+    if rest.is_empty() {
+        return Ok(SocketAddr::new(NTP_MULTICAST_V4.into(), NTP_PORT));
+    }
+    let spec = ServerSpec::parse(rest, NTP_PORT)?;
+    spec.host
+        .parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, spec.port))
+        .map_err(|_| {
+            NtpError::new(
+                "INVALID_SERVER",
+                format!("廣播位址必須是 IP 位址: {}", server),
+            )
+        })
+}
+
+/// 只接受已同步伺服器送出的 mode 5 封包
+fn is_valid_broadcast(header: &NtpPacket) -> bool {
+    header.mode == 5
+        && header.leap != 3
+        && (1..16).contains(&header.stratum)
+        && !header.transmit.is_zero()
+}
+
+fn bind_listener(listen_addr: SocketAddr) -> Result<UdpSocket, NtpError> {
+    let local: IpAddr = match listen_addr.ip() {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind((local, listen_addr.port())).map_err(|e| {
+        NtpError::new(
+            "SOCKET_BIND",
+            format!(
+                "無法綁定廣播接收連接埠 {} (需要系統管理員權限或已被占用): {}",
+                listen_addr.port(),
+                e
+            ),
+        )
+    })?;
+
+    let joined = match listen_addr.ip() {
+        IpAddr::V4(group) if group.is_multicast() => {
+            socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)
+        }
+        IpAddr::V6(group) if group.is_multicast() => socket.join_multicast_v6(&group, 0),
+        _ => Ok(()),
+    };
+    joined.map_err(|e| {
+        NtpError::new(
+            "SOCKET_BIND",
+            format!("無法加入 multicast 群組 {}: {}", listen_addr.ip(), e),
+        )
+    })?;
+
+    Ok(socket)
+}
+
+/// 取得 (必要時啟動) 指定位址的接收執行緒
+fn listener(listen_addr: SocketAddr) -> Result<Arc<Listener>, NtpError> {
+    let mut listeners = LISTENERS.lock().unwrap();
+    if let Some(listener) = listeners.get(&listen_addr) {
+        return Ok(listener.clone());
+    }
+
+    let socket = bind_listener(listen_addr)?;
+    let mode = timestamping::enable(&socket);
+    let listener = Arc::new(Listener {
+        latest: Mutex::new(None),
+        updated: Condvar::new(),
+    });
+    listeners.insert(listen_addr, listener.clone());
+    println!("[BCAST] 開始接收 {} 的廣播封包", listen_addr);
+
+    let shared = listener.clone();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; NTP_MAX_RESPONSE_SIZE];
+        loop {
+            let Ok((size, peer, t4, t4_source)) = timestamping::recv_from(&socket, &mut buf, mode)
+            else {
+                std::thread::sleep(RECV_RETRY_DELAY);
+                continue;
+            };
+            let Ok(header) = NtpPacket::decode(&buf[..size]) else {
+                continue;
+            };
+            if !is_valid_broadcast(&header) {
+                continue;
+            }
+            *shared.latest.lock().unwrap() = Some(BroadcastSample {
+                packet: buf[..size].to_vec(),
+                header,
+                peer,
+                t4,
+                t4_source,
+                received_at: Instant::now(),
+            });
+            shared.updated.notify_all();
+        }
+    });
+
+    Ok(listener)
+}
+
+/// 等待未過期的廣播封包，已有新鮮的封包時直接使用
+fn wait_sample(listener: &Listener, timeout: Duration) -> Result<BroadcastSample, NtpError> {
+    let latest = listener.latest.lock().unwrap();
+    let (latest, _) = listener
+        .updated
+        .wait_timeout_while(latest, timeout, |latest| {
+            !latest.as_ref().is_some_and(BroadcastSample::is_fresh)
+        })
+        .unwrap();
+    latest
+        .clone()
+        .filter(BroadcastSample::is_fresh)
+        .ok_or_else(|| {
+            NtpError::new(
+                "TIMEOUT",
+                format!("{} 秒內未收到廣播封包", timeout.as_secs()),
+            )
+        })
+}
+
+/// 廣播封包只有 T3/T4，offset = T3 - T4 + 單程延遲；
+/// 向送出廣播的位址做一次 client/server 交換，以其 offset 反推單程延遲，並限制在 [0, delay] 之間
+fn calibrate(
+    server: &str,
+    sample: &BroadcastSample,
+    options: &QueryOptions,
+) -> Result<i128, NtpError> {
+    let broadcaster = sample.peer.ip();
+    if let Some(calibration) = CALIBRATIONS.lock().unwrap().get(&broadcaster) {
+        if calibration.calibrated_at.elapsed() < CALIBRATION_INTERVAL {
+            return Ok(calibration.one_way_delay_ns);
+        }
+    }
+
+    let result = ntp::query_ntp_addr(server, sample.peer, options)?;
+    let offset_ns = (result.offset * 1_000_000.0) as i128;
+    let delay_ns = (result.delay * 1_000_000.0).max(0.0) as i128;
+    let raw_offset_ns = sample.header.transmit.diff_nanos(sample.t4);
+    let one_way_delay_ns = (offset_ns - raw_offset_ns).clamp(0, delay_ns);
+    println!(
+        "[BCAST] 校準 {}: 單程延遲 {:.3}ms (來回 {:.3}ms)",
+        broadcaster,
+        one_way_delay_ns as f64 / 1_000_000.0,
+        result.delay
+    );
+
+    CALIBRATIONS.lock().unwrap().insert(
+        broadcaster,
+        Calibration {
+            one_way_delay_ns,
+            calibrated_at: Instant::now(),
+        },
+    );
+    Ok(one_way_delay_ns)
+}
+
+/// 以最近的廣播封包計算 offset，第一次收到某個廣播伺服器的封包時先校準單程延遲
+pub fn query_broadcast(server: &str, options: &QueryOptions) -> Result<NtpResult, NtpError> {
+    let listen_addr = parse_listen_addr(server)?;
+    let listener = listener(listen_addr)?;
+    let sample = wait_sample(&listener, options.timeout())?;
+    let one_way_delay_ns = calibrate(server, &sample, options)?;
+
+    // 虛擬的 t1 讓 offset_and_delay 得到 offset = T3 - T4 + d、delay = 2d
+    let t3 = sample.header.transmit;
+    let t1 = NtpTimestamp::from_unix_nanos(
+        sample.t4.to_unix_nanos(DEFAULT_PIVOT_UNIX_SECS) - 2 * one_way_delay_ns,
+    );
+    let exchange = Exchange {
+        origin: t1,
+        t1,
+        t4: sample.t4,
+        t1_source: TimestampSource::User,
+        t4_source: sample.t4_source,
+        t2: Some(t3),
+        sample_mode: SampleMode::Broadcast,
+        size: sample.packet.len(),
+        peer_addr: sample.peer,
+    };
+    ntp::parse_response(server, &exchange, &sample.packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// CALIBRATIONS 以 IP 為鍵，所有廣播者都在 127.0.0.1
+    static CALIBRATION_LOCK: Mutex<()> = Mutex::new(());
+
+    const AHEAD_MS: i128 = 3_600_000;
+
+    fn now_plus(ms: i128) -> NtpTimestamp {
+        NtpTimestamp::from_unix_nanos(
+            NtpTimestamp::now().to_unix_nanos(DEFAULT_PIVOT_UNIX_SECS) + ms * 1_000_000,
+        )
+    }
+
+    fn broadcast_packet(transmit: NtpTimestamp) -> NtpPacket {
+        NtpPacket {
+            version: 4,
+            mode: 5,
+            stratum: 2,
+            poll: 6,
+            ref_time: transmit,
+            transmit,
+            ..Default::default()
+        }
+    }
+
+    /// 本機廣播者：持續送出比 AHEAD_MS 多 skew_ms 的 mode 5 封包，
+    /// 並以同一個 socket 回答 mode 3 校準請求 (快 AHEAD_MS)
+    fn serve_broadcast(skew_ms: i128) -> String {
+        let listen_port = UdpSocket::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(20)))
+            .unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; NTP_MAX_RESPONSE_SIZE];
+            for _ in 0..150 {
+                let packet = broadcast_packet(now_plus(AHEAD_MS + skew_ms));
+                socket
+                    .send_to(&packet.encode(), ("127.0.0.1", listen_port))
+                    .unwrap();
+                let Ok((size, peer)) = socket.recv_from(&mut buf) else {
+                    continue;
+                };
+                let request = NtpPacket::decode(&buf[..size]).unwrap();
+                assert_eq!(request.mode, 3);
+                let now = now_plus(AHEAD_MS);
+                let response = NtpPacket {
+                    version: 4,
+                    mode: 4,
+                    stratum: 2,
+                    ref_time: now,
+                    origin: request.transmit,
+                    receive: now,
+                    transmit: now,
+                    ..Default::default()
+                };
+                socket.send_to(&response.encode(), peer).unwrap();
+            }
+        });
+        format!("broadcast://127.0.0.1:{}", listen_port)
+    }
+
+    fn calibrated_delay_ns() -> i128 {
+        CALIBRATIONS.lock().unwrap()[&IpAddr::from(Ipv4Addr::LOCALHOST)].one_way_delay_ns
+    }
+
+    #[test]
+    fn negative_one_way_delay_is_clamped_to_zero() {
+        let _guard = CALIBRATION_LOCK.lock().unwrap();
+        CALIBRATIONS.lock().unwrap().clear();
+
+        // 廣播封包比校準交換快 5 秒，反推的單程延遲為負
+        let server = serve_broadcast(5_000);
+        let result = query_broadcast(&server, &QueryOptions::default()).unwrap();
+
+        assert_eq!(calibrated_delay_ns(), 0);
+        assert!(
+            (result.offset - 3_605_000.0).abs() < 50.0,
+            "{}",
+            result.offset
+        );
+        assert_eq!(result.delay, 0.0);
+    }
+
+    #[test]
+    fn one_way_delay_is_clamped_to_round_trip() {
+        let _guard = CALIBRATION_LOCK.lock().unwrap();
+        CALIBRATIONS.lock().unwrap().clear();
+
+        // 廣播封包比校準交換慢 5 秒，反推的單程延遲超過來回延遲
+        let server = serve_broadcast(-5_000);
+        let result = query_broadcast(&server, &QueryOptions::default()).unwrap();
+
+        let one_way_delay_ns = calibrated_delay_ns();
+        assert!(one_way_delay_ns > 0 && one_way_delay_ns < 50_000_000);
+        assert!(
+            (result.offset - 3_595_000.0).abs() < 50.0,
+            "{}",
+            result.offset
+        );
+        let delay_ns = (result.delay * 1_000_000.0) as i128;
+        assert!(
+            (delay_ns - 2 * one_way_delay_ns).abs() < 1_000,
+            "{}",
+            result.delay
+        );
+    }
+
+    #[test]
+    fn stale_sample_times_out() {
+        let packet = broadcast_packet(NtpTimestamp::now());
+        let sample = BroadcastSample {
+            packet: packet.encode().to_vec(),
+            header: packet,
+            peer: SocketAddr::from((Ipv4Addr::LOCALHOST, NTP_PORT)),
+            t4: NtpTimestamp::now(),
+            t4_source: TimestampSource::User,
+            received_at: Instant::now(),
+        };
+        let listener = Listener {
+            latest: Mutex::new(Some(sample.clone())),
+            updated: Condvar::new(),
+        };
+        let timeout = Duration::from_millis(100);
+        assert!(wait_sample(&listener, timeout).is_ok());
+
+        // poll 6 時超過 128 秒未更新即過期
+        *listener.latest.lock().unwrap() = Some(BroadcastSample {
+            received_at: Instant::now() - Duration::from_secs(129),
+            ..sample
+        });
+        let error = wait_sample(&listener, timeout).err().unwrap();
+        assert_eq!(error.code, "TIMEOUT");
+    }
+}
@@ -1,12 +1,17 @@
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use rusqlite::{params, Connection, Result as SqliteResult};
+use rusqlite::{params, Connection, OptionalExtension, Result as SqliteResult};
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::sync::Mutex;
 
+use crate::core::ntp::{NtpResult, TimeProtocol};
+
+/// 封存批次的序列化格式版本，2 起 NtpRecord 包含 protocol，3 起包含 root_distance
+const ARCHIVE_VERSION: i64 = 3;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NtpRecord {
     pub id: Option<i64>,
@@ -14,6 +19,30 @@ pub struct NtpRecord {
     pub delay: f64,
     pub server: String,
     pub timestamp: i64,
+    /// 時間來源協定 (`TimeProtocol`)，舊資料為 ntp
+    #[serde(default = "default_protocol")]
+    pub protocol: String,
+    /// 同步距離 (ms)，即 offset 的最大誤差範圍，舊資料為 None
+    #[serde(default)]
+    pub root_distance: Option<f64>,
+}
+
+fn default_protocol() -> String {
+    TimeProtocol::Ntp.as_str().to_string()
+}
+
+impl NtpRecord {
+    pub fn from_result(result: &NtpResult, timestamp: i64) -> Self {
+        NtpRecord {
+            id: None,
+            offset: result.offset,
+            delay: result.delay,
+            server: result.server.clone(),
+            timestamp,
+            protocol: result.protocol.as_str().to_string(),
+            root_distance: Some(result.root_distance),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +50,37 @@ pub struct CompressedBatch {
     pub records: Vec<NtpRecord>,
 }
 
+/// 版本 1 的封存格式，沒有 protocol 欄位
+#[derive(Deserialize)]
+struct CompressedBatchV1 {
+    records: Vec<NtpRecordV1>,
+}
+
+#[derive(Deserialize)]
+struct NtpRecordV1 {
+    id: Option<i64>,
+    offset: f64,
+    delay: f64,
+    server: String,
+    timestamp: i64,
+}
+
+/// 版本 2 的封存格式，沒有 root_distance 欄位
+#[derive(Deserialize)]
+struct CompressedBatchV2 {
+    records: Vec<NtpRecordV2>,
+}
+
+#[derive(Deserialize)]
+struct NtpRecordV2 {
+    id: Option<i64>,
+    offset: f64,
+    delay: f64,
+    server: String,
+    timestamp: i64,
+    protocol: String,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct QueryFilter {
     pub start_time: Option<i64>,
@@ -65,7 +125,9 @@ pub fn init_db() -> SqliteResult<()> {
             offset REAL NOT NULL,
             delay REAL NOT NULL,
             server TEXT NOT NULL,
-            timestamp INTEGER NOT NULL
+            timestamp INTEGER NOT NULL,
+            protocol TEXT NOT NULL DEFAULT 'ntp',
+            root_distance REAL
         )",
         [],
     )?;
@@ -77,11 +139,38 @@ pub fn init_db() -> SqliteResult<()> {
             end_time INTEGER NOT NULL,
             record_count INTEGER NOT NULL,
             data BLOB NOT NULL,
-            created_at INTEGER NOT NULL
+            created_at INTEGER NOT NULL,
+            version INTEGER NOT NULL DEFAULT 1
         )",
         [],
     )?;
 
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS server_options (
+            server TEXT PRIMARY KEY,
+            options TEXT NOT NULL,
+            updated_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // 舊版資料庫缺少的欄位
+    if conn.prepare("SELECT protocol FROM ntp_records LIMIT 0").is_err() {
+        conn.execute(
+            "ALTER TABLE ntp_records ADD COLUMN protocol TEXT NOT NULL DEFAULT 'ntp'",
+            [],
+        )?;
+    }
+    if conn.prepare("SELECT root_distance FROM ntp_records LIMIT 0").is_err() {
+        conn.execute("ALTER TABLE ntp_records ADD COLUMN root_distance REAL", [])?;
+    }
+    if conn.prepare("SELECT version FROM compressed_batches LIMIT 0").is_err() {
+        conn.execute(
+            "ALTER TABLE compressed_batches ADD COLUMN version INTEGER NOT NULL DEFAULT 1",
+            [],
+        )?;
+    }
+
     conn.execute(
         "CREATE INDEX IF NOT EXISTS idx_timestamp ON ntp_records(timestamp)",
         [],
@@ -123,8 +212,15 @@ pub fn insert_record(record: &NtpRecord) -> SqliteResult<i64> {
     let conn = guard.as_ref().unwrap();
 
     conn.execute(
-        "INSERT INTO ntp_records (offset, delay, server, timestamp) VALUES (?1, ?2, ?3, ?4)",
-        params![record.offset, record.delay, record.server, record.timestamp],
+        "INSERT INTO ntp_records (offset, delay, server, timestamp, protocol, root_distance) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            record.offset,
+            record.delay,
+            record.server,
+            record.timestamp,
+            record.protocol,
+            record.root_distance
+        ],
     )?;
 
     Ok(conn.last_insert_rowid())
@@ -135,7 +231,7 @@ pub fn insert_records(records: &[NtpRecord]) -> SqliteResult<usize> {
     let conn = guard.as_ref().unwrap();
 
     let mut stmt = conn.prepare(
-        "INSERT INTO ntp_records (offset, delay, server, timestamp) VALUES (?1, ?2, ?3, ?4)",
+        "INSERT INTO ntp_records (offset, delay, server, timestamp, protocol, root_distance) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
     )?;
 
     let mut count = 0;
@@ -144,7 +240,9 @@ pub fn insert_records(records: &[NtpRecord]) -> SqliteResult<usize> {
             record.offset,
             record.delay,
             record.server,
-            record.timestamp
+            record.timestamp,
+            record.protocol,
+            record.root_distance
         ])?;
         count += 1;
     }
@@ -156,7 +254,7 @@ pub fn query_records(filter: &QueryFilter) -> SqliteResult<Vec<NtpRecord>> {
     let guard = get_connection()?;
     let conn = guard.as_ref().unwrap();
 
-    let mut sql = String::from("SELECT id, offset, delay, server, timestamp FROM ntp_records WHERE 1=1");
+    let mut sql = String::from("SELECT id, offset, delay, server, timestamp, protocol, root_distance FROM ntp_records WHERE 1=1");
     let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
 
     if let Some(start) = filter.start_time {
@@ -218,6 +316,8 @@ pub fn query_records(filter: &QueryFilter) -> SqliteResult<Vec<NtpRecord>> {
             delay: row.get(2)?,
             server: row.get(3)?,
             timestamp: row.get(4)?,
+            protocol: row.get(5)?,
+            root_distance: row.get(6)?,
         })
     })?;
 
@@ -260,7 +360,7 @@ pub fn query_outliers(threshold: f64, limit: Option<usize>) -> SqliteResult<Vec<
 
     let limit_sql = limit.map(|l| format!(" LIMIT {}", l)).unwrap_or_default();
     let sql = format!(
-        "SELECT id, offset, delay, server, timestamp FROM ntp_records
+        "SELECT id, offset, delay, server, timestamp, protocol, root_distance FROM ntp_records
          WHERE ABS(offset) > ?1 ORDER BY timestamp DESC{}",
         limit_sql
     );
@@ -273,6 +373,8 @@ pub fn query_outliers(threshold: f64, limit: Option<usize>) -> SqliteResult<Vec<
             delay: row.get(2)?,
             server: row.get(3)?,
             timestamp: row.get(4)?,
+            protocol: row.get(5)?,
+            root_distance: row.get(6)?,
         })
     })?;
 
@@ -321,7 +423,7 @@ pub fn archive_old_records(before_timestamp: i64) -> SqliteResult<usize> {
     let conn = guard.as_ref().unwrap();
 
     let mut stmt = conn.prepare(
-        "SELECT id, offset, delay, server, timestamp FROM ntp_records WHERE timestamp < ?1 ORDER BY timestamp",
+        "SELECT id, offset, delay, server, timestamp, protocol, root_distance FROM ntp_records WHERE timestamp < ?1 ORDER BY timestamp",
     )?;
     let rows = stmt.query_map(params![before_timestamp], |row| {
         Ok(NtpRecord {
@@ -330,6 +432,8 @@ pub fn archive_old_records(before_timestamp: i64) -> SqliteResult<usize> {
             delay: row.get(2)?,
             server: row.get(3)?,
             timestamp: row.get(4)?,
+            protocol: row.get(5)?,
+            root_distance: row.get(6)?,
         })
     })?;
 
@@ -346,14 +450,15 @@ pub fn archive_old_records(before_timestamp: i64) -> SqliteResult<usize> {
     let compressed = compress_batch(&batch)?;
 
     conn.execute(
-        "INSERT INTO compressed_batches (start_time, end_time, record_count, data, created_at)
-         VALUES (?1, ?2, ?3, ?4, ?5)",
+        "INSERT INTO compressed_batches (start_time, end_time, record_count, data, created_at, version)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
         params![
             start_time,
             end_time,
             count as i64,
             compressed,
-            chrono::Utc::now().timestamp_millis()
+            chrono::Utc::now().timestamp_millis(),
+            ARCHIVE_VERSION
         ],
     )?;
 
@@ -383,19 +488,57 @@ fn compress_batch(batch: &CompressedBatch) -> SqliteResult<Vec<u8>> {
     })
 }
 
-fn decompress_batch(data: &[u8]) -> SqliteResult<CompressedBatch> {
+fn decompress_batch(data: &[u8], version: i64) -> SqliteResult<CompressedBatch> {
     let mut decoder = GzDecoder::new(data);
     let mut decompressed = Vec::new();
     decoder.read_to_end(&mut decompressed).map_err(|e| {
         rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Blob, Box::new(e))
     })?;
 
-    bincode::deserialize(&decompressed).map_err(|e| {
+    let decode_error = |e: bincode::Error| {
         rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Blob, Box::new(std::io::Error::new(
             std::io::ErrorKind::Other,
             e.to_string(),
         )))
-    })
+    };
+
+    if version < 2 {
+        let batch: CompressedBatchV1 = bincode::deserialize(&decompressed).map_err(decode_error)?;
+        let records = batch
+            .records
+            .into_iter()
+            .map(|r| NtpRecord {
+                id: r.id,
+                offset: r.offset,
+                delay: r.delay,
+                server: r.server,
+                timestamp: r.timestamp,
+                protocol: default_protocol(),
+                root_distance: None,
+            })
+            .collect();
+        return Ok(CompressedBatch { records });
+    }
+
+    if version < 3 {
+        let batch: CompressedBatchV2 = bincode::deserialize(&decompressed).map_err(decode_error)?;
+        let records = batch
+            .records
+            .into_iter()
+            .map(|r| NtpRecord {
+                id: r.id,
+                offset: r.offset,
+                delay: r.delay,
+                server: r.server,
+                timestamp: r.timestamp,
+                protocol: r.protocol,
+                root_distance: None,
+            })
+            .collect();
+        return Ok(CompressedBatch { records });
+    }
+
+    bincode::deserialize(&decompressed).map_err(decode_error)
 }
 
 pub fn query_archived_records(start: i64, end: i64) -> SqliteResult<Vec<NtpRecord>> {
@@ -403,19 +546,20 @@ pub fn query_archived_records(start: i64, end: i64) -> SqliteResult<Vec<NtpRecor
     let conn = guard.as_ref().unwrap();
 
     let mut stmt = conn.prepare(
-        "SELECT data FROM compressed_batches
+        "SELECT data, version FROM compressed_batches
          WHERE start_time <= ?2 AND end_time >= ?1",
     )?;
 
     let rows = stmt.query_map(params![start, end], |row| {
         let data: Vec<u8> = row.get(0)?;
-        Ok(data)
+        let version: i64 = row.get(1)?;
+        Ok((data, version))
     })?;
 
     let mut all_records = Vec::new();
     for row in rows {
-        if let Ok(data) = row {
-            if let Ok(batch) = decompress_batch(&data) {
+        if let Ok((data, version)) = row {
+            if let Ok(batch) = decompress_batch(&data, version) {
                 for record in batch.records {
                     if record.timestamp >= start && record.timestamp <= end {
                         all_records.push(record);
@@ -467,6 +611,42 @@ pub fn optimize_db() -> SqliteResult<()> {
     Ok(())
 }
 
+pub fn load_server_options(server: &str) -> SqliteResult<Option<String>> {
+    let guard = get_connection()?;
+    let conn = guard.as_ref().unwrap();
+
+    conn.query_row(
+        "SELECT options FROM server_options WHERE server = ?1",
+        params![server],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub fn save_server_options(server: &str, options: &str) -> SqliteResult<()> {
+    let guard = get_connection()?;
+    let conn = guard.as_ref().unwrap();
+
+    conn.execute(
+        "INSERT INTO server_options (server, options, updated_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(server) DO UPDATE SET options = excluded.options, updated_at = excluded.updated_at",
+        params![server, options, chrono::Utc::now().timestamp_millis()],
+    )?;
+
+    Ok(())
+}
+
+pub fn delete_server_options(server: &str) -> SqliteResult<bool> {
+    let guard = get_connection()?;
+    let conn = guard.as_ref().unwrap();
+
+    let deleted = conn.execute(
+        "DELETE FROM server_options WHERE server = ?1",
+        params![server],
+    )?;
+    Ok(deleted > 0)
+}
+
 pub fn aggregate_by_hour(start: i64, end: i64) -> SqliteResult<Vec<(i64, f64, f64, usize)>> {
     let guard = get_connection()?;
     let conn = guard.as_ref().unwrap();
@@ -537,13 +717,22 @@ pub async fn db_init() -> Result<(), String> {
 }
 
 #[tauri::command]
-pub async fn db_insert_record(offset: f64, delay: f64, server: String, timestamp: i64) -> Result<i64, String> {
+pub async fn db_insert_record(
+    offset: f64,
+    delay: f64,
+    server: String,
+    timestamp: i64,
+    protocol: Option<String>,
+    root_distance: Option<f64>,
+) -> Result<i64, String> {
     let record = NtpRecord {
         id: None,
         offset,
         delay,
         server,
         timestamp,
+        protocol: protocol.unwrap_or_else(default_protocol),
+        root_distance,
     };
     insert_record(&record).map_err(|e| e.to_string())
 }
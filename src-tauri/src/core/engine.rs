@@ -0,0 +1,799 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use tokio::task::JoinSet;
+
+use crate::core::ntp::{
+    self, AddressQueryResult, Exchange, NtpError, NtpResult, QueryOptions, ServerSpec,
+    NTP_MAX_RESPONSE_SIZE, NTP_PORT,
+};
+use crate::core::packet::NtpPacket;
+use crate::core::timestamp::{NtpTimestamp, DEFAULT_PIVOT_UNIX_SECS};
+use crate::core::timestamping::{self, KernelTimestamping, TimestampSource};
+use crate::core::{broadcast, http, kod, legacy, ptp};
+
+struct Reply {
+    data: Vec<u8>,
+    peer_addr: SocketAddr,
+    t4: NtpTimestamp,
+    t4_source: TimestampSource,
+}
+
+struct Pending {
+    sender: Option<oneshot::Sender<Reply>>,
+    /// 送出時的 OPT_ID 序號，用來對應 error queue 中的發送時間戳
+    tx_id: Option<u32>,
+    tx_timestamp: Option<NtpTimestamp>,
+}
+
+/// 同一位址族的所有查詢共用一個 socket，回應依 Origin Timestamp 分派給等待中的請求
+struct SharedSocket {
+    socket: UdpSocket,
+    /// 與 socket 指向同一個檔案描述的 std socket，用於 recvmsg 讀取核心時間戳
+    raw: std::net::UdpSocket,
+    mode: KernelTimestamping,
+    /// 下一個送出封包的 OPT_ID 序號，送出時需持有此鎖以保持順序
+    next_tx_id: tokio::sync::Mutex<u32>,
+    pending: Mutex<HashMap<NtpTimestamp, Pending>>,
+}
+
+lazy_static::lazy_static! {
+    static ref SHARED_SOCKETS: Mutex<HashMap<bool, Arc<SharedSocket>>> = Mutex::new(HashMap::new());
+}
+
+/// 開啟核心時間戳並轉為 tokio socket，另保留一份 std 副本供 recvmsg 讀取時間戳
+pub(crate) fn async_socket(
+    std_socket: std::net::UdpSocket,
+) -> std::io::Result<(UdpSocket, std::net::UdpSocket, KernelTimestamping)> {
+    let mode = timestamping::enable(&std_socket);
+    std_socket.set_nonblocking(true)?;
+    let raw = std_socket.try_clone()?;
+    Ok((UdpSocket::from_std(std_socket)?, raw, mode))
+}
+
+fn shared_socket(target: &SocketAddr) -> Result<Arc<SharedSocket>, NtpError> {
+    let mut sockets = SHARED_SOCKETS.lock().unwrap();
+    if let Some(shared) = sockets.get(&target.is_ipv6()) {
+        return Ok(shared.clone());
+    }
+
+    let (socket, raw, mode) =
+        ntp::bind_socket(target, &QueryOptions::default()).and_then(|std_socket| {
+            async_socket(std_socket).map_err(|e| {
+                NtpError::new("SOCKET_BIND", format!("無法建立共用 UDP socket: {}", e))
+            })
+        })?;
+
+    let shared = Arc::new(SharedSocket {
+        socket,
+        raw,
+        mode,
+        next_tx_id: tokio::sync::Mutex::new(0),
+        pending: Mutex::new(HashMap::new()),
+    });
+    tokio::spawn(shared.clone().receive_loop());
+
+    sockets.insert(target.is_ipv6(), shared.clone());
+    Ok(shared)
+}
+
+impl SharedSocket {
+    async fn receive_loop(self: Arc<Self>) {
+        let mut buf = [0u8; NTP_MAX_RESPONSE_SIZE];
+        loop {
+            let ready = match self
+                .socket
+                .ready(Interest::READABLE | Interest::ERROR)
+                .await
+            {
+                Ok(ready) => ready,
+                Err(e) => {
+                    println!("[ENGINE] 共用 socket 錯誤: {}，下次查詢時重新建立", e);
+                    self.evict();
+                    return;
+                }
+            };
+
+            // 讀到 WouldBlock 為止，清除 error 就緒狀態
+            if ready.is_error() {
+                while let Ok(tx) = self.socket.try_io(Interest::ERROR, || {
+                    timestamping::read_tx_timestamp(&self.raw)
+                }) {
+                    if let Some(tx) = tx {
+                        self.record_tx_timestamp(tx.id, tx.timestamp);
+                    }
+                }
+            }
+
+            if ready.is_readable() {
+                let received = self.socket.try_io(Interest::READABLE, || {
+                    timestamping::recv_from(&self.raw, &mut buf, self.mode)
+                });
+                match received {
+                    Ok((size, peer_addr, t4, t4_source)) => self.dispatch(Reply {
+                        data: buf[..size].to_vec(),
+                        peer_addr,
+                        t4,
+                        t4_source,
+                    }),
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(e) => println!("[ENGINE] 接收失敗: {}", e),
+                }
+            }
+        }
+    }
+
+    /// 從共用表移除此 socket，讓下一次查詢重新綁定
+    fn evict(self: &Arc<Self>) {
+        SHARED_SOCKETS
+            .lock()
+            .unwrap()
+            .retain(|_, shared| !Arc::ptr_eq(shared, self));
+    }
+
+    fn record_tx_timestamp(&self, id: u32, timestamp: NtpTimestamp) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(entry) = pending.values_mut().find(|p| p.tx_id == Some(id)) {
+            entry.tx_timestamp = Some(timestamp);
+        }
+    }
+
+    fn dispatch(&self, reply: Reply) {
+        let Ok(packet) = NtpPacket::decode(&reply.data) else {
+            return;
+        };
+        let origin = packet.origin;
+        let sender = self
+            .pending
+            .lock()
+            .unwrap()
+            .get_mut(&origin)
+            .and_then(|p| p.sender.take());
+        // 找不到對應請求的封包 (逾時後才到達或偽造) 直接丟棄
+        if let Some(sender) = sender {
+            let _ = sender.send(reply);
+        }
+    }
+
+    /// 以目前時間登記一個等待中的請求，時間戳重複時順延 1ns 以保持唯一
+    fn register(self: &Arc<Self>) -> (PendingGuard, oneshot::Receiver<Reply>) {
+        let (sender, receiver) = oneshot::channel();
+        let mut pending = self.pending.lock().unwrap();
+        let mut origin = NtpTimestamp::now();
+        while pending.contains_key(&origin) {
+            origin =
+                NtpTimestamp::from_unix_nanos(origin.to_unix_nanos(DEFAULT_PIVOT_UNIX_SECS) + 1);
+        }
+        pending.insert(
+            origin,
+            Pending {
+                sender: Some(sender),
+                tx_id: None,
+                tx_timestamp: None,
+            },
+        );
+
+        let guard = PendingGuard {
+            shared: self.clone(),
+            origin,
+        };
+        (guard, receiver)
+    }
+
+    /// 送出請求並回傳送出前一刻的 user-space 時間，作為沒有核心時間戳時的 t1
+    async fn send(
+        &self,
+        packet: &[u8],
+        target: SocketAddr,
+        origin: NtpTimestamp,
+    ) -> Result<NtpTimestamp, NtpError> {
+        let mut next_tx_id = self.next_tx_id.lock().await;
+        if let Some(entry) = self.pending.lock().unwrap().get_mut(&origin) {
+            entry.tx_id = Some(*next_tx_id);
+        }
+
+        let t1 = NtpTimestamp::now();
+        self.socket
+            .send_to(packet, target)
+            .await
+            .map_err(|e| NtpError::new("SEND_ERROR", format!("無法發送請求: {}", e)))?;
+
+        *next_tx_id = next_tx_id.wrapping_add(1);
+        Ok(t1)
+    }
+}
+
+/// 請求結束 (完成、逾時或 future 被取消) 時移除登記
+struct PendingGuard {
+    shared: Arc<SharedSocket>,
+    origin: NtpTimestamp,
+}
+
+impl PendingGuard {
+    fn tx_timestamp(&self) -> Option<NtpTimestamp> {
+        self.shared
+            .pending
+            .lock()
+            .unwrap()
+            .get(&self.origin)
+            .and_then(|p| p.tx_timestamp)
+    }
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.shared.pending.lock().unwrap().remove(&self.origin);
+    }
+}
+
+/// 透過共用 socket 查詢單一位址，逾時或被取消時不會佔用 runtime worker
+pub async fn query_addr(
+    server: &str,
+    server_addr: SocketAddr,
+    options: &QueryOptions,
+    timeout: Duration,
+) -> Result<NtpResult, NtpError> {
+    let key = ntp::load_key(options)?;
+    let shared = shared_socket(&server_addr)?;
+
+    let (guard, receiver) = shared.register();
+    let origin = guard.origin;
+    let mut packet = ntp::build_request(origin, options.version()).to_vec();
+    ntp::finish_request(&mut packet, options, key.as_ref());
+
+    let user_t1 = shared.send(&packet, server_addr, origin).await?;
+
+    let reply = match tokio::time::timeout(timeout, receiver).await {
+        Ok(Ok(reply)) => reply,
+        _ => {
+            return Err(NtpError::new(
+                "TIMEOUT",
+                format!("等待 {} 回應逾時 ({}ms)", server_addr, timeout.as_millis()),
+            ))
+        }
+    };
+
+    let (t1, t1_source) = match guard.tx_timestamp() {
+        Some(ts) => (ts, TimestampSource::Kernel),
+        None => (user_t1, TimestampSource::User),
+    };
+    let exchange = Exchange {
+        origin,
+        t1,
+        t4: reply.t4,
+        t1_source,
+        t4_source: reply.t4_source,
+        t2: None,
+        sample_mode: ntp::SampleMode::Basic,
+        size: reply.data.len(),
+        peer_addr: reply.peer_addr,
+    };
+
+    ntp::process_response(
+        server,
+        server_addr,
+        &exchange,
+        &reply.data,
+        key.as_ref(),
+        options,
+    )
+}
+
+/// 非同步版本的 `ntp::query_ntp`
+pub async fn query(
+    server: &str,
+    options: &QueryOptions,
+    timeout: Duration,
+) -> Result<NtpResult, NtpError> {
+    // NTS-KE 與 HTTP、RFC 868/867、PTP、廣播來源使用各自的連線，其餘需要獨立 socket 的選項見 `needs_blocking`
+    if options.nts || is_dedicated(server) || needs_blocking(options) {
+        let timeout = total_timeout(timeout, options);
+        let (server, options) = (server.to_string(), options.clone());
+        return run_blocking(timeout, move || ntp::query_ntp(&server, &options)).await;
+    }
+
+    options.validate()?;
+    kod::check_allowed(server)?;
+
+    let result = match ServerSpec::parse(server, NTP_PORT) {
+        Ok(spec) => match spec.lookup_all(options.address_family).await {
+            Ok(addrs) => query_addr_retry(server, addrs[0], options, timeout).await,
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    }
+    .and_then(|result| ntp::finish_result(result, options));
+
+    kod::record_result(server, &result);
+
+    result
+}
+
+/// 不經由 UDP 查詢 NTP 的來源
+fn is_dedicated(server: &str) -> bool {
+    http::is_http_url(server)
+        || legacy::is_legacy_url(server)
+        || ptp::is_ptp_url(server)
+        || broadcast::is_broadcast_url(server)
+}
+
+/// 寬鬆模式與交錯模式需要接收 Origin 不是本次請求的回應，
+/// 指定來源位址、介面、DSCP 或隱私模式時需要設定 socket，都無法使用共用 socket
+fn needs_blocking(options: &QueryOptions) -> bool {
+    options.lenient || options.interleaved || options.needs_own_socket()
+}
+
+/// 每次嘗試各自有 options.timeout()，整體上限涵蓋所有重試
+fn total_timeout(timeout: Duration, options: &QueryOptions) -> Duration {
+    timeout * (options.retries + 1)
+}
+
+/// 在 blocking 執行緒中查詢，不佔用 runtime worker
+async fn run_blocking(
+    timeout: Duration,
+    query: impl FnOnce() -> Result<NtpResult, NtpError> + Send + 'static,
+) -> Result<NtpResult, NtpError> {
+    match tokio::time::timeout(timeout, tokio::task::spawn_blocking(query)).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(NtpError::new("TASK_ERROR", e.to_string())),
+        Err(_) => Err(NtpError::new(
+            "TIMEOUT",
+            format!("查詢逾時 ({}ms)", timeout.as_millis()),
+        )),
+    }
+}
+
+async fn query_addr_retry(
+    server: &str,
+    server_addr: SocketAddr,
+    options: &QueryOptions,
+    timeout: Duration,
+) -> Result<NtpResult, NtpError> {
+    let mut attempt = 0;
+    loop {
+        match query_addr(server, server_addr, options, timeout).await {
+            Err(e) if attempt < options.retries && ntp::is_retryable(&e) => {
+                attempt += 1;
+                println!(
+                    "[ENGINE] {} 第 {} 次重試: {}",
+                    server_addr, attempt, e.error
+                );
+            }
+            result => return result,
+        }
+    }
+}
+
+/// 一次同步工作階段：主機名稱只解析一次，所有樣本固定查詢同一個位址，
+/// 該位址無回應時才依序改用下一個位址
+pub struct Session {
+    server: String,
+    addrs: Vec<SocketAddr>,
+    current: usize,
+    /// DNS 解析耗時 (毫秒)，NTS 與非 NTP 來源不在此解析，為 None
+    pub dns_ms: Option<f64>,
+}
+
+impl Session {
+    pub async fn resolve(server: &str, options: &QueryOptions) -> Result<Self, NtpError> {
+        let mut session = Session {
+            server: server.to_string(),
+            addrs: Vec::new(),
+            current: 0,
+            dns_ms: None,
+        };
+        // NTS 的位址由 NTS-KE 指定，其他來源各自建立連線
+        if options.nts || is_dedicated(server) {
+            return Ok(session);
+        }
+
+        let spec = ServerSpec::parse(server, NTP_PORT)?;
+        let started = Instant::now();
+        let mut addrs = spec.lookup_all(options.address_family).await?;
+        let dns_ms = started.elapsed().as_secs_f64() * 1000.0;
+        addrs.dedup();
+        println!(
+            "[ENGINE] 解析 {} 耗時 {:.3}ms，固定使用 {} (共 {} 個位址)",
+            server,
+            dns_ms,
+            addrs[0],
+            addrs.len()
+        );
+
+        session.addrs = addrs;
+        session.dns_ms = Some(dns_ms);
+        Ok(session)
+    }
+
+    pub async fn query(
+        &mut self,
+        options: &QueryOptions,
+        timeout: Duration,
+    ) -> Result<NtpResult, NtpError> {
+        if self.addrs.is_empty() {
+            return query(&self.server, options, timeout).await;
+        }
+
+        options.validate()?;
+        kod::check_allowed(&self.server)?;
+
+        let result = loop {
+            let addr = self.addrs[self.current];
+            match query_pinned(&self.server, addr, options, timeout).await {
+                Err(e) if ntp::is_retryable(&e) && self.current + 1 < self.addrs.len() => {
+                    self.current += 1;
+                    println!(
+                        "[ENGINE] {} 無回應 ({})，改用 {}",
+                        addr, e.error, self.addrs[self.current]
+                    );
+                }
+                result => break result.and_then(|result| ntp::finish_result(result, options)),
+            }
+        };
+
+        kod::record_result(&self.server, &result);
+
+        result
+    }
+}
+
+async fn query_pinned(
+    server: &str,
+    server_addr: SocketAddr,
+    options: &QueryOptions,
+    timeout: Duration,
+) -> Result<NtpResult, NtpError> {
+    if needs_blocking(options) {
+        let timeout = total_timeout(timeout, options);
+        let (server, options) = (server.to_string(), options.clone());
+        return run_blocking(timeout, move || {
+            ntp::with_retries(&server_addr.to_string(), &options, || {
+                ntp::query_ntp_addr(&server, server_addr, &options)
+            })
+        })
+        .await;
+    }
+    query_addr_retry(server, server_addr, options, timeout).await
+}
+
+/// 解析一次主機名稱，並行查詢所有 A/AAAA 記錄，用於找出 pool/anycast 中異常的後端
+pub async fn query_all_addresses(
+    server: &str,
+    options: &QueryOptions,
+    timeout: Duration,
+) -> Result<Vec<AddressQueryResult>, NtpError> {
+    if options.nts {
+        return Err(NtpError::new(
+            "UNSUPPORTED",
+            "NTS 由 NTS-KE 指定伺服器，無法逐一查詢位址",
+        ));
+    }
+    options.validate()?;
+    kod::check_allowed(server)?;
+
+    let mut addrs = ServerSpec::parse(server, NTP_PORT)?
+        .lookup_all(options.address_family)
+        .await?;
+    addrs.dedup();
+
+    let mut tasks = JoinSet::new();
+    for (index, addr) in addrs.into_iter().enumerate() {
+        let (server, options) = (server.to_string(), options.clone());
+        tasks.spawn(async move {
+            let result = query_pinned(&server, addr, &options, timeout)
+                .await
+                .and_then(|result| ntp::finish_result(result, &options));
+            (index, addr, result)
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(Ok((index, addr, result))) = tasks.join_next().await {
+        let (result, error) = match result {
+            Ok(r) => (Some(r), None),
+            Err(e) => (None, Some(e)),
+        };
+        results.push((
+            index,
+            AddressQueryResult {
+                address: addr.ip().to_string(),
+                address_family: ntp::address_family_name(&addr).to_string(),
+                result,
+                error,
+            },
+        ));
+    }
+
+    results.sort_by_key(|(index, _)| *index);
+
+    // 任一位址回應 Kiss-o'-Death 即套用於整個主機名稱
+    let kiss = results.iter().find_map(|(_, r)| {
+        r.error
+            .as_ref()
+            .filter(|e| e.code.starts_with("KOD_"))
+            .map(|e| Err(e.clone()))
+    });
+    if let Some(outcome) =
+        kiss.or_else(|| results.iter().find_map(|(_, r)| r.result.clone().map(Ok)))
+    {
+        kod::record_result(server, &outcome);
+    }
+
+    Ok(results.into_iter().map(|(_, r)| r).collect())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerQueryResult {
+    pub server: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<NtpResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<NtpError>,
+}
+
+/// 並行查詢多台伺服器，整體耗時約為最慢的回應或 timeout
+///
+/// 未指定 options 時各伺服器使用自己儲存的預設選項，未指定 timeout 時使用選項中的逾時
+pub async fn query_servers(
+    servers: &[String],
+    options: Option<&QueryOptions>,
+    timeout: Option<Duration>,
+) -> Vec<ServerQueryResult> {
+    let mut tasks = JoinSet::new();
+    for (index, server) in servers.iter().enumerate() {
+        let server = server.clone();
+        let options = options
+            .cloned()
+            .unwrap_or_else(|| ntp::server_defaults(&server));
+        let timeout = timeout.unwrap_or_else(|| options.timeout());
+        tasks.spawn(async move {
+            let result = query(&server, &options, timeout).await;
+            (index, server, result)
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(Ok((index, server, result))) = tasks.join_next().await {
+        let (result, error) = match result {
+            Ok(r) => (Some(r), None),
+            Err(e) => (None, Some(e)),
+        };
+        results.push((
+            index,
+            ServerQueryResult {
+                server,
+                result,
+                error,
+            },
+        ));
+    }
+
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+#[tauri::command]
+pub async fn query_ntp_servers(
+    servers: Vec<String>,
+    options: Option<QueryOptions>,
+    timeout_ms: Option<u64>,
+) -> Result<String, String> {
+    println!("[ENGINE] 並行查詢 {} 台伺服器", servers.len());
+
+    let timeout = timeout_ms.map(Duration::from_millis);
+    let results = query_servers(&servers, options.as_ref(), timeout).await;
+
+    for entry in &results {
+        match (&entry.result, &entry.error) {
+            (Some(r), _) => println!(
+                "[ENGINE] ✓ {} | offset={}ms delay={}ms stratum={}",
+                entry.server, r.offset, r.delay, r.stratum
+            ),
+            (_, Some(e)) => println!("[ENGINE] ✗ {} | {} ({})", entry.server, e.error, e.code),
+            _ => {}
+        }
+    }
+
+    serde_json::to_string(&results).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::packet::ReferenceId;
+    use crate::core::timestamp::NtpShort;
+    use std::net::{Ipv4Addr, UdpSocket as StdUdpSocket};
+
+    /// 共用 socket 綁定在建立它的 runtime 上，測試間必須共用同一個 runtime
+    fn runtime() -> &'static tokio::runtime::Runtime {
+        static RUNTIME: std::sync::OnceLock<tokio::runtime::Runtime> = std::sync::OnceLock::new();
+        RUNTIME.get_or_init(|| {
+            tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+        })
+    }
+
+    /// 本機 NTP 替身，以 build 依請求產生回應，回答 replies 次後結束
+    fn serve(replies: usize, build: fn(&NtpPacket) -> NtpPacket) -> String {
+        let socket = StdUdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = socket.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; NTP_MAX_RESPONSE_SIZE];
+            for _ in 0..replies {
+                let Ok((size, peer)) = socket.recv_from(&mut buf) else {
+                    return;
+                };
+                let request = NtpPacket::decode(&buf[..size]).unwrap();
+                socket.send_to(&build(&request).encode(), peer).unwrap();
+            }
+        });
+        addr.to_string()
+    }
+
+    fn reply(request: &NtpPacket) -> NtpPacket {
+        let now = NtpTimestamp::now();
+        NtpPacket {
+            version: request.version,
+            mode: 4,
+            stratum: 2,
+            precision: -20,
+            ref_id: ReferenceId(*b"\x7f\x00\x00\x01"),
+            ref_time: now,
+            origin: request.transmit,
+            receive: now,
+            transmit: now,
+            ..Default::default()
+        }
+    }
+
+    fn unsynchronized(request: &NtpPacket) -> NtpPacket {
+        NtpPacket {
+            leap: 3,
+            stratum: 16,
+            ..reply(request)
+        }
+    }
+
+    fn far_away(request: &NtpPacket) -> NtpPacket {
+        NtpPacket {
+            root_dispersion: NtpShort::from(Duration::from_secs(2)),
+            ..reply(request)
+        }
+    }
+
+    fn deny(request: &NtpPacket) -> NtpPacket {
+        NtpPacket {
+            stratum: 0,
+            ref_id: ReferenceId(*b"DENY"),
+            ..reply(request)
+        }
+    }
+
+    fn query_one(server: &str, options: &QueryOptions) -> Result<AddressQueryResult, NtpError> {
+        let results =
+            runtime().block_on(query_all_addresses(server, options, Duration::from_secs(2)))?;
+        assert_eq!(results.len(), 1);
+        Ok(results.into_iter().next().unwrap())
+    }
+
+    #[test]
+    fn all_addresses_honours_lenient() {
+        let server = serve(2, unsynchronized);
+
+        let strict = query_one(&server, &QueryOptions::default()).unwrap();
+        assert!(strict.result.is_none());
+
+        let options = QueryOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let lenient = query_one(&server, &options).unwrap();
+        assert!(lenient.result.is_some(), "{:?}", lenient.error);
+    }
+
+    #[test]
+    fn all_addresses_applies_root_distance() {
+        let server = serve(1, far_away);
+        let options = QueryOptions {
+            max_distance_ms: Some(1500.0),
+            ..Default::default()
+        };
+        let result = query_one(&server, &options).unwrap();
+        assert_eq!(result.error.unwrap().code, "DISTANCE_EXCEEDED");
+    }
+
+    #[test]
+    fn all_addresses_records_kiss_of_death() {
+        let server = serve(1, deny);
+        let result = query_one(&server, &QueryOptions::default()).unwrap();
+        assert_eq!(result.error.unwrap().code, "KOD_DENY");
+
+        let again = query_one(&server, &QueryOptions::default());
+        assert_eq!(again.unwrap_err().code, "SERVER_DISABLED");
+        kod::reenable(&server);
+    }
+
+    #[test]
+    fn evicted_socket_is_rebound() {
+        runtime().block_on(async {
+            let target: SocketAddr = "127.0.0.1:123".parse().unwrap();
+            let first = shared_socket(&target).unwrap();
+            first.evict();
+            let second = shared_socket(&target).unwrap();
+            assert!(!Arc::ptr_eq(&first, &second));
+        });
+    }
+
+    #[test]
+    fn silent_servers_do_not_serialize() {
+        // 綁定後不讀取的 socket，請求會石沉大海
+        let silent: Vec<StdUdpSocket> = (0..3)
+            .map(|_| StdUdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap())
+            .collect();
+        let mut servers: Vec<String> = silent
+            .iter()
+            .map(|s| s.local_addr().unwrap().to_string())
+            .collect();
+        servers.push(serve(1, reply));
+        servers.push(serve(1, reply));
+
+        let options = QueryOptions {
+            retries: 0,
+            ..Default::default()
+        };
+        let timeout = Duration::from_millis(300);
+        let started = Instant::now();
+        let results = runtime().block_on(query_servers(&servers, Some(&options), Some(timeout)));
+        let elapsed = started.elapsed();
+
+        assert_eq!(results.len(), 5);
+        for entry in &results[..3] {
+            assert_eq!(entry.error.as_ref().unwrap().code, "TIMEOUT");
+        }
+        for entry in &results[3..] {
+            assert!(entry.result.is_some(), "{:?}", entry.error);
+        }
+        assert!(
+            elapsed < timeout + Duration::from_millis(200),
+            "{:?}",
+            elapsed
+        );
+    }
+
+    #[test]
+    fn cancelled_query_releases_pending_entry() {
+        // IPv6 共用 socket 只有此測試使用，等待中的請求數不受其他測試影響
+        let silent = StdUdpSocket::bind("[::1]:0").unwrap();
+        let target = silent.local_addr().unwrap();
+
+        runtime().block_on(async {
+            let shared = shared_socket(&target).unwrap();
+            let task = tokio::spawn(async move {
+                query_addr(
+                    "cancel.test",
+                    target,
+                    &QueryOptions::default(),
+                    Duration::from_secs(30),
+                )
+                .await
+            });
+
+            let deadline = Instant::now() + Duration::from_secs(2);
+            while shared.pending.lock().unwrap().is_empty() {
+                assert!(Instant::now() < deadline, "請求未登記");
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+
+            task.abort();
+            assert!(task.await.unwrap_err().is_cancelled());
+            assert!(shared.pending.lock().unwrap().is_empty());
+        });
+    }
+}
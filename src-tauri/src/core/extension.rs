@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+
+use crate::core::ntp::{NtpError, NTP_PACKET_SIZE};
+
+/// 擴充欄位的最小長度 (含 4 bytes 標頭)
+const MIN_FIELD_LEN: usize = 16;
+/// 後面沒有 MAC 時最後一個欄位的最小長度，讓接收端能與 MAC 區分
+const MIN_LAST_FIELD_LEN: usize = 28;
+/// 舊式 MAC 的最大長度 (key ID + SHA1)
+const MAX_MAC_LEN: usize = 24;
+/// 合法的 MAC 長度：crypto-NAK、MD5/AES-CMAC、SHA1
+const MAC_LENGTHS: [usize; 3] = [4, 20, 24];
+
+/// NTPv4 擴充欄位 (RFC 7822)，解析得到的 value 包含 padding
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtensionField {
+    pub field_type: u16,
+    pub value: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParsedExtensions {
+    /// (欄位在封包中的位置, 欄位)
+    pub fields: Vec<(usize, ExtensionField)>,
+    pub mac: Option<Vec<u8>>,
+}
+
+fn write_padded(packet: &mut Vec<u8>, field_type: u16, value: &[u8], min_len: usize) {
+    let field_len = (value.len() + 4).max(min_len).div_ceil(4) * 4;
+    packet.extend_from_slice(&field_type.to_be_bytes());
+    packet.extend_from_slice(&(field_len as u16).to_be_bytes());
+    packet.extend_from_slice(value);
+    packet.resize(packet.len() + field_len - 4 - value.len(), 0);
+}
+
+/// 寫入單一擴充欄位，長度補齊至 4 的倍數且至少 16 bytes
+pub fn write_field(packet: &mut Vec<u8>, field_type: u16, value: &[u8]) {
+    write_padded(packet, field_type, value, MIN_FIELD_LEN);
+}
+
+/// 依序寫入多個擴充欄位，mac_follows 為 false 時最後一個欄位補齊至 28 bytes
+pub fn write_fields(packet: &mut Vec<u8>, fields: &[ExtensionField], mac_follows: bool) {
+    for (i, field) in fields.iter().enumerate() {
+        let min_len = if i + 1 == fields.len() && !mac_follows {
+            MIN_LAST_FIELD_LEN
+        } else {
+            MIN_FIELD_LEN
+        };
+        write_padded(packet, field.field_type, &field.value, min_len);
+    }
+}
+
+/// 讀取 pos 位置的欄位，回傳欄位與其總長度
+fn read_field(data: &[u8], pos: usize) -> Result<(ExtensionField, usize), String> {
+    if pos + 4 > data.len() {
+        return Err("標頭不完整".to_string());
+    }
+    let field_type = u16::from_be_bytes([data[pos], data[pos + 1]]);
+    let field_len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+    if field_len < MIN_FIELD_LEN || !field_len.is_multiple_of(4) || pos + field_len > data.len() {
+        return Err(format!("長度錯誤 ({})", field_len));
+    }
+
+    let field = ExtensionField {
+        field_type,
+        value: data[pos + 4..pos + field_len].to_vec(),
+    };
+    Ok((field, field_len))
+}
+
+/// 解析連續的 type-length-value 欄位，回傳的位置相對於 data 開頭
+pub fn parse_fields(data: &[u8]) -> Result<Vec<(usize, ExtensionField)>, NtpError> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let (field, field_len) = read_field(data, pos).map_err(|e| invalid_field(pos, &e))?;
+        fields.push((pos, field));
+        pos += field_len;
+    }
+
+    Ok(fields)
+}
+
+/// 解析 48 bytes 標頭之後的擴充欄位與 MAC
+///
+/// 擴充欄位至少 16 bytes，沒有 MAC 時最後一個欄位至少 28 bytes (RFC 7822 §7.5)，
+/// 因此剩餘長度不超過 24 bytes 時必定是 MAC
+pub fn parse(packet: &[u8]) -> Result<ParsedExtensions, NtpError> {
+    let mut parsed = ParsedExtensions::default();
+    let mut pos = NTP_PACKET_SIZE;
+
+    while pos < packet.len() {
+        let remaining = packet.len() - pos;
+        if remaining <= MAX_MAC_LEN {
+            if !MAC_LENGTHS.contains(&remaining) {
+                return Err(invalid_field(pos, &format!("MAC 長度錯誤 ({})", remaining)));
+            }
+            parsed.mac = Some(packet[pos..].to_vec());
+            break;
+        }
+
+        let (field, field_len) = read_field(packet, pos).map_err(|e| invalid_field(pos, &e))?;
+        if pos + field_len == packet.len() && field_len < MIN_LAST_FIELD_LEN {
+            return Err(invalid_field(
+                pos,
+                &format!("最後一個欄位長度不足 ({})", field_len),
+            ));
+        }
+        parsed.fields.push((pos, field));
+        pos += field_len;
+    }
+
+    Ok(parsed)
+}
+
+fn invalid_field(offset: usize, reason: &str) -> NtpError {
+    NtpError::new(
+        "INVALID_EXTENSION",
+        format!("位置 {} 的擴充欄位格式錯誤: {}", offset, reason),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Vec<u8> {
+        vec![0u8; NTP_PACKET_SIZE]
+    }
+
+    fn raw_field(packet: &mut Vec<u8>, field_len: u16) {
+        packet.extend_from_slice(&0x0104u16.to_be_bytes());
+        packet.extend_from_slice(&field_len.to_be_bytes());
+        packet.resize(packet.len() + field_len.saturating_sub(4) as usize, 0);
+    }
+
+    #[test]
+    fn fields_and_mac_round_trip() {
+        let fields = vec![
+            ExtensionField {
+                field_type: 0x0104,
+                value: vec![1; 32],
+            },
+            ExtensionField {
+                field_type: 0x0204,
+                value: vec![2; 4],
+            },
+        ];
+        for (mac_len, mac_follows) in [(0, false), (20, true), (24, true)] {
+            let mut packet = header();
+            write_fields(&mut packet, &fields, mac_follows);
+            packet.extend(std::iter::repeat_n(0xAA, mac_len));
+
+            let parsed = parse(&packet).unwrap();
+            assert_eq!(parsed.fields.len(), 2);
+            assert_eq!(
+                parsed.fields[1].1.value.len(),
+                if mac_follows { 12 } else { 24 }
+            );
+            assert_eq!(parsed.mac.map_or(0, |mac| mac.len()), mac_len);
+        }
+    }
+
+    #[test]
+    fn crypto_nak_without_fields() {
+        let mut packet = header();
+        packet.extend_from_slice(&[0; 4]);
+        let parsed = parse(&packet).unwrap();
+        assert!(parsed.fields.is_empty());
+        assert_eq!(parsed.mac.unwrap().len(), 4);
+    }
+
+    #[test]
+    fn short_fields_are_rejected() {
+        for field_len in [4, 8, 12] {
+            let mut packet = header();
+            raw_field(&mut packet, field_len);
+            raw_field(&mut packet, 28);
+            let error = parse(&packet).unwrap_err();
+            assert_eq!(error.code, "INVALID_EXTENSION", "{}", field_len);
+        }
+    }
+
+    #[test]
+    fn short_last_field_without_mac_is_rejected() {
+        let mut packet = header();
+        raw_field(&mut packet, 16);
+        raw_field(&mut packet, 16);
+        assert!(parse(&packet).is_err());
+    }
+
+    #[test]
+    fn invalid_mac_length_is_rejected() {
+        for mac_len in [8, 12, 16] {
+            let mut packet = header();
+            raw_field(&mut packet, 28);
+            packet.resize(packet.len() + mac_len, 0);
+            assert!(parse(&packet).is_err(), "{}", mac_len);
+        }
+    }
+}
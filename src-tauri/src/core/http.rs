@@ -0,0 +1,223 @@
+use chrono::DateTime;
+use rustls::pki_types::ServerName;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+use crate::core::ntp::{self, NtpError, NtpResult, QueryOptions, ServerSpec, TimeProtocol};
+use crate::core::timestamp::NtpTimestamp;
+
+/// NTP 查詢全部失敗時預設改用的 HTTP 時間來源
+pub const DEFAULT_FALLBACK_URL: &str = "https://www.google.com";
+/// Date 標頭只有秒精度，伺服器實際時間落在 [Date, Date + 1s) 之間
+const DATE_QUANTIZATION_MS: f64 = 1000.0;
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+
+struct HttpUrl {
+    tls: bool,
+    spec: ServerSpec,
+    path: String,
+}
+
+pub fn is_http_url(server: &str) -> bool {
+    let server = server.trim().to_ascii_lowercase();
+    server.starts_with("http://") || server.starts_with("https://")
+}
+
+fn parse_url(url: &str) -> Result<HttpUrl, NtpError> {
+    let url = url.trim();
+    let invalid = || NtpError::new("INVALID_SERVER", format!("無效的 HTTP 網址: {}", url));
+    let (scheme, rest) = url.split_once("://").ok_or_else(invalid)?;
+    let tls = match scheme.to_ascii_lowercase().as_str() {
+        "http" => false,
+        "https" => true,
+        _ => return Err(invalid()),
+    };
+
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(i) => (&rest[..i], rest[i..].to_string()),
+        None => (rest, "/".to_string()),
+    };
+    let path = if path.starts_with('?') {
+        format!("/{}", path)
+    } else {
+        path
+    };
+    let spec = ServerSpec::parse(authority, if tls { 443 } else { 80 })?;
+
+    Ok(HttpUrl { tls, spec, path })
+}
+
+fn tls_config() -> Arc<rustls::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .expect("ring 支援預設 TLS 版本")
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+
+    Arc::new(config)
+}
+
+/// 送出 HEAD 請求並讀取回應標頭，回傳 (標頭, t1, t4)
+///
+/// TLS 交握在 t1 之前完成，t1~t4 只包含 HTTP 請求本身的來回時間
+fn timed_head<S: Read + Write>(
+    stream: &mut S,
+    request: &[u8],
+) -> Result<(String, NtpTimestamp, NtpTimestamp), NtpError> {
+    let recv_error =
+        |e: std::io::Error| NtpError::new("RECV_ERROR", format!("無法接收回應: {}", e));
+
+    let t1 = NtpTimestamp::now();
+    stream
+        .write_all(request)
+        .and_then(|_| stream.flush())
+        .map_err(|e| NtpError::new("SEND_ERROR", format!("無法發送請求: {}", e)))?;
+
+    let mut header = Vec::new();
+    let mut buf = [0u8; 4096];
+    let size = stream.read(&mut buf).map_err(recv_error)?;
+    let t4 = NtpTimestamp::now();
+    header.extend_from_slice(&buf[..size]);
+
+    while size > 0 && !header.windows(4).any(|w| w == b"\r\n\r\n") {
+        if header.len() > MAX_HEADER_SIZE {
+            return Err(NtpError::new("HTTP_INVALID_RESPONSE", "HTTP 回應標頭過長"));
+        }
+        match stream.read(&mut buf).map_err(recv_error)? {
+            0 => break,
+            n => header.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    Ok((String::from_utf8_lossy(&header).into_owned(), t1, t4))
+}
+
+fn parse_date(header: &str) -> Result<f64, NtpError> {
+    let mut lines = header.lines();
+    let status = lines.next().unwrap_or_default();
+    if !status.starts_with("HTTP/") {
+        return Err(NtpError::new(
+            "HTTP_INVALID_RESPONSE",
+            format!("無效的 HTTP 回應: {}", status),
+        ));
+    }
+
+    let date = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("date"))
+        .map(|(_, value)| value.trim())
+        .ok_or_else(|| NtpError::new("HTTP_NO_DATE", "HTTP 回應缺少 Date 標頭"))?;
+
+    DateTime::parse_from_rfc2822(date)
+        .map(|dt| dt.timestamp_millis() as f64)
+        .map_err(|_| NtpError::new("HTTP_NO_DATE", format!("無法解析 Date 標頭: {}", date)))
+}
+
+/// 以 HEAD 請求的 Date 標頭估計時間偏差，root_dispersion 為 ±0.5 秒的量化誤差
+pub fn query_http(url: &str, options: &QueryOptions) -> Result<NtpResult, NtpError> {
+    let parsed = parse_url(url)?;
+    let addr = parsed.spec.resolve(options.address_family)?;
+
+    let timeout = options.timeout();
+    let mut tcp = TcpStream::connect_timeout(&addr, timeout)
+        .map_err(|e| NtpError::new("HTTP_CONNECT", format!("無法連線 HTTP 伺服器: {}", e)))?;
+    tcp.set_read_timeout(Some(timeout)).ok();
+    tcp.set_write_timeout(Some(timeout)).ok();
+    tcp.set_nodelay(true).ok();
+
+    let host = if addr.is_ipv6() && parsed.spec.host.contains(':') {
+        format!("[{}]", parsed.spec.host)
+    } else {
+        parsed.spec.host.clone()
+    };
+    let request = format!(
+        "HEAD {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: ntp-client\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        parsed.path, host
+    );
+
+    let (header, t1, t4) = if parsed.tls {
+        let server_name = ServerName::try_from(parsed.spec.host.clone())
+            .map_err(|e| NtpError::new("HTTP_TLS", format!("無效的伺服器名稱: {}", e)))?;
+        let mut conn = rustls::ClientConnection::new(tls_config(), server_name)
+            .map_err(|e| NtpError::new("HTTP_TLS", format!("無法建立 TLS 連線: {}", e)))?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut tcp)
+                .map_err(|e| NtpError::new("HTTP_TLS", format!("TLS 交握失敗: {}", e)))?;
+        }
+        timed_head(&mut rustls::StreamOwned::new(conn, tcp), request.as_bytes())?
+    } else {
+        timed_head(&mut tcp, request.as_bytes())?
+    };
+
+    // 以 Date 所在秒的中點作為伺服器時間
+    let server_time = NtpTimestamp::from_unix_nanos(
+        ((parse_date(&header)? + DATE_QUANTIZATION_MS / 2.0) * 1e6) as i128,
+    );
+
+    Ok(ntp::single_time_result(
+        url,
+        addr,
+        (t1, t4),
+        server_time,
+        DATE_QUANTIZATION_MS / 2.0,
+        TimeProtocol::Http,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// 本機 HTTP 替身，回應一次 HEAD 請求
+    fn serve(date: Option<chrono::DateTime<chrono::Utc>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let size = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..size]);
+            }
+            assert!(request.starts_with(b"HEAD /time HTTP/1.1\r\n"));
+
+            let date = date.map_or(String::new(), |date| {
+                format!("Date: {}\r\n", date.format("%a, %d %b %Y %H:%M:%S GMT"))
+            });
+            let response = format!(
+                "HTTP/1.1 204 No Content\r\n{}Connection: close\r\n\r\n",
+                date
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        format!("http://{}/time", addr)
+    }
+
+    #[test]
+    fn date_header_against_local_server() {
+        let ahead = chrono::Duration::hours(1);
+        let url = serve(Some(chrono::Utc::now() + ahead));
+        let result = query_http(&url, &QueryOptions::default()).unwrap();
+
+        assert_eq!(result.protocol, TimeProtocol::Http);
+        assert_eq!(result.stratum, ntp::UNSYNCHRONIZED_STRATUM);
+        assert_eq!(result.root_dispersion, DATE_QUANTIZATION_MS / 2.0);
+        let error = result.offset - ahead.num_milliseconds() as f64;
+        assert!(error.abs() <= DATE_QUANTIZATION_MS, "{}", result.offset);
+    }
+
+    #[test]
+    fn missing_date_header_is_rejected() {
+        let url = serve(None);
+        let error = query_http(&url, &QueryOptions::default()).unwrap_err();
+        assert_eq!(error.code, "HTTP_NO_DATE");
+    }
+}
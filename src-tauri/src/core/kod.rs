@@ -0,0 +1,257 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::core::ntp::{NtpError, NtpResult};
+
+/// 背景同步的基本輪詢間隔
+pub const BASE_POLL_SECS: u64 = 60;
+/// RATE 退避的上限 (約 36 小時，對應 NTP maxpoll 17)
+const MAX_POLL_SECS: u64 = 131072;
+
+const KNOWN_KISS_CODES: [&str; 15] = [
+    "ACST", "AUTH", "AUTO", "BCST", "CRYP", "DENY", "DROP", "RSTR", "INIT", "MCST", "NKEY",
+    "NTSN", "RATE", "RMOT", "STEP",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackoffState {
+    pub server: String,
+    pub kiss_code: Option<String>,
+    pub poll_interval_secs: u64,
+    pub next_allowed_ms: i64,
+    pub disabled: bool,
+    pub updated_at: i64,
+}
+
+impl BackoffState {
+    fn new(server: &str) -> Self {
+        BackoffState {
+            server: server.to_string(),
+            kiss_code: None,
+            poll_interval_secs: BASE_POLL_SECS,
+            next_allowed_ms: 0,
+            disabled: false,
+            updated_at: 0,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref BACKOFF_STATES: Mutex<HashMap<String, BackoffState>> = Mutex::new(HashMap::new());
+}
+
+/// 將 stratum 0 回應的 kiss code 轉為錯誤碼，例如 RATE -> KOD_RATE
+pub fn kiss_error(kiss_code: &str) -> NtpError {
+    let code = if KNOWN_KISS_CODES.contains(&kiss_code) {
+        format!("KOD_{}", kiss_code)
+    } else {
+        "KOD_UNKNOWN".to_string()
+    };
+
+    let message = match kiss_code {
+        "RATE" => "伺服器要求降低查詢頻率 (Kiss-o'-Death RATE)".to_string(),
+        "DENY" => "伺服器拒絕存取 (Kiss-o'-Death DENY)".to_string(),
+        "RSTR" => "伺服器限制存取 (Kiss-o'-Death RSTR)".to_string(),
+        _ => format!("伺服器回應 Kiss-o'-Death: {}", kiss_code),
+    };
+
+    NtpError {
+        kiss_code: Some(kiss_code.to_string()),
+        ..NtpError::new(&code, message)
+    }
+}
+
+/// 依 kiss code 更新伺服器的退避狀態
+pub fn record_kiss(server: &str, kiss_code: &str) {
+    let now = chrono::Utc::now().timestamp_millis();
+    let mut states = BACKOFF_STATES.lock().unwrap();
+    let state = states
+        .entry(server.to_string())
+        .or_insert_with(|| BackoffState::new(server));
+
+    match kiss_code {
+        "RATE" => {
+            state.poll_interval_secs = (state.poll_interval_secs * 2).min(MAX_POLL_SECS);
+            state.next_allowed_ms = now + (state.poll_interval_secs * 1000) as i64;
+            println!(
+                "[KOD] {} 要求降低頻率，輪詢間隔調整為 {}s",
+                server, state.poll_interval_secs
+            );
+        }
+        "DENY" | "RSTR" => {
+            state.disabled = true;
+            println!("[KOD] {} 拒絕服務 ({})，停止輪詢直到重新啟用", server, kiss_code);
+        }
+        _ => {}
+    }
+
+    state.kiss_code = Some(kiss_code.to_string());
+    state.updated_at = now;
+}
+
+/// 查詢失敗且為 Kiss-o'-Death 時記錄實際的 kiss code
+fn record_error(server: &str, error: &NtpError) {
+    if let Some(kiss_code) = &error.kiss_code {
+        record_kiss(server, kiss_code);
+    }
+}
+
+/// 成功完成交換後，RATE 退避的輪詢間隔每次減半，回到基本值才清除狀態，
+/// 避免單次成功就讓仍在限流的伺服器恢復全速輪詢
+fn record_success(server: &str) {
+    let mut states = BACKOFF_STATES.lock().unwrap();
+    let Some(state) = states.get_mut(server).filter(|state| !state.disabled) else {
+        return;
+    };
+
+    state.poll_interval_secs = (state.poll_interval_secs / 2).max(BASE_POLL_SECS);
+    state.updated_at = chrono::Utc::now().timestamp_millis();
+    if state.poll_interval_secs == BASE_POLL_SECS {
+        states.remove(server);
+        println!(
+            "[KOD] {} 查詢成功，輪詢間隔恢復為 {}s",
+            server, BASE_POLL_SECS
+        );
+    } else {
+        println!(
+            "[KOD] {} 查詢成功，輪詢間隔降為 {}s",
+            server, state.poll_interval_secs
+        );
+    }
+}
+
+/// 依查詢結果更新伺服器的退避狀態
+pub fn record_result(server: &str, result: &Result<NtpResult, NtpError>) {
+    match result {
+        Ok(_) => record_success(server),
+        Err(e) => record_error(server, e),
+    }
+}
+
+/// 查詢前檢查伺服器是否已停用或仍在退避期間
+pub fn check_allowed(server: &str) -> Result<(), NtpError> {
+    let states = BACKOFF_STATES.lock().unwrap();
+    let Some(state) = states.get(server) else {
+        return Ok(());
+    };
+
+    if state.disabled {
+        return Err(NtpError::new(
+            "SERVER_DISABLED",
+            format!(
+                "{} 已因 Kiss-o'-Death {} 停用，請重新啟用後再查詢",
+                server,
+                state.kiss_code.as_deref().unwrap_or("")
+            ),
+        ));
+    }
+
+    let now = chrono::Utc::now().timestamp_millis();
+    if now < state.next_allowed_ms {
+        return Err(NtpError::new(
+            "KOD_BACKOFF",
+            format!(
+                "{} 退避中，{:.0}s 後才可再次查詢",
+                server,
+                (state.next_allowed_ms - now) as f64 / 1000.0
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn is_disabled(server: &str) -> bool {
+    BACKOFF_STATES
+        .lock()
+        .unwrap()
+        .get(server)
+        .map(|s| s.disabled)
+        .unwrap_or(false)
+}
+
+pub fn poll_interval(server: &str) -> Duration {
+    let secs = BACKOFF_STATES
+        .lock()
+        .unwrap()
+        .get(server)
+        .map(|s| s.poll_interval_secs)
+        .unwrap_or(BASE_POLL_SECS);
+    Duration::from_secs(secs)
+}
+
+pub fn reenable(server: &str) -> bool {
+    BACKOFF_STATES.lock().unwrap().remove(server).is_some()
+}
+
+#[tauri::command]
+pub async fn get_kod_states() -> Result<Vec<BackoffState>, String> {
+    let states = BACKOFF_STATES.lock().map_err(|e| e.to_string())?;
+    Ok(states.values().cloned().collect())
+}
+
+#[tauri::command]
+pub async fn reenable_ntp_server(server: String) -> Result<bool, String> {
+    println!("[KOD] 重新啟用 {}", server);
+    Ok(reenable(&server))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ntp::{self, TimeProtocol};
+    use crate::core::timestamp::NtpTimestamp;
+
+    fn success(server: &str) -> Result<NtpResult, NtpError> {
+        let now = NtpTimestamp::now();
+        Ok(ntp::single_time_result(
+            server,
+            "192.0.2.1:123".parse().unwrap(),
+            (now, now),
+            now,
+            0.0,
+            TimeProtocol::Ntp,
+        ))
+    }
+
+    #[test]
+    fn rate_backoff_decays_after_success() {
+        let server = "kod-rate.test";
+        record_kiss(server, "RATE");
+        record_kiss(server, "RATE");
+        assert_eq!(
+            poll_interval(server),
+            Duration::from_secs(BASE_POLL_SECS * 4)
+        );
+
+        record_result(server, &success(server));
+        assert_eq!(
+            poll_interval(server),
+            Duration::from_secs(BASE_POLL_SECS * 2)
+        );
+        record_result(server, &success(server));
+        assert_eq!(poll_interval(server), Duration::from_secs(BASE_POLL_SECS));
+        assert!(BACKOFF_STATES.lock().unwrap().get(server).is_none());
+    }
+
+    #[test]
+    fn unknown_kiss_code_is_kept() {
+        let server = "kod-unknown.test";
+        let error = kiss_error("XFOO");
+        assert_eq!(error.code, "KOD_UNKNOWN");
+        record_result(server, &Err(error));
+        let states = BACKOFF_STATES.lock().unwrap();
+        assert_eq!(states[server].kiss_code.as_deref(), Some("XFOO"));
+    }
+
+    #[test]
+    fn deny_is_not_cleared_by_success() {
+        let server = "kod-deny.test";
+        record_result(server, &Err(kiss_error("DENY")));
+        record_result(server, &success(server));
+        assert!(is_disabled(server));
+        assert_eq!(check_allowed(server).unwrap_err().code, "SERVER_DISABLED");
+    }
+}
@@ -0,0 +1,790 @@
+use chrono::{Datelike, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::core::ntp::{NtpError, NtpResult, ServerSpec, TimeProtocol, NTP_PORT};
+
+/// NTP 紀元 (1900) 與 Unix 紀元 (1970) 相差的秒數
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+/// 系統 tzdata 與 ntpd 常見的 leap-seconds.list 位置
+const SYSTEM_LEAP_FILES: [&str; 3] = [
+    "/usr/share/zoneinfo/leap-seconds.list",
+    "/etc/leap-seconds.list",
+    "/var/lib/ntp/leap-seconds.list",
+];
+/// 伺服器的 leap indicator 超過此時間沒有更新即不列入投票
+const VOTE_LIFETIME_MS: i64 = 24 * 3600 * 1000;
+/// 閏秒前此時間內開始警告
+const WARNING_WINDOW_MS: i64 = 24 * 3600 * 1000;
+/// 閏秒前後此時間內不調整系統時間
+const STEP_BLACKOUT_MS: i64 = 30 * 60 * 1000;
+/// Google 與 AWS 的 leap smear：以閏秒為中心、前後各 12 小時的線性調整
+const SMEAR_HALF_WINDOW_MS: i64 = 12 * 3600 * 1000;
+/// 已知以 24 小時線性 smear 處理閏秒的伺服器 (Google Public NTP、Amazon Time Sync Service)
+const KNOWN_SMEAR_HOSTS: [&str; 5] = [
+    "google.com",
+    "time.android.com",
+    "time.aws.com",
+    "169.254.169.123",
+    "fd00:ec2::123",
+];
+/// smear 量小於此值時無法與一般的 offset 差異區分
+const SMEAR_DETECT_MIN_MS: f64 = 100.0;
+/// 比較 offset 偏離時只採用此時間內的其他來源
+const OFFSET_LIFETIME_MS: i64 = 5 * 60 * 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeapDirection {
+    Insert,
+    Delete,
+}
+
+/// 預定閏秒的資料來源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeapSource {
+    File,
+    Servers,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeapEntry {
+    /// 生效時間 (Unix ms)，即閏秒結束後的 UTC 00:00
+    pub effective_ms: i64,
+    pub tai_utc: i32,
+}
+
+#[derive(Debug, Clone)]
+struct LeapTable {
+    path: String,
+    updated_ms: i64,
+    expires_ms: i64,
+    entries: Vec<LeapEntry>,
+}
+
+impl LeapTable {
+    fn is_expired(&self, now_ms: i64) -> bool {
+        now_ms >= self.expires_ms
+    }
+
+    fn tai_utc(&self, now_ms: i64) -> Option<i32> {
+        self.entries
+            .iter()
+            .take_while(|e| e.effective_ms <= now_ms)
+            .last()
+            .map(|e| e.tai_utc)
+    }
+
+    /// 檔案中尚未發生的下一個閏秒
+    fn next_leap(&self, now_ms: i64) -> Option<ScheduledLeap> {
+        let index = self.entries.iter().position(|e| e.effective_ms > now_ms)?;
+        let entry = &self.entries[index];
+        let previous = index
+            .checked_sub(1)
+            .map(|i| self.entries[i].tai_utc)
+            .unwrap_or(entry.tai_utc);
+        Some(ScheduledLeap {
+            at_ms: entry.effective_ms,
+            direction: match entry.tai_utc < previous {
+                true => LeapDirection::Delete,
+                false => LeapDirection::Insert,
+            },
+            tai_utc_after: Some(entry.tai_utc),
+            source: LeapSource::File,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeapFileInfo {
+    pub path: String,
+    pub updated_ms: i64,
+    pub expires_ms: i64,
+    pub expired: bool,
+    pub entries: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledLeap {
+    /// 閏秒結束的時間 (Unix ms)，即當月最後一天結束時的 UTC 00:00
+    pub at_ms: i64,
+    pub direction: LeapDirection,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tai_utc_after: Option<i32>,
+    pub source: LeapSource,
+}
+
+/// 最近一天內各伺服器的 leap indicator
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LeapVotes {
+    pub insert: usize,
+    pub delete: usize,
+    pub none: usize,
+}
+
+impl LeapVotes {
+    /// 過半數伺服器宣告的閏秒方向
+    fn majority(&self) -> Option<LeapDirection> {
+        let total = self.insert + self.delete + self.none;
+        if self.insert * 2 > total {
+            Some(LeapDirection::Insert)
+        } else if self.delete * 2 > total {
+            Some(LeapDirection::Delete)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeapStatus {
+    /// 目前的 TAI-UTC (秒)，沒有可用的 leap-seconds.list 時為 None
+    pub tai_utc: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<LeapFileInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_error: Option<NtpError>,
+    pub votes: LeapVotes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_leap: Option<ScheduledLeap>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+lazy_static::lazy_static! {
+    /// 已載入的 leap-seconds.list，None 表示尚未嘗試載入
+    static ref LEAP_TABLE: Mutex<Option<Result<LeapTable, NtpError>>> = Mutex::new(None);
+    /// 伺服器位址 -> (leap indicator, 記錄時間 ms)
+    static ref VOTES: Mutex<HashMap<String, (u8, i64)>> = Mutex::new(HashMap::new());
+    /// 未 smear 的伺服器位址 -> (offset ms, 記錄時間 ms)，用於偵測 offset 偏離
+    static ref OFFSETS: Mutex<HashMap<String, (f64, i64)>> = Mutex::new(HashMap::new());
+}
+
+fn ntp_secs_to_unix_ms(secs: i64) -> i64 {
+    (secs - NTP_UNIX_OFFSET) * 1000
+}
+
+fn digits(text: &str) -> impl Iterator<Item = u8> + '_ {
+    text.bytes().filter(u8::is_ascii_digit)
+}
+
+/// 解析 IERS/IETF leap-seconds.list，並以檔案中的 SHA-1 (`#h`) 驗證內容；
+/// 雜湊涵蓋 `#$`、`#@` 與資料列去除註解後的所有數字
+fn parse_leap_file(path: &str, content: &str) -> Result<LeapTable, NtpError> {
+    let invalid = |msg: String| NtpError::new("LEAP_FILE_INVALID", format!("{}: {}", path, msg));
+    let parse_secs = |value: &str| {
+        value
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| invalid(format!("無效的時間: {}", value.trim())))
+    };
+
+    let mut hasher = Sha1::new();
+    let mut updated = None;
+    let mut expires = None;
+    let mut expected_hash = None;
+    let mut entries = Vec::new();
+
+    for line in content.lines() {
+        if let Some(value) = line.strip_prefix("#$") {
+            updated = Some(parse_secs(value)?);
+            hasher.update(digits(value).collect::<Vec<_>>());
+        } else if let Some(value) = line.strip_prefix("#@") {
+            expires = Some(parse_secs(value)?);
+            hasher.update(digits(value).collect::<Vec<_>>());
+        } else if let Some(value) = line.strip_prefix("#h") {
+            let words: Result<Vec<u32>, _> = value
+                .split_whitespace()
+                .map(|w| u32::from_str_radix(w, 16))
+                .collect();
+            expected_hash = words.ok().filter(|w| w.len() == 5);
+        } else if !line.starts_with('#') && !line.trim().is_empty() {
+            let data = line.split('#').next().unwrap_or_default();
+            let mut fields = data.split_whitespace();
+            let (Some(secs), Some(tai_utc)) = (fields.next(), fields.next()) else {
+                return Err(invalid(format!("無法解析的資料列: {}", line.trim())));
+            };
+            entries.push(LeapEntry {
+                effective_ms: ntp_secs_to_unix_ms(parse_secs(secs)?),
+                tai_utc: tai_utc
+                    .parse()
+                    .map_err(|_| invalid(format!("無效的 TAI-UTC: {}", tai_utc)))?,
+            });
+            hasher.update(digits(data).collect::<Vec<_>>());
+        }
+    }
+
+    let (Some(updated), Some(expires)) = (updated, expires) else {
+        return Err(invalid("缺少 #$ 更新時間或 #@ 到期時間".to_string()));
+    };
+    let Some(expected_hash) = expected_hash else {
+        return Err(invalid("缺少 #h 雜湊".to_string()));
+    };
+    let digest = hasher.finalize();
+    let actual: Vec<u32> = digest
+        .chunks(4)
+        .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+        .collect();
+    if actual != expected_hash {
+        return Err(NtpError::new(
+            "LEAP_HASH_MISMATCH",
+            format!("{} 的雜湊驗證失敗，檔案可能已損毀或被修改", path),
+        ));
+    }
+    if entries.is_empty()
+        || entries
+            .windows(2)
+            .any(|w| w[0].effective_ms >= w[1].effective_ms)
+    {
+        return Err(invalid("閏秒資料為空或未依時間排序".to_string()));
+    }
+
+    Ok(LeapTable {
+        path: path.to_string(),
+        updated_ms: ntp_secs_to_unix_ms(updated),
+        expires_ms: ntp_secs_to_unix_ms(expires),
+        entries,
+    })
+}
+
+fn load_file(path: &str) -> Result<LeapTable, NtpError> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| NtpError::new("LEAP_FILE_ERROR", format!("無法讀取 {}: {}", path, e)))?;
+    let table = parse_leap_file(path, &content)?;
+    println!(
+        "[LEAP] 已載入 {} ({} 筆，TAI-UTC={}s)",
+        path,
+        table.entries.len(),
+        table.entries.last().map(|e| e.tai_utc).unwrap_or_default()
+    );
+    Ok(table)
+}
+
+/// 依序嘗試系統的 leap-seconds.list，全部失敗時回傳第一個存在的檔案的錯誤
+fn load_system_file() -> Result<LeapTable, NtpError> {
+    let mut first_error = None;
+    for path in SYSTEM_LEAP_FILES {
+        if !std::path::Path::new(path).exists() {
+            continue;
+        }
+        match load_file(path) {
+            Ok(table) => return Ok(table),
+            Err(e) => {
+                println!("[LEAP] {} ({})", e.error, e.code);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    Err(first_error
+        .unwrap_or_else(|| NtpError::new("LEAP_FILE_NOT_FOUND", "找不到系統的 leap-seconds.list")))
+}
+
+/// 取得已載入的表，第一次使用時載入系統檔案
+fn with_table<T>(f: impl FnOnce(&Result<LeapTable, NtpError>) -> T) -> T {
+    let mut table = LEAP_TABLE.lock().unwrap();
+    f(table.get_or_insert_with(load_system_file))
+}
+
+/// 記錄 NTP/PTP 回應的 leap indicator 並標記 leap smear 伺服器，未同步 (3) 的伺服器不參與投票
+pub fn annotate(result: &mut NtpResult) {
+    if !matches!(result.protocol, TimeProtocol::Ntp | TimeProtocol::Ptp) || result.leap == 3 {
+        return;
+    }
+    let now = Utc::now().timestamp_millis();
+    observe(result, now);
+
+    let expected = smear_offset_ms(now as f64);
+    let offsets = OFFSETS.lock().unwrap().clone();
+    result.smearing =
+        is_known_smear_host(&result.server) || diverges_like_smear(result, expected, now, &offsets);
+    if result.smearing {
+        OFFSETS.lock().unwrap().remove(&result.server_ip);
+    } else {
+        OFFSETS
+            .lock()
+            .unwrap()
+            .insert(result.server_ip.clone(), (result.offset, now));
+    }
+}
+
+fn observe(result: &NtpResult, now: i64) {
+    let mut votes = VOTES.lock().unwrap();
+    let previous = votes.insert(result.server_ip.clone(), (result.leap, now));
+    if result.leap != 0 && previous.map(|(leap, _)| leap) != Some(result.leap) {
+        println!(
+            "[LEAP] {} 宣告本月底{}閏秒",
+            result.server_ip,
+            if result.leap == 1 { "插入" } else { "刪除" }
+        );
+    }
+}
+
+fn is_known_smear_host(server: &str) -> bool {
+    let Ok(spec) = ServerSpec::parse(server, NTP_PORT) else {
+        return false;
+    };
+    let host = spec.host.to_ascii_lowercase();
+    KNOWN_SMEAR_HOSTS
+        .iter()
+        .any(|known| host == *known || host.ends_with(&format!(".{}", known)))
+}
+
+/// 閏秒前後，offset 與其他來源 (offsets) 中位數的差距接近預期的 smear 量 (expected) 即視為 smear 伺服器
+fn diverges_like_smear(
+    result: &NtpResult,
+    expected: f64,
+    now_ms: i64,
+    offsets: &HashMap<String, (f64, i64)>,
+) -> bool {
+    if expected.abs() < SMEAR_DETECT_MIN_MS {
+        return false;
+    }
+
+    let mut others: Vec<f64> = offsets
+        .iter()
+        .filter(|(ip, (_, at))| **ip != result.server_ip && now_ms - *at < OFFSET_LIFETIME_MS)
+        .map(|(_, (offset, _))| *offset)
+        .collect();
+    if others.len() < 2 {
+        return false;
+    }
+    others.sort_by(f64::total_cmp);
+    let divergence = result.offset - others[others.len() / 2];
+
+    let smearing = (divergence - expected).abs() < expected.abs() / 2.0;
+    if smearing {
+        println!(
+            "[LEAP] {} 的 offset 偏離其他來源 {:.3}ms，符合 leap smear ({:.3}ms)",
+            result.server_ip, divergence, expected
+        );
+    }
+    smearing
+}
+
+/// 檔案中每次 TAI-UTC 變動的時間與方向
+fn table_leaps(table: &LeapTable) -> Vec<(i64, LeapDirection)> {
+    table
+        .entries
+        .windows(2)
+        .map(|w| {
+            let direction = match w[1].tai_utc < w[0].tai_utc {
+                true => LeapDirection::Delete,
+                false => LeapDirection::Insert,
+            };
+            (w[1].effective_ms, direction)
+        })
+        .collect()
+}
+
+/// 距離指定時間 12 小時內的閏秒：檔案中的閏秒與多數伺服器宣告的閏秒
+fn leap_near(utc_ms: i64) -> Option<(i64, LeapDirection)> {
+    let mut leaps = with_table(|table| table.as_ref().map(table_leaps).unwrap_or_default());
+    let now = Utc::now().timestamp_millis();
+    if let Some(direction) = count_votes(now).majority() {
+        let (start, next) = month_boundaries(now);
+        leaps.extend([(start, direction), (next, direction)]);
+    }
+
+    leaps
+        .into_iter()
+        .find(|(at, _)| (utc_ms - at).abs() < SMEAR_HALF_WINDOW_MS)
+}
+
+/// 24 小時線性 smear 的時鐘與 UTC 的差 (smeared - UTC，ms)；
+/// 插入閏秒時 smear 時鐘在閏秒前逐漸落後至 -0.5s，閏秒後 UTC 退回一秒，差距變為 +0.5s 再回到 0
+pub fn smear_offset_ms(utc_ms: f64) -> f64 {
+    smear_lag_ms(leap_near(utc_ms as i64), utc_ms)
+}
+
+/// 以指定的閏秒 (時間, 方向) 計算 smear 量，沒有鄰近的閏秒時為 0
+fn smear_lag_ms(leap: Option<(i64, LeapDirection)>, utc_ms: f64) -> f64 {
+    let Some((at, direction)) = leap else {
+        return 0.0;
+    };
+    let elapsed = utc_ms - (at - SMEAR_HALF_WINDOW_MS) as f64;
+    let fraction = elapsed / (2 * SMEAR_HALF_WINDOW_MS) as f64;
+    let lag_ms = match utc_ms < at as f64 {
+        true => -fraction * 1000.0,
+        false => (1.0 - fraction) * 1000.0,
+    };
+    match direction {
+        LeapDirection::Insert => lag_ms,
+        LeapDirection::Delete => -lag_ms,
+    }
+}
+
+/// 指定 UTC 時間的 TAI-UTC (秒)，沒有可用的 leap-seconds.list 時為 None；
+/// 超過檔案到期時間後沿用最後的值
+pub fn tai_utc_at(utc_ms: i64) -> Option<i32> {
+    with_table(|table| {
+        let table = table.as_ref().ok()?;
+        table
+            .tai_utc(utc_ms)
+            .or(table.entries.first().map(|e| e.tai_utc))
+    })
+}
+
+fn count_votes(now_ms: i64) -> LeapVotes {
+    let mut votes = VOTES.lock().unwrap();
+    votes.retain(|_, (_, at)| now_ms - *at < VOTE_LIFETIME_MS);
+
+    let mut counts = LeapVotes::default();
+    for (leap, _) in votes.values() {
+        match leap {
+            1 => counts.insert += 1,
+            2 => counts.delete += 1,
+            _ => counts.none += 1,
+        }
+    }
+    counts
+}
+
+/// 當月與下個月第一天的 UTC 00:00 (Unix ms)，leap indicator 指的是當月最後一分鐘
+fn month_boundaries(now_ms: i64) -> (i64, i64) {
+    let now = Utc.timestamp_millis_opt(now_ms).unwrap();
+    let start = Utc
+        .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+        .unwrap();
+    let (year, month) = match now.month() {
+        12 => (now.year() + 1, 1),
+        month => (now.year(), month + 1),
+    };
+    let next = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap();
+    (start.timestamp_millis(), next.timestamp_millis())
+}
+
+/// 有效 (雜湊正確且未到期) 的檔案優先，否則採用過半數伺服器的 leap indicator
+fn schedule(
+    table: Option<&LeapTable>,
+    votes: &LeapVotes,
+    now_ms: i64,
+) -> (Option<ScheduledLeap>, Option<String>) {
+    let from_servers = votes.majority().map(|direction| ScheduledLeap {
+        at_ms: month_boundaries(now_ms).1,
+        direction,
+        tai_utc_after: None,
+        source: LeapSource::Servers,
+    });
+
+    let Some(table) = table.filter(|t| !t.is_expired(now_ms)) else {
+        return (from_servers, None);
+    };
+    let from_file = table.next_leap(now_ms);
+
+    // 伺服器宣告的閏秒與檔案不一致時以檔案為準，但提醒使用者
+    let this_month = from_file
+        .as_ref()
+        .filter(|leap| leap.at_ms == month_boundaries(now_ms).1)
+        .map(|leap| leap.direction);
+    let conflict = match (this_month, votes.majority()) {
+        (file, Some(servers)) if file != Some(servers) => Some(format!(
+            "多數伺服器宣告本月底{}閏秒，但 {} 沒有此閏秒",
+            if servers == LeapDirection::Insert {
+                "插入"
+            } else {
+                "刪除"
+            },
+            table.path
+        )),
+        _ => None,
+    };
+    (from_file, conflict)
+}
+
+fn status_at(now_ms: i64) -> LeapStatus {
+    let votes = count_votes(now_ms);
+    let (file, file_error, tai_utc, (next_leap, conflict)) = with_table(|table| match table {
+        Ok(table) => (
+            Some(LeapFileInfo {
+                path: table.path.clone(),
+                updated_ms: table.updated_ms,
+                expires_ms: table.expires_ms,
+                expired: table.is_expired(now_ms),
+                entries: table.entries.len(),
+            }),
+            None,
+            table.tai_utc(now_ms),
+            schedule(Some(table), &votes, now_ms),
+        ),
+        Err(e) => (None, Some(e.clone()), None, schedule(None, &votes, now_ms)),
+    });
+
+    let warning = match &next_leap {
+        Some(leap) if leap.at_ms - now_ms <= WARNING_WINDOW_MS => Some(format!(
+            "將於 {} UTC {}一秒閏秒",
+            Utc.timestamp_millis_opt(leap.at_ms)
+                .unwrap()
+                .format("%Y-%m-%d %H:%M:%S"),
+            if leap.direction == LeapDirection::Insert {
+                "插入"
+            } else {
+                "刪除"
+            }
+        )),
+        _ => None,
+    }
+    .or(conflict)
+    .or_else(|| {
+        file.as_ref()
+            .filter(|f| f.expired)
+            .map(|f| format!("{} 已過期，無法得知之後的閏秒", f.path))
+    });
+
+    LeapStatus {
+        tai_utc,
+        file,
+        file_error,
+        votes,
+        next_leap,
+        warning,
+    }
+}
+
+pub fn status() -> LeapStatus {
+    status_at(Utc::now().timestamp_millis())
+}
+
+/// 閏秒前後的一段時間內調整系統時間可能與伺服器的閏秒處理互相抵觸，回傳拒絕的原因
+pub fn step_blackout() -> Option<NtpError> {
+    let now = Utc::now().timestamp_millis();
+    let votes = count_votes(now);
+    with_table(|table| blackout_at(table.as_ref().ok(), &votes, now))
+}
+
+fn blackout_at(table: Option<&LeapTable>, votes: &LeapVotes, now_ms: i64) -> Option<NtpError> {
+    let mut candidates: Vec<i64> = table
+        .map(|table| table.entries.iter().map(|e| e.effective_ms).collect())
+        .unwrap_or_default();
+    // 伺服器在閏秒後才會清除 leap indicator，月初也要涵蓋
+    if votes.majority().is_some() {
+        let (start, next) = month_boundaries(now_ms);
+        candidates.extend([start, next]);
+    }
+
+    candidates
+        .into_iter()
+        .find(|at| (now_ms - at).abs() < STEP_BLACKOUT_MS)
+        .map(|at| {
+            NtpError::new(
+                "LEAP_SECOND_WINDOW",
+                format!(
+                    "{} UTC 的閏秒前後 {} 分鐘內不調整系統時間",
+                    Utc.timestamp_millis_opt(at)
+                        .unwrap()
+                        .format("%Y-%m-%d %H:%M:%S"),
+                    STEP_BLACKOUT_MS / 60_000
+                ),
+            )
+        })
+}
+
+#[tauri::command]
+pub async fn get_leap_status() -> Result<String, String> {
+    serde_json::to_string(&status()).map_err(|e| e.to_string())
+}
+
+/// 載入使用者指定的 leap-seconds.list，未指定路徑時重新載入系統檔案
+#[tauri::command]
+pub async fn load_leap_seconds_file(path: Option<String>) -> Result<String, String> {
+    let table = match path {
+        Some(path) => load_file(&path),
+        None => load_system_file(),
+    };
+    if let Err(ref error) = table {
+        println!("[LEAP] ✗ {} ({})", error.error, error.code);
+        return serde_json::to_string(error).map_err(|e| e.to_string());
+    }
+
+    *LEAP_TABLE.lock().unwrap() = Some(table);
+    serde_json::to_string(&status()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ntp::{self, QueryOptions};
+    use crate::core::timestamp::NtpTimestamp;
+
+    const FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/leap/leap-seconds.list"
+    );
+    /// 2017-01-01 00:00:00 UTC，最近一次插入的閏秒結束的時間
+    const LEAP_2017_MS: i64 = 1_483_228_800_000;
+
+    fn fixture() -> String {
+        std::fs::read_to_string(FIXTURE).unwrap()
+    }
+
+    fn utc_ms(year: i32, month: u32, day: u32) -> i64 {
+        Utc.with_ymd_and_hms(year, month, day, 0, 0, 0)
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    fn votes(insert: usize, delete: usize, none: usize) -> LeapVotes {
+        LeapVotes {
+            insert,
+            delete,
+            none,
+        }
+    }
+
+    fn ntp_result(server_ip: &str, leap: u8, root_dispersion: f64) -> NtpResult {
+        let now = NtpTimestamp::now();
+        let addr = format!("{}:123", server_ip).parse().unwrap();
+        let mut result = ntp::single_time_result(
+            server_ip,
+            addr,
+            (now, now),
+            now,
+            root_dispersion,
+            TimeProtocol::Ntp,
+        );
+        result.leap = leap;
+        result
+    }
+
+    #[test]
+    fn distant_samples_do_not_vote() {
+        let options = QueryOptions::default();
+
+        let distant = ntp_result("192.0.2.201", 1, 5000.0);
+        let error = ntp::finish_result(distant, &options).unwrap_err();
+        assert_eq!(error.code, "DISTANCE_EXCEEDED");
+        assert!(!VOTES.lock().unwrap().contains_key("192.0.2.201"));
+
+        let near = ntp_result("192.0.2.202", 1, 5.0);
+        ntp::finish_result(near, &options).unwrap();
+        assert!(VOTES.lock().unwrap().remove("192.0.2.202").is_some());
+        OFFSETS.lock().unwrap().remove("192.0.2.202");
+    }
+
+    #[test]
+    fn valid_file() {
+        let table = parse_leap_file(FIXTURE, &fixture()).unwrap();
+        assert_eq!(table.entries.first().map(|e| e.tai_utc), Some(10));
+        assert_eq!(table.entries.last().unwrap().effective_ms, LEAP_2017_MS);
+        assert_eq!(table.tai_utc(LEAP_2017_MS - 1), Some(36));
+        assert_eq!(table.tai_utc(LEAP_2017_MS), Some(37));
+        assert_eq!(table.expires_ms, utc_ms(2026, 6, 28));
+        assert!(table.next_leap(utc_ms(2026, 1, 1)).is_none());
+    }
+
+    #[test]
+    fn bad_hash_is_rejected() {
+        let tampered = fixture().replace("3692217600      37", "3692217600      38");
+        assert!(tampered != fixture());
+        let error = parse_leap_file(FIXTURE, &tampered).unwrap_err();
+        assert_eq!(error.code, "LEAP_HASH_MISMATCH");
+
+        let unsigned: String = fixture()
+            .lines()
+            .filter(|line| !line.starts_with("#h"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let error = parse_leap_file(FIXTURE, &unsigned).unwrap_err();
+        assert_eq!(error.code, "LEAP_FILE_INVALID");
+    }
+
+    #[test]
+    fn expired_file_defers_to_servers() {
+        let table = parse_leap_file(FIXTURE, &fixture()).unwrap();
+        let now = utc_ms(2026, 12, 15);
+        assert!(table.is_expired(now));
+        assert!(!table.is_expired(utc_ms(2026, 6, 27)));
+
+        let (leap, _) = schedule(Some(&table), &votes(0, 0, 3), now);
+        assert!(leap.is_none());
+
+        let (leap, _) = schedule(Some(&table), &votes(2, 0, 1), now);
+        let leap = leap.unwrap();
+        assert_eq!(leap.source, LeapSource::Servers);
+        assert_eq!(leap.at_ms, utc_ms(2027, 1, 1));
+        assert_eq!(leap.direction, LeapDirection::Insert);
+    }
+
+    #[test]
+    fn split_vote_has_no_majority() {
+        assert_eq!(votes(1, 0, 1).majority(), None);
+        assert_eq!(votes(1, 1, 0).majority(), None);
+        assert_eq!(votes(2, 1, 1).majority(), None);
+        assert_eq!(votes(2, 0, 1).majority(), Some(LeapDirection::Insert));
+        assert_eq!(votes(0, 2, 1).majority(), Some(LeapDirection::Delete));
+
+        let (leap, warning) = schedule(None, &votes(1, 0, 1), utc_ms(2026, 12, 15));
+        assert!(leap.is_none() && warning.is_none());
+    }
+
+    #[test]
+    fn blackout_boundary() {
+        let table = parse_leap_file(FIXTURE, &fixture()).unwrap();
+        let none = votes(0, 0, 1);
+        let at = |now| blackout_at(Some(&table), &none, now).map(|e| e.code);
+
+        assert_eq!(at(LEAP_2017_MS - STEP_BLACKOUT_MS), None);
+        assert_eq!(
+            at(LEAP_2017_MS - STEP_BLACKOUT_MS + 1).as_deref(),
+            Some("LEAP_SECOND_WINDOW")
+        );
+        assert!(at(LEAP_2017_MS).is_some());
+        assert!(at(LEAP_2017_MS + STEP_BLACKOUT_MS - 1).is_some());
+        assert_eq!(at(LEAP_2017_MS + STEP_BLACKOUT_MS), None);
+
+        // 沒有檔案時，過半數伺服器宣告的閏秒涵蓋月底與月初
+        let month_end = utc_ms(2027, 1, 1);
+        let servers = |now| blackout_at(None, &votes(2, 0, 1), now).is_some();
+        assert!(servers(month_end - 60_000));
+        assert!(!servers(month_end - STEP_BLACKOUT_MS));
+        assert!(blackout_at(None, &votes(1, 0, 1), month_end - 60_000).is_none());
+    }
+
+    #[test]
+    fn offset_divergence_detects_smear() {
+        // 不經過全域的 LEAP_TABLE/OFFSETS，避免與平行執行的查詢測試互相干擾
+        let leaps = table_leaps(&parse_leap_file(FIXTURE, &fixture()).unwrap());
+        let smear = |utc_ms: i64| {
+            let leap = leaps
+                .iter()
+                .find(|(at, _)| (utc_ms - at).abs() < SMEAR_HALF_WINDOW_MS)
+                .copied();
+            smear_lag_ms(leap, utc_ms as f64)
+        };
+
+        // 閏秒前 6 小時，smear 時鐘落後 UTC 250ms
+        let now = LEAP_2017_MS - 6 * 3600 * 1000;
+        let expected = smear(now);
+        assert!((expected + 250.0).abs() < 1e-6);
+        assert!((smear(LEAP_2017_MS - 1) + 500.0).abs() < 0.01);
+        assert!((smear(LEAP_2017_MS) - 500.0).abs() < 1e-6);
+        assert_eq!(smear(LEAP_2017_MS - SMEAR_HALF_WINDOW_MS), 0.0);
+
+        let offsets: HashMap<String, (f64, i64)> = ["198.51.100.1", "198.51.100.2", "198.51.100.3"]
+            .into_iter()
+            .zip([2.0, -1.0, 0.5])
+            .map(|(ip, offset)| (ip.to_string(), (offset, now)))
+            .collect();
+
+        let mut smearing = ntp_result("198.51.100.10", 0, 5.0);
+        smearing.offset = -248.0;
+        let mut honest = ntp_result("198.51.100.11", 0, 5.0);
+        honest.offset = 3.0;
+        let mut unrelated = ntp_result("198.51.100.12", 0, 5.0);
+        unrelated.offset = 250.0;
+
+        assert!(diverges_like_smear(&smearing, expected, now, &offsets));
+        assert!(!diverges_like_smear(&honest, expected, now, &offsets));
+        assert!(!diverges_like_smear(&unrelated, expected, now, &offsets));
+        // 遠離閏秒時不判斷
+        let distant = utc_ms(2016, 12, 1);
+        assert!(!diverges_like_smear(
+            &smearing,
+            smear(distant),
+            distant,
+            &offsets
+        ));
+    }
+}
@@ -0,0 +1,514 @@
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDateTime, Utc};
+use std::io::Read;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::core::db::NtpRecord;
+use crate::core::ntp::{self, NtpError, NtpResult, QueryOptions, ServerSpec, TimeProtocol};
+use crate::core::timestamp::NtpTimestamp;
+
+const TIME_PORT: u16 = 37;
+const DAYTIME_PORT: u16 = 13;
+const MAX_DAYTIME_SIZE: usize = 512;
+/// 只有秒精度的來源，以該秒的中點作為伺服器時間
+const SECOND_QUANTIZATION_MS: f64 = 1000.0;
+
+const SCHEMES: [(&str, TimeProtocol); 3] = [
+    ("time://", TimeProtocol::TimeTcp),
+    ("time+udp://", TimeProtocol::TimeUdp),
+    ("daytime://", TimeProtocol::Daytime),
+];
+
+/// 常見時區縮寫的 UTC 偏移 (分鐘)，CST 同時是美國中部與台灣/中國標準時間
+const ZONE_ABBREVIATIONS: &[(&str, &[i32])] = &[
+    ("UT", &[0]),
+    ("UTC", &[0]),
+    ("GMT", &[0]),
+    ("Z", &[0]),
+    ("EST", &[-300]),
+    ("EDT", &[-240]),
+    ("CST", &[-360, 480]),
+    ("CDT", &[-300]),
+    ("MST", &[-420]),
+    ("MDT", &[-360]),
+    ("PST", &[-480]),
+    ("PDT", &[-420]),
+    ("JST", &[540]),
+    ("KST", &[540]),
+    ("HKT", &[480]),
+    ("CET", &[60]),
+    ("CEST", &[120]),
+];
+
+/// 時區欄位之前的日期時間格式，依序嘗試
+const DAYTIME_FORMATS: &[&str] = &[
+    // RFC 867 範例: Tuesday, February 22, 1982 17:37:43
+    "%A, %B %d, %Y %H:%M:%S%.f",
+    // RFC 867 範例: 02 FEB 82 07:59:01
+    "%d %b %y %H:%M:%S%.f",
+    "%d %b %Y %H:%M:%S%.f",
+    // inetd / ctime: Tue Mar  4 12:34:56 2025
+    "%a %b %d %H:%M:%S%.f %Y",
+    "%a, %d %b %Y %H:%M:%S%.f",
+    "%a %d %b %Y %H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y/%m/%d %H:%M:%S%.f",
+];
+
+/// `time://`、`time+udp://`、`daytime://` 開頭的伺服器使用 RFC 868/867 查詢
+pub fn is_legacy_url(server: &str) -> bool {
+    parse_scheme(server).is_some()
+}
+
+fn parse_scheme(server: &str) -> Option<(TimeProtocol, &str)> {
+    let server = server.trim();
+    SCHEMES.iter().find_map(|(scheme, protocol)| {
+        let prefix = server.get(..scheme.len())?;
+        prefix
+            .eq_ignore_ascii_case(scheme)
+            .then(|| (*protocol, server[scheme.len()..].trim_end_matches('/')))
+    })
+}
+
+fn connect(addr: &std::net::SocketAddr, timeout: Duration) -> Result<TcpStream, NtpError> {
+    let tcp = TcpStream::connect_timeout(addr, timeout)
+        .map_err(|e| NtpError::new("TCP_CONNECT", format!("無法連線伺服器: {}", e)))?;
+    tcp.set_read_timeout(Some(timeout)).ok();
+    Ok(tcp)
+}
+
+/// 讀取伺服器在連線建立後主動送出的資料，回傳 (資料, t1, t4)
+///
+/// 伺服器收到握手的最後一個 ACK 後才送出時間，因此以 connect 完成的時間作為 t1
+fn read_greeting(
+    addr: &std::net::SocketAddr,
+    limit: usize,
+    timeout: Duration,
+) -> Result<(Vec<u8>, NtpTimestamp, NtpTimestamp), NtpError> {
+    let recv_error =
+        |e: std::io::Error| NtpError::new("RECV_ERROR", format!("無法接收回應: {}", e));
+
+    let mut tcp = connect(addr, timeout)?;
+    let t1 = NtpTimestamp::now();
+
+    let mut buf = vec![0u8; limit];
+    let size = tcp.read(&mut buf).map_err(recv_error)?;
+    let t4 = NtpTimestamp::now();
+
+    let mut data = buf[..size].to_vec();
+    while size > 0 && data.len() < limit {
+        match tcp
+            .read(&mut buf[..limit - data.len()])
+            .map_err(recv_error)?
+        {
+            0 => break,
+            n => data.extend_from_slice(&buf[..n]),
+        }
+    }
+    Ok((data, t1, t4))
+}
+
+/// RFC 868 回傳 1900 年起的 32 位元秒數，與 NTP 時間戳的秒數部分相同
+fn time_from_seconds(data: &[u8]) -> Result<NtpTimestamp, NtpError> {
+    if data.len() < 4 {
+        return Err(NtpError::new(
+            "INCOMPLETE",
+            format!("回應不完整: {} bytes", data.len()),
+        ));
+    }
+    let mut timestamp = [0u8; 8];
+    timestamp[..4].copy_from_slice(&data[..4]);
+    // 小數部分設為 0.5 秒
+    timestamp[4] = 0x80;
+    Ok(NtpTimestamp::read(&timestamp, 0))
+}
+
+fn query_time_tcp(server: &str, host: &str, options: &QueryOptions) -> Result<NtpResult, NtpError> {
+    let addr = ServerSpec::parse(host, TIME_PORT)?.resolve(options.address_family)?;
+    let (data, t1, t4) = read_greeting(&addr, 4, options.timeout())?;
+    let server_time = time_from_seconds(&data)?;
+
+    Ok(ntp::single_time_result(
+        server,
+        addr,
+        (t1, t4),
+        server_time,
+        SECOND_QUANTIZATION_MS / 2.0,
+        TimeProtocol::TimeTcp,
+    ))
+}
+
+fn query_time_udp(server: &str, host: &str, options: &QueryOptions) -> Result<NtpResult, NtpError> {
+    let addr = ServerSpec::parse(host, TIME_PORT)?.resolve(options.address_family)?;
+    let socket = ntp::bind_socket(&addr, options)?;
+
+    // RFC 868 的 UDP 請求為空的 datagram
+    let mut response = [0u8; 64];
+    let exchange = ntp::exchange(&socket, addr, &[], NtpTimestamp::now(), &mut response)?;
+    if exchange.peer_addr != addr {
+        return Err(NtpError::new(
+            "SOURCE_MISMATCH",
+            format!("回應來源 {} 與請求目標 {} 不符", exchange.peer_addr, addr),
+        ));
+    }
+    let server_time = time_from_seconds(&response[..exchange.size])?;
+
+    let mut result = ntp::single_time_result(
+        server,
+        addr,
+        (exchange.t1, exchange.t4),
+        server_time,
+        SECOND_QUANTIZATION_MS / 2.0,
+        TimeProtocol::TimeUdp,
+    );
+    result.t1_source = exchange.t1_source;
+    result.t4_source = exchange.t4_source;
+    Ok(result)
+}
+
+fn query_daytime(server: &str, host: &str, options: &QueryOptions) -> Result<NtpResult, NtpError> {
+    let addr = ServerSpec::parse(host, DAYTIME_PORT)?.resolve(options.address_family)?;
+    let (data, t1, t4) = read_greeting(&addr, MAX_DAYTIME_SIZE, options.timeout())?;
+    let text = String::from_utf8_lossy(&data);
+
+    let reading = parse_daytime(&text, t4.to_unix_ms())?;
+    let (server_time, uncertainty) = if reading.exact {
+        (reading.time, 0.0)
+    } else {
+        let half = ChronoDuration::milliseconds((SECOND_QUANTIZATION_MS / 2.0) as i64);
+        (reading.time + half, SECOND_QUANTIZATION_MS / 2.0)
+    };
+
+    let mut result = ntp::single_time_result(
+        server,
+        addr,
+        (t1, t4),
+        NtpTimestamp::from(server_time),
+        uncertainty,
+        TimeProtocol::Daytime,
+    );
+    result.zone_unknown = reading.zone_unknown;
+    Ok(result)
+}
+
+/// 解析後的 daytime 時間
+struct DaytimeReading {
+    time: DateTime<Utc>,
+    /// 含小數秒或為 NIST 的準時時刻，不需以該秒的中點估計
+    exact: bool,
+    /// 回應未標示時區或時區不明，時間以 UTC 解讀
+    zone_unknown: bool,
+}
+
+/// NIST 格式: `JJJJJ YR-MO-DA HH:MM:SS TT L H msADV UTC(NIST) OTM`
+///
+/// msADV 為伺服器為補償網路延遲而提前的毫秒數，扣除後即為送出時間；
+/// 結尾的 OTM 標記該秒開始的時刻，因此沒有量化誤差
+fn parse_nist(line: &str) -> Option<DateTime<Utc>> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.len() < 3 || !tokens[0].bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let time =
+        NaiveDateTime::parse_from_str(&format!("{} {}", tokens[1], tokens[2]), "%y-%m-%d %H:%M:%S")
+            .ok()?
+            .and_utc();
+
+    let advance_ms = match tokens.iter().position(|t| t.starts_with("UTC(")) {
+        Some(i) if i >= 1 => tokens[i - 1].parse::<f64>().unwrap_or(0.0),
+        _ => 0.0,
+    };
+    Some(time - ChronoDuration::microseconds((advance_ms * 1000.0) as i64))
+}
+
+fn zone_offsets(zone: &str) -> Option<Vec<i32>> {
+    if let Some(digits) = zone.strip_prefix(['+', '-']) {
+        let sign = if zone.starts_with('-') { -1 } else { 1 };
+        let digits = digits.replace(':', "");
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let value: i32 = digits.parse().ok()?;
+        return match digits.len() {
+            2 => Some(vec![sign * value * 60]),
+            4 => Some(vec![sign * (value / 100 * 60 + value % 100)]),
+            _ => None,
+        };
+    }
+    ZONE_ABBREVIATIONS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(zone))
+        .map(|(_, offsets)| offsets.to_vec())
+}
+
+fn is_zone_token(token: &str) -> bool {
+    zone_offsets(token).is_some()
+        || (token.len() <= 5 && token.bytes().all(|b| b.is_ascii_uppercase()))
+}
+
+/// 拆出時區，回傳 (日期時間部分, 可能的 UTC 偏移)，時區不明時偏移為 None
+///
+/// 支援 `... 17:37:43 PST`、`... 17:37:43-PST`、`+0800`/`-05:00` 與 date(1) 的 `... 12:34:56 CST 2025`
+fn split_zone(line: &str) -> (String, Option<Vec<i32>>) {
+    let mut tokens: Vec<&str> = line.split_whitespace().collect();
+    let Some(&last) = tokens.last() else {
+        return (String::new(), None);
+    };
+
+    // 時區緊接在時間之後，例如 17:37:43-PST 或 17:37:43+08:00
+    if let Some(colon) = last.find(':') {
+        let Some(i) = last[colon..].find(['+', '-']) else {
+            return (line.to_string(), None);
+        };
+        let (time, zone) = last.split_at(colon + i);
+        let zone = match zone.strip_prefix('-') {
+            Some(name) if name.bytes().all(|b| b.is_ascii_alphabetic()) => name,
+            _ => zone,
+        };
+        let offsets = zone_offsets(zone);
+        *tokens.last_mut().unwrap() = time;
+        return (tokens.join(" "), offsets);
+    }
+
+    let index = if last.bytes().all(|b| b.is_ascii_digit()) {
+        // date(1) 格式的時區在年份之前
+        tokens.len().checked_sub(2)
+    } else {
+        Some(tokens.len() - 1)
+    };
+    match index {
+        Some(i) if i > 0 && is_zone_token(tokens[i]) => {
+            let offsets = zone_offsets(tokens.remove(i));
+            (tokens.join(" "), offsets)
+        }
+        _ => (line.to_string(), None),
+    }
+}
+
+/// 解析常見的 daytime 字串
+///
+/// 未標示時區或時區不明時以 UTC 解讀並標記，不從本機時間推定偏移，
+/// 否則本機時鐘的誤差會被當成時區吸收；縮寫對應多個時區時取最接近本機時間者
+fn parse_daytime(text: &str, local_ms: f64) -> Result<DaytimeReading, NtpError> {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    let invalid = || {
+        NtpError::new(
+            "DAYTIME_PARSE",
+            format!("無法解析 daytime 回應: {:?}", line),
+        )
+    };
+
+    let reading = |time: DateTime<Utc>, exact: bool| DaytimeReading {
+        time,
+        exact,
+        zone_unknown: false,
+    };
+    if let Some(time) = parse_nist(line) {
+        return Ok(reading(time, true));
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(line) {
+        return Ok(reading(time.to_utc(), time.timestamp_subsec_nanos() != 0));
+    }
+    if let Ok(time) = DateTime::parse_from_rfc2822(line) {
+        return Ok(reading(time.to_utc(), false));
+    }
+
+    let (body, offsets) = split_zone(line);
+    let naive = DAYTIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&body, format).ok())
+        .ok_or_else(invalid)?;
+
+    let exact = naive.and_utc().timestamp_subsec_nanos() != 0;
+    let Some(offsets) = offsets else {
+        return Ok(DaytimeReading {
+            time: naive.and_utc(),
+            exact,
+            zone_unknown: true,
+        });
+    };
+
+    let local = DateTime::from_timestamp_millis(local_ms as i64).ok_or_else(invalid)?;
+    let time = offsets
+        .iter()
+        .map(|offset| naive.and_utc() - ChronoDuration::minutes(*offset as i64))
+        .min_by_key(|time| (*time - local).num_milliseconds().abs())
+        .ok_or_else(invalid)?;
+
+    Ok(reading(time, exact))
+}
+
+pub fn query_legacy(server: &str, options: &QueryOptions) -> Result<NtpResult, NtpError> {
+    let (protocol, host) = parse_scheme(server)
+        .ok_or_else(|| NtpError::new("INVALID_SERVER", format!("無效的伺服器位址: {}", server)))?;
+
+    match protocol {
+        TimeProtocol::TimeTcp => query_time_tcp(server, host, options),
+        TimeProtocol::TimeUdp => query_time_udp(server, host, options),
+        _ => query_daytime(server, host, options),
+    }
+}
+
+/// 查詢 RFC 868/867 伺服器，回傳可直接寫入歷史資料庫的記錄
+#[tauri::command]
+pub async fn query_legacy_time(
+    server: String,
+    options: Option<QueryOptions>,
+) -> Result<String, String> {
+    println!("[LEGACY] 查詢 {}", server);
+
+    let options = options.unwrap_or_else(|| ntp::server_defaults(&server));
+    let task = tokio::task::spawn_blocking(move || query_legacy(&server, &options));
+    match task.await.map_err(|e| e.to_string())? {
+        Ok(result) => {
+            if result.zone_unknown {
+                println!("[LEGACY] ⚠ {} 未標示時區，以 UTC 解讀", result.server);
+            }
+            println!(
+                "[LEGACY] ✓ {} ({}) | offset={}ms delay={}ms protocol={}",
+                result.server,
+                result.server_ip,
+                result.offset,
+                result.delay,
+                result.protocol.as_str()
+            );
+            let record = NtpRecord::from_result(&result, Utc::now().timestamp_millis());
+            serde_json::to_string(&record).map_err(|e| e.to_string())
+        }
+        Err(error) => {
+            println!("[LEGACY] ✗ {} ({})", error.error, error.code);
+            serde_json::to_string(&error).map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::{TcpListener, UdpSocket};
+
+    fn at(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().to_utc()
+    }
+
+    fn local_ms(text: &str) -> f64 {
+        at(text).timestamp_millis() as f64
+    }
+
+    #[test]
+    fn nist_line_is_exact() {
+        let line = "60740 25-03-04 12:34:56 00 0 0  50.0 UTC(NIST) *\n";
+        let reading = parse_daytime(line, local_ms("2025-03-04T12:34:56Z")).unwrap();
+        assert!(reading.exact);
+        assert!(!reading.zone_unknown);
+        assert_eq!(reading.time, at("2025-03-04T12:34:55.950Z"));
+    }
+
+    #[test]
+    fn zoneless_reply_is_flagged_not_inferred() {
+        // 本機時鐘慢了 8 小時又 10 分鐘，不能被當成時區吸收
+        let local = local_ms("2025-03-04T04:24:56Z");
+        let reading = parse_daytime("Tue Mar  4 12:34:56 2025\r\n", local).unwrap();
+        assert!(reading.zone_unknown);
+        assert!(!reading.exact);
+        assert_eq!(reading.time, at("2025-03-04T12:34:56Z"));
+    }
+
+    #[test]
+    fn labelled_zone_is_applied() {
+        let local = local_ms("2025-03-04T04:34:56Z");
+        for line in [
+            "Tuesday, March 4, 2025 12:34:56-CST",
+            "Tue Mar  4 12:34:56 CST 2025",
+            "2025-03-04 12:34:56 +0800",
+        ] {
+            let reading = parse_daytime(line, local).unwrap();
+            assert!(!reading.zone_unknown, "{}", line);
+            assert_eq!(reading.time, at("2025-03-04T04:34:56Z"), "{}", line);
+        }
+    }
+
+    /// 伺服器時間比本機快一小時
+    const AHEAD_MS: f64 = 3_600_000.0;
+
+    fn ahead() -> DateTime<Utc> {
+        Utc::now() + ChronoDuration::milliseconds(AHEAD_MS as i64)
+    }
+
+    /// RFC 868 回應的 32 位元秒數
+    fn time_payload() -> Vec<u8> {
+        NtpTimestamp::from(ahead()).seconds().to_be_bytes().to_vec()
+    }
+
+    /// 接受一個連線後送出 payload 並關閉，回傳 127.0.0.1 位址
+    fn serve_tcp(payload: fn() -> Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&payload()).unwrap();
+        });
+        addr.to_string()
+    }
+
+    fn serve_udp(payload: fn() -> Vec<u8>) -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            let (size, peer) = socket.recv_from(&mut buf).unwrap();
+            assert_eq!(size, 0);
+            socket.send_to(&payload(), peer).unwrap();
+        });
+        addr.to_string()
+    }
+
+    fn query(url: String) -> NtpResult {
+        let result = query_legacy(&url, &QueryOptions::default()).unwrap();
+        assert_eq!(result.stratum, ntp::UNSYNCHRONIZED_STRATUM);
+        // 秒精度的來源加上量化誤差，實際偏差在一秒內
+        let error = result.offset - AHEAD_MS;
+        assert!(error.abs() <= SECOND_QUANTIZATION_MS, "{}", result.offset);
+        result
+    }
+
+    #[test]
+    fn time_tcp_against_local_server() {
+        let result = query(format!("time://{}", serve_tcp(time_payload)));
+        assert_eq!(result.protocol, TimeProtocol::TimeTcp);
+        assert_eq!(result.root_dispersion, SECOND_QUANTIZATION_MS / 2.0);
+    }
+
+    #[test]
+    fn time_udp_against_local_server() {
+        let result = query(format!("time+udp://{}", serve_udp(time_payload)));
+        assert_eq!(result.protocol, TimeProtocol::TimeUdp);
+        assert_eq!(result.root_dispersion, SECOND_QUANTIZATION_MS / 2.0);
+    }
+
+    #[test]
+    fn daytime_against_local_server() {
+        let nist = || {
+            let line = ahead().format("60740 %y-%m-%d %H:%M:%S 00 0 0   0.0 UTC(NIST) *\r\n");
+            line.to_string().into_bytes()
+        };
+        let result = query(format!("daytime://{}", serve_tcp(nist)));
+        assert_eq!(result.protocol, TimeProtocol::Daytime);
+        assert_eq!(result.root_dispersion, 0.0);
+        assert!(!result.zone_unknown);
+
+        let zoneless = || {
+            let line = ahead().format("%a %b %e %H:%M:%S %Y\r\n");
+            line.to_string().into_bytes()
+        };
+        let result = query(format!("daytime://{}", serve_tcp(zoneless)));
+        assert!(result.zone_unknown);
+        assert_eq!(result.root_dispersion, SECOND_QUANTIZATION_MS / 2.0);
+    }
+}
@@ -1,3 +1,20 @@
+pub mod auth;
+pub mod broadcast;
 pub mod db;
+pub mod engine;
+pub mod extension;
+pub mod http;
+pub mod kod;
+pub mod leap;
+pub mod legacy;
 pub mod ntp;
+pub mod nts;
 pub mod offset;
+pub mod packet;
+pub mod ptp;
+pub mod roughtime;
+pub mod server;
+pub mod sockopt;
+pub mod timescale;
+pub mod timestamp;
+pub mod timestamping;
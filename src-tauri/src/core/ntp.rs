@@ -1,6 +1,15 @@
+use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::net::UdpSocket;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::core::packet::{self, NtpPacket};
+use crate::core::timestamp::{offset_and_delay, NtpTimestamp};
+use crate::core::timestamping::{self, TimestampSource};
+use crate::core::extension::{self, ExtensionField};
+use crate::core::{auth, broadcast, db, engine, http, kod, leap, legacy, nts, ptp, sockopt};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NtpResult {
@@ -21,8 +30,73 @@ pub struct NtpResult {
     pub precision: i8,
     pub root_delay: f64,
     pub root_dispersion: f64,
+    /// RFC 5905 同步距離：offset 的最大誤差範圍 (ms)，為 t4 當下的值，不隨樣本老化增加
+    pub root_distance: f64,
+    /// 伺服器以 leap smear 處理閏秒 (已知的業者，或閏秒前後 offset 與其他來源的偏離符合 smear)
+    pub smearing: bool,
     pub ref_id: String,
+    /// Reference ID 原始的 4 bytes (十六進位)，單一時間來源為空字串
+    #[serde(default)]
+    pub ref_id_raw: String,
+    /// stratum 2 以上時 ref_id 顯示的 IPv4 位址也可能是 IPv6 上游的 MD5 雜湊
+    #[serde(default)]
+    pub ref_id_ambiguous: bool,
     pub ref_time: f64,
+    pub nts: bool,
+    pub auth_key_id: Option<u32>,
+    pub address_family: String,
+    pub t1_source: TimestampSource,
+    pub t4_source: TimestampSource,
+    pub sample_mode: SampleMode,
+    pub extension_fields: Vec<ExtensionField>,
+    pub protocol: TimeProtocol,
+    /// daytime 回應未標示時區，時間以 UTC 解讀，offset 可能差了整數個時區
+    #[serde(default)]
+    pub zone_unknown: bool,
+}
+
+/// 結果的時間來源協定，寫入歷史資料庫時一併記錄
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeProtocol {
+    #[default]
+    Ntp,
+    Roughtime,
+    Http,
+    /// RFC 868 Time (TCP 37)
+    TimeTcp,
+    /// RFC 868 Time (UDP 37)
+    TimeUdp,
+    /// RFC 867 Daytime (TCP 13)
+    Daytime,
+    /// IEEE 1588 PTPv2 over UDP (319/320)
+    Ptp,
+}
+
+impl TimeProtocol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TimeProtocol::Ntp => "ntp",
+            TimeProtocol::Roughtime => "roughtime",
+            TimeProtocol::Http => "http",
+            TimeProtocol::TimeTcp => "time_tcp",
+            TimeProtocol::TimeUdp => "time_udp",
+            TimeProtocol::Daytime => "daytime",
+            TimeProtocol::Ptp => "ptp",
+        }
+    }
+}
+
+/// 樣本由哪一種模式的交換計算而來
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleMode {
+    #[default]
+    Basic,
+    /// 交錯模式：t1~t4 來自前一次交換，t3 為伺服器實際送出的時間
+    Interleaved,
+    /// 廣播模式 (mode 5)：t1 由校準的單程延遲推算，t2 與 t3 同為伺服器送出時間
+    Broadcast,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,176 +104,606 @@ pub struct NtpError {
     pub success: bool,
     pub error: String,
     pub code: String,
+    /// Kiss-o'-Death 回應的原始 kiss code，未知代碼的錯誤碼為 KOD_UNKNOWN 時仍保留實際內容
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kiss_code: Option<String>,
+}
+
+impl NtpError {
+    pub(crate) fn new(code: &str, error: impl Into<String>) -> Self {
+        NtpError {
+            success: false,
+            error: error.into(),
+            code: code.to_string(),
+            kiss_code: None,
+        }
+    }
 }
 
-const NTP_TO_UNIX_EPOCH: u64 = 2208988800;
-const NTP_PACKET_SIZE: usize = 48;
-const NTP_PORT: u16 = 123;
-const NTP_TIMEOUT_SECS: u64 = 5;
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressFamilyPreference {
+    /// 依系統解析順序
+    #[default]
+    System,
+    PreferIpv6,
+    PreferIpv4,
+}
 
-fn unix_ms_to_ntp(unix_ms: f64) -> (u32, u32) {
-    let unix_secs = unix_ms / 1000.0;
-    let ntp_secs = (unix_secs + NTP_TO_UNIX_EPOCH as f64) as u32;
-    let fraction = ((unix_ms % 1000.0) / 1000.0 * 4294967296.0) as u32;
-    (ntp_secs, fraction)
+/// 伺服器位址，支援 `host`、`host:port`、`[v6]:port` 與未加括號的 IPv6 位址
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerSpec {
+    pub host: String,
+    pub port: u16,
 }
 
-fn write_ntp_timestamp(packet: &mut [u8], offset: usize, secs: u32, frac: u32) {
-    packet[offset..offset + 4].copy_from_slice(&secs.to_be_bytes());
-    packet[offset + 4..offset + 8].copy_from_slice(&frac.to_be_bytes());
+impl ServerSpec {
+    pub fn parse(server: &str, default_port: u16) -> Result<Self, NtpError> {
+        let server = server.trim();
+        let invalid = || NtpError::new("INVALID_SERVER", format!("無效的伺服器位址: {}", server));
+
+        if let Some(rest) = server.strip_prefix('[') {
+            let (host, tail) = rest.split_once(']').ok_or_else(invalid)?;
+            host.parse::<Ipv6Addr>().map_err(|_| invalid())?;
+            let port = match tail {
+                "" => default_port,
+                _ => tail
+                    .strip_prefix(':')
+                    .and_then(|p| p.parse().ok())
+                    .ok_or_else(invalid)?,
+            };
+            return Ok(ServerSpec {
+                host: host.to_string(),
+                port,
+            });
+        }
+
+        if server.parse::<Ipv6Addr>().is_ok() {
+            return Ok(ServerSpec {
+                host: server.to_string(),
+                port: default_port,
+            });
+        }
+
+        match server.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && !host.contains(':') => Ok(ServerSpec {
+                host: host.to_string(),
+                port: port.parse().map_err(|_| invalid())?,
+            }),
+            Some(_) => Err(invalid()),
+            None if server.is_empty() => Err(invalid()),
+            None => Ok(ServerSpec {
+                host: server.to_string(),
+                port: default_port,
+            }),
+        }
+    }
+
+    /// 解析所有 A/AAAA 記錄，並依偏好的位址族排序
+    pub fn resolve_all(
+        &self,
+        preference: AddressFamilyPreference,
+    ) -> Result<Vec<SocketAddr>, NtpError> {
+        let addrs = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(|e| self.dns_error(e))?
+            .collect();
+        self.order_addresses(addrs, preference)
+    }
+
+    /// `resolve_all` 的非同步版本，不佔用 runtime worker
+    pub async fn lookup_all(
+        &self,
+        preference: AddressFamilyPreference,
+    ) -> Result<Vec<SocketAddr>, NtpError> {
+        let addrs = tokio::net::lookup_host((self.host.as_str(), self.port))
+            .await
+            .map_err(|e| self.dns_error(e))?
+            .collect();
+        self.order_addresses(addrs, preference)
+    }
+
+    fn dns_error(&self, e: std::io::Error) -> NtpError {
+        NtpError::new("DNS_ERROR", format!("無法解析 {}: {}", self.host, e))
+    }
+
+    fn order_addresses(
+        &self,
+        mut addrs: Vec<SocketAddr>,
+        preference: AddressFamilyPreference,
+    ) -> Result<Vec<SocketAddr>, NtpError> {
+        match preference {
+            AddressFamilyPreference::System => {}
+            AddressFamilyPreference::PreferIpv6 => addrs.sort_by_key(|a| !a.is_ipv6()),
+            AddressFamilyPreference::PreferIpv4 => addrs.sort_by_key(|a| !a.is_ipv4()),
+        }
+
+        if addrs.is_empty() {
+            return Err(NtpError::new(
+                "DNS_ERROR",
+                format!("{} 沒有可用的位址", self.host),
+            ));
+        }
+        Ok(addrs)
+    }
+
+    pub fn resolve(&self, preference: AddressFamilyPreference) -> Result<SocketAddr, NtpError> {
+        Ok(self.resolve_all(preference)?[0])
+    }
 }
 
-fn extract_ntp_timestamp(packet: &[u8], offset: usize) -> (u64, u64) {
-    let seconds = u32::from_be_bytes([
-        packet[offset],
-        packet[offset + 1],
-        packet[offset + 2],
-        packet[offset + 3],
-    ]) as u64;
-    let fraction = u32::from_be_bytes([
-        packet[offset + 4],
-        packet[offset + 5],
-        packet[offset + 6],
-        packet[offset + 7],
-    ]) as u64;
-    (seconds, fraction)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueryOptions {
+    /// 使用 NTS (RFC 8915) 進行金鑰交換與封包認證
+    pub nts: bool,
+    /// 額外信任的 CA 憑證 (PEM)，用於自簽憑證的 NTS-KE 伺服器
+    pub nts_ca_file: Option<String>,
+    /// 對稱金鑰認證使用的 key ID，設定後會拒絕未認證的回應
+    pub key_id: Option<u32>,
+    /// ntpd/chrony 格式的金鑰檔，未指定時搜尋系統預設位置
+    pub keys_file: Option<String>,
+    /// 雙協定堆疊主機上優先使用的位址族
+    pub address_family: AddressFamilyPreference,
+    /// 寬鬆模式：回應驗證失敗時只印出警告，用於除錯行為特殊的伺服器
+    pub lenient: bool,
+    /// 交錯模式 (chrony xleave)，伺服器不支援時自動退回基本模式
+    pub interleaved: bool,
+    /// 附加在請求中的擴充欄位，用於測試伺服器對實驗性欄位的處理
+    pub extension_fields: Vec<ExtensionField>,
+    /// 同步時 NTP 查詢全部失敗後改用此網址的 HTTP Date 標頭，未指定時為 http::DEFAULT_FALLBACK_URL，空字串表示停用
+    pub http_fallback: Option<String>,
+    /// 單次嘗試的逾時 (ms)，未指定時為 NTP_TIMEOUT_SECS
+    pub timeout_ms: Option<u64>,
+    /// 逾時或收送失敗後的重試次數
+    pub retries: u32,
+    /// 請求封包的 NTP 版本 (1~4)，未指定時為 4
+    pub version: Option<u8>,
+    /// 多網卡主機上指定送出請求的本機位址
+    pub source_address: Option<String>,
+    /// 固定的本機來源連接埠，部分防火牆只放行 123 對 123
+    pub source_port: Option<u16>,
+    /// 以 SO_BINDTODEVICE 綁定的網路介面名稱 (Linux)
+    pub interface: Option<String>,
+    /// 請求封包的 DSCP 標記 (0~63)
+    pub dscp: Option<u8>,
+    /// 資料最小化 (RFC 9109)：Transmit Timestamp 改放隨機 nonce，每次請求使用隨機來源連接埠
+    pub privacy: bool,
+    /// 同步距離上限 (ms)，超過時拒絕樣本，未指定時為 MAX_DISTANCE_MS
+    pub max_distance_ms: Option<f64>,
 }
 
-fn ntp_to_unix_ms(seconds: u64, fraction: u64) -> f64 {
-    if seconds == 0 && fraction == 0 {
-        return 0.0;
+impl QueryOptions {
+    pub fn timeout(&self) -> Duration {
+        self.timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(Duration::from_secs(NTP_TIMEOUT_SECS))
+    }
+
+    pub(crate) fn version(&self) -> u8 {
+        self.version.unwrap_or(NTP_VERSION)
+    }
+
+    /// 指定了來源位址、介面、DSCP 或隱私模式時無法使用共用 socket
+    pub(crate) fn needs_own_socket(&self) -> bool {
+        self.source_address.is_some()
+            || self.source_port.is_some()
+            || self.interface.is_some()
+            || self.dscp.is_some()
+            || self.privacy
+    }
+
+    /// 請求的 Transmit Timestamp，隱私模式下為隨機 nonce，t1 另由本地記錄
+    pub(crate) fn request_origin(&self) -> NtpTimestamp {
+        match self.privacy {
+            true => NtpTimestamp::random(),
+            false => NtpTimestamp::now(),
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), NtpError> {
+        let invalid = |msg: String| Err(NtpError::new("INVALID_OPTION", msg));
+        if let Some(version) = self.version.filter(|v| !(1..=4).contains(v)) {
+            return invalid(format!("不支援的 NTP 版本: {} (1~4)", version));
+        }
+        if let Some(dscp) = self.dscp.filter(|d| *d > 63) {
+            return invalid(format!("DSCP 超出範圍: {} (0~63)", dscp));
+        }
+        if self.timeout_ms == Some(0) {
+            return invalid("逾時必須大於 0".to_string());
+        }
+        if let Some(max) = self.max_distance_ms.filter(|m| m.is_nan() || *m <= 0.0) {
+            return invalid(format!("同步距離上限必須大於 0: {}", max));
+        }
+        // 交錯模式需要在請求中帶回前一次的時間戳，固定連接埠則無法隨機化
+        if self.privacy && self.interleaved {
+            return invalid("隱私模式無法與交錯模式同時使用".to_string());
+        }
+        if self.privacy && self.source_port.is_some() {
+            return invalid("隱私模式使用隨機來源連接埠，無法指定 source_port".to_string());
+        }
+        if let Some(address) = &self.source_address {
+            if address.trim().parse::<IpAddr>().is_err() {
+                return invalid(format!("無效的來源位址: {}", address));
+            }
+        }
+        Ok(())
     }
-    if seconds < NTP_TO_UNIX_EPOCH {
-        return 0.0;
+
+    /// 拒絕同步距離超過上限的樣本，寬鬆模式只印出警告
+    pub(crate) fn check_distance(&self, result: NtpResult) -> Result<NtpResult, NtpError> {
+        let max = self.max_distance_ms.unwrap_or(MAX_DISTANCE_MS);
+        if result.root_distance <= max {
+            return Ok(result);
+        }
+        let error = NtpError::new(
+            "DISTANCE_EXCEEDED",
+            format!(
+                "{} 的同步距離 {:.3}ms 超過上限 {:.3}ms",
+                result.server_ip, result.root_distance, max
+            ),
+        );
+        if self.lenient {
+            println!("[NTP] 警告 (寬鬆模式): {} ({})", error.error, error.code);
+            return Ok(result);
+        }
+        Err(error)
     }
-    let unix_seconds = seconds - NTP_TO_UNIX_EPOCH;
-    (unix_seconds as f64) * 1000.0 + (fraction as f64) * 1000.0 / 4294967296.0
 }
 
-fn duration_to_unix_ms(duration: Duration) -> f64 {
-    duration.as_secs_f64() * 1000.0
+/// 查詢成功後的共同處理：先拒絕同步距離過大的樣本，再記錄閏秒資訊並標記 leap smear 伺服器
+pub(crate) fn finish_result(
+    result: NtpResult,
+    options: &QueryOptions,
+) -> Result<NtpResult, NtpError> {
+    let mut result = options.check_distance(result)?;
+    leap::annotate(&mut result);
+    Ok(result)
 }
 
-fn decode_reference_id(ref_id_bytes: [u8; 4], stratum: u8) -> String {
-    if stratum == 0 || stratum == 1 {
-        ref_id_bytes
-            .iter()
-            .filter(|&&b| b >= 0x20 && b <= 0x7E)
-            .map(|&b| b as char)
-            .collect::<String>()
-            .trim()
-            .to_string()
-    } else {
-        format!(
-            "{}.{}.{}.{}",
-            ref_id_bytes[0], ref_id_bytes[1], ref_id_bytes[2], ref_id_bytes[3]
-        )
+/// RFC 5905 同步距離 (ms)：root delay/2 + root dispersion + delay/2 + 伺服器精度 + 量測期間的 PHI 漂移
+///
+/// 這是 t4 當下的快照：`delay * PHI` 只涵蓋 t1~t4 這段量測期間的本機頻率誤差，
+/// 不含樣本之後的老化 (RFC 5905 的 PHI * (now - t4))，之後再使用樣本時需自行加上
+pub(crate) fn root_distance(
+    root_delay: f64,
+    root_dispersion: f64,
+    delay: f64,
+    precision: Option<i8>,
+) -> f64 {
+    let delay = delay.max(0.0);
+    let precision_ms = precision.map_or(0.0, |p| 2f64.powi(p as i32) * 1000.0);
+    (root_delay + delay) / 2.0 + root_dispersion + precision_ms + delay * PHI
+}
+
+/// 只有逾時與收送失敗值得重試，KoD、認證失敗等重試也不會改變結果
+pub(crate) fn is_retryable(error: &NtpError) -> bool {
+    matches!(error.code.as_str(), "TIMEOUT" | "RECV_ERROR" | "SEND_ERROR")
+}
+
+/// 依 options.retries 重試逾時、收送失敗的查詢
+pub(crate) fn with_retries(
+    target: &str,
+    options: &QueryOptions,
+    mut query: impl FnMut() -> Result<NtpResult, NtpError>,
+) -> Result<NtpResult, NtpError> {
+    let mut attempt = 0;
+    loop {
+        match query() {
+            Err(e) if attempt < options.retries && is_retryable(&e) => {
+                attempt += 1;
+                println!("[NTP] {} 第 {} 次重試: {}", target, attempt, e.error);
+            }
+            result => return result,
+        }
     }
 }
 
-pub fn query_ntp(server: &str) -> Result<NtpResult, NtpError> {
-    let mut ntp_packet = [0u8; NTP_PACKET_SIZE];
-    ntp_packet[0] = 0x23;
+pub(crate) const NTP_PACKET_SIZE: usize = packet::HEADER_SIZE;
+pub(crate) const NTP_PORT: u16 = 123;
+pub(crate) const NTP_MAX_RESPONSE_SIZE: usize = 1024;
+pub(crate) const NTP_TIMEOUT_SECS: u64 = 5;
+pub(crate) const NTP_VERSION: u8 = 4;
+/// 本機時鐘的頻率容許誤差 (RFC 5905 PHI，15 ppm)
+pub(crate) const PHI: f64 = 15e-6;
+/// 未同步或不適用的 stratum (RFC 5905 MAXSTRAT)，0 保留給 Kiss-o'-Death
+pub(crate) const UNSYNCHRONIZED_STRATUM: u8 = 16;
+/// root distance 超過此值視為未同步 (RFC 5905 MAXDIST)
+pub(crate) const MAX_DISTANCE_MS: f64 = 1500.0;
+/// 連續收到這麼多次基本模式回應後，判定伺服器不支援交錯模式
+const INTERLEAVED_MAX_BASIC_REPLIES: u32 = 4;
+/// 隱私模式隨機選擇來源連接埠的範圍 (IANA 動態連接埠)
+const PRIVACY_PORT_RANGE: std::ops::RangeInclusive<u16> = 49152..=65535;
+const PRIVACY_BIND_ATTEMPTS: usize = 8;
 
-    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| NtpError {
-        success: false,
-        error: format!("無法綁定 UDP socket: {}", e),
-        code: "SOCKET_BIND".to_string(),
-    })?;
+/// 交錯模式下每個伺服器位址保留的前一次交換
+#[derive(Debug, Clone, Copy)]
+struct InterleavedState {
+    t1: NtpTimestamp,
+    t1_source: TimestampSource,
+    /// 前一次回應的 Receive Timestamp
+    t2: NtpTimestamp,
+    t4: NtpTimestamp,
+    t4_source: TimestampSource,
+    /// 送出交錯模式請求後連續收到基本模式回應的次數
+    basic_replies: u32,
+}
 
-    socket
-        .set_read_timeout(Some(Duration::from_secs(NTP_TIMEOUT_SECS)))
-        .map_err(|e| NtpError {
-            success: false,
-            error: format!("無法設定超時: {}", e),
-            code: "SOCKET_TIMEOUT".to_string(),
-        })?;
+impl InterleavedState {
+    fn supported(&self) -> bool {
+        self.basic_replies < INTERLEAVED_MAX_BASIC_REPLIES
+    }
+}
 
-    let server_addr = format!("{}:{}", server, NTP_PORT);
+lazy_static::lazy_static! {
+    static ref INTERLEAVED_STATES: Mutex<HashMap<SocketAddr, InterleavedState>> = Mutex::new(HashMap::new());
+}
 
-    let t1 = duration_to_unix_ms(SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| NtpError {
-        success: false,
-        error: format!("系統時間錯誤: {}", e),
-        code: "TIME_ERROR".to_string(),
-    })?);
+pub(crate) fn build_request(t1: NtpTimestamp, version: u8) -> [u8; NTP_PACKET_SIZE] {
+    NtpPacket::request(version, t1).encode()
+}
 
-    let (t1_secs, t1_frac) = unix_ms_to_ntp(t1);
-    write_ntp_timestamp(&mut ntp_packet, 40, t1_secs, t1_frac);
+pub(crate) fn address_family_name(addr: &SocketAddr) -> &'static str {
+    if addr.is_ipv6() {
+        "ipv6"
+    } else {
+        "ipv4"
+    }
+}
 
-    socket.send_to(&ntp_packet, &server_addr).map_err(|e| NtpError {
-        success: false,
-        error: format!("無法發送請求: {}", e),
-        code: "SEND_ERROR".to_string(),
+/// 綁定與目標位址相同位址族的 UDP socket，並套用來源位址、介面與 DSCP 設定
+pub(crate) fn bind_socket(
+    target: &SocketAddr,
+    options: &QueryOptions,
+) -> Result<UdpSocket, NtpError> {
+    let source = options
+        .source_address
+        .as_deref()
+        .and_then(|address| address.trim().parse::<IpAddr>().ok());
+    let ip = match (target, source) {
+        (_, Some(ip)) if ip.is_ipv6() != target.is_ipv6() => {
+            return Err(NtpError::new(
+                "INVALID_OPTION",
+                format!("來源位址 {} 與伺服器 {} 的位址族不同", ip, target),
+            ));
+        }
+        (_, Some(ip)) => ip,
+        (SocketAddr::V4(_), None) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        (SocketAddr::V6(_), None) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let local = SocketAddr::new(ip, options.source_port.unwrap_or(0));
+    let socket = match options.privacy {
+        true => bind_random_port(ip),
+        false => UdpSocket::bind(local),
+    }
+    .map_err(|e| {
+        NtpError::new(
+            "SOCKET_BIND",
+            format!("無法綁定 UDP socket {}: {}", local, e),
+        )
     })?;
 
-    let mut response = [0u8; NTP_PACKET_SIZE];
-    let (size, peer_addr) = socket.recv_from(&mut response).map_err(|e| NtpError {
-        success: false,
-        error: format!("無法接收回應: {}", e),
-        code: "RECV_ERROR".to_string(),
-    })?;
+    if let Some(interface) = &options.interface {
+        sockopt::bind_to_device(&socket, interface).map_err(|e| {
+            NtpError::new(
+                "SOCKET_BIND",
+                format!("無法綁定網路介面 {}: {}", interface, e),
+            )
+        })?;
+    }
+    if let Some(dscp) = options.dscp {
+        sockopt::set_dscp(&socket, target.is_ipv6(), dscp)
+            .map_err(|e| NtpError::new("SOCKET_BIND", format!("無法設定 DSCP: {}", e)))?;
+    }
 
-    let t4 = duration_to_unix_ms(SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| NtpError {
-        success: false,
-        error: format!("系統時間錯誤: {}", e),
-        code: "TIME_ERROR".to_string(),
-    })?);
+    socket
+        .set_read_timeout(Some(options.timeout()))
+        .map_err(|e| NtpError::new("SOCKET_TIMEOUT", format!("無法設定超時: {}", e)))?;
 
-    if size < NTP_PACKET_SIZE {
-        return Err(NtpError {
-            success: false,
-            error: format!("回應不完整: {} bytes", size),
-            code: "INCOMPLETE".to_string(),
-        });
+    Ok(socket)
+}
+
+/// 自行隨機選擇來源連接埠，不依賴系統的 ephemeral port 分配方式
+fn bind_random_port(ip: IpAddr) -> std::io::Result<UdpSocket> {
+    for _ in 0..PRIVACY_BIND_ATTEMPTS {
+        let port = rand::thread_rng().gen_range(PRIVACY_PORT_RANGE);
+        match UdpSocket::bind((ip, port)) {
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => continue,
+            result => return result,
+        }
     }
+    UdpSocket::bind((ip, 0))
+}
 
-    let li_vn_mode = response[0];
-    let leap = (li_vn_mode >> 6) & 0x03;
-    let version = (li_vn_mode >> 3) & 0x07;
-    let mode = li_vn_mode & 0x07;
-    let stratum = response[1];
-    let poll = response[2] as i8;
-    let precision = response[3] as i8;
+fn origin_matches(response: &[u8], origin: NtpTimestamp) -> bool {
+    NtpPacket::decode(response).is_ok_and(|packet| packet.origin == origin)
+}
 
-    let root_delay_raw = u32::from_be_bytes([response[4], response[5], response[6], response[7]]);
-    let root_dispersion_raw =
-        u32::from_be_bytes([response[8], response[9], response[10], response[11]]);
-    let root_delay = (root_delay_raw as f64 / 65536.0) * 1000.0;
-    let root_dispersion = (root_dispersion_raw as f64 / 65536.0) * 1000.0;
+/// 一次請求/回應交換的結果
+pub(crate) struct Exchange {
+    /// 請求中 Transmit Timestamp 的值，用於比對回應的 Origin Timestamp
+    pub origin: NtpTimestamp,
+    pub t1: NtpTimestamp,
+    pub t4: NtpTimestamp,
+    pub t1_source: TimestampSource,
+    pub t4_source: TimestampSource,
+    /// 交錯模式下使用前一次回應的 Receive Timestamp，None 時取自本次回應
+    pub t2: Option<NtpTimestamp>,
+    pub sample_mode: SampleMode,
+    pub size: usize,
+    pub peer_addr: SocketAddr,
+}
 
-    let ref_id_bytes = [response[12], response[13], response[14], response[15]];
-    let ref_id = decode_reference_id(ref_id_bytes, stratum);
+/// 發送請求並接收回應，可用時以核心時間戳作為 t1/t4，否則退回 user-space 時間
+pub(crate) fn exchange(
+    socket: &UdpSocket,
+    server_addr: SocketAddr,
+    packet: &[u8],
+    origin: NtpTimestamp,
+    response: &mut [u8],
+) -> Result<Exchange, NtpError> {
+    let mode = timestamping::enable(socket);
 
-    let (ref_sec, ref_frac) = extract_ntp_timestamp(&response, 16);
-    let ref_time = ntp_to_unix_ms(ref_sec, ref_frac);
+    // origin 在隱私模式下是 nonce，不能當作 t1
+    let user_t1 = NtpTimestamp::now();
+    socket
+        .send_to(packet, server_addr)
+        .map_err(|e| NtpError::new("SEND_ERROR", format!("無法發送請求: {}", e)))?;
 
-    let (org_sec, org_frac) = extract_ntp_timestamp(&response, 24);
-    let origin_time = ntp_to_unix_ms(org_sec, org_frac);
+    let (size, peer_addr, t4, t4_source) = timestamping::recv_from(socket, response, mode)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                NtpError::new("TIMEOUT", format!("等待 {} 回應逾時", server_addr))
+            }
+            _ => NtpError::new("RECV_ERROR", format!("無法接收回應: {}", e)),
+        })?;
 
-    let (t2_sec, t2_frac) = extract_ntp_timestamp(&response, 32);
-    let t2 = ntp_to_unix_ms(t2_sec, t2_frac);
+    // 回應抵達時發送時間戳早已進入 error queue，收到回應後才讀取以免阻塞等待
+    let (t1, t1_source) = match timestamping::tx_timestamp(socket, mode) {
+        Some(ts) => (ts, TimestampSource::Kernel),
+        None => (user_t1, TimestampSource::User),
+    };
 
-    let (t3_sec, t3_frac) = extract_ntp_timestamp(&response, 40);
-    let t3 = ntp_to_unix_ms(t3_sec, t3_frac);
+    Ok(Exchange {
+        origin,
+        t1,
+        t4,
+        t1_source,
+        t4_source,
+        t2: None,
+        sample_mode: SampleMode::Basic,
+        size,
+        peer_addr,
+    })
+}
 
-    if (origin_time - t1).abs() > 1.0 {
-        println!(
-            "[NTP] 警告: Origin Timestamp 不匹配 (sent={:.3}, recv={:.3})",
-            t1, origin_time
-        );
+/// 檢查回應是否為合法的伺服器回覆，寬鬆模式下只印出警告
+pub(crate) fn validate_response(
+    response: &[u8],
+    origin: NtpTimestamp,
+    sent_to: SocketAddr,
+    received_from: SocketAddr,
+    lenient: bool,
+) -> Result<(), NtpError> {
+    let packet = NtpPacket::decode(response)?;
+    let (leap, mode, stratum) = (packet.leap, packet.mode, packet.stratum);
+
+    let mut problems = Vec::new();
+
+    if mode != 4 {
+        problems.push(NtpError::new(
+            "INVALID_MODE",
+            format!("回應模式錯誤: mode={} (預期 4)", mode),
+        ));
+    }
+    if received_from.ip() != sent_to.ip() || received_from.port() != sent_to.port() {
+        problems.push(NtpError::new(
+            "SOURCE_MISMATCH",
+            format!("回應來源 {} 與查詢目標 {} 不符", received_from, sent_to),
+        ));
+    }
+    if stratum >= 16 {
+        problems.push(NtpError::new(
+            "INVALID_STRATUM",
+            format!("伺服器 stratum={}，尚未同步", stratum),
+        ));
+    }
+    if leap == 3 {
+        problems.push(NtpError::new(
+            "SERVER_UNSYNCHRONIZED",
+            "伺服器 leap indicator=3，時鐘未同步",
+        ));
+    }
+    if packet.receive.is_zero() || packet.transmit.is_zero() {
+        problems.push(NtpError::new(
+            "ZERO_TIMESTAMP",
+            "回應的 Receive/Transmit Timestamp 為零",
+        ));
+    }
+    if let Err(e) = extension::parse(response) {
+        problems.push(e);
+    }
+    if packet.origin != origin {
+        problems.push(NtpError::new(
+            "ORIGIN_MISMATCH",
+            format!(
+                "Origin Timestamp 不匹配 (sent={:.3}, recv={:.3})",
+                origin.to_unix_ms(),
+                packet.origin.to_unix_ms()
+            ),
+        ));
+    }
+
+    if lenient {
+        for problem in &problems {
+            println!("[NTP] 警告 (寬鬆模式): {} ({})", problem.error, problem.code);
+        }
+        return Ok(());
+    }
+
+    match problems.into_iter().next() {
+        Some(problem) => Err(problem),
+        None => Ok(()),
+    }
+}
+
+/// 解析 NTP 回應的 48 bytes 標頭並計算 offset/delay，擴充欄位由呼叫端自行處理
+pub(crate) fn parse_response(
+    server: &str,
+    exchange: &Exchange,
+    response: &[u8],
+) -> Result<NtpResult, NtpError> {
+    let packet = NtpPacket::decode(response)?;
+    let NtpPacket {
+        leap,
+        version,
+        mode,
+        stratum,
+        poll,
+        precision,
+        ..
+    } = packet;
+
+    let root_delay = packet.root_delay.to_ms();
+    let root_dispersion = packet.root_dispersion.to_ms();
+    let ref_id = packet.ref_id.decode(stratum);
+
+    let ref_time = packet.ref_time.to_unix_ms();
+    let origin_time = packet.origin.to_unix_ms();
+    let t2 = exchange.t2.unwrap_or(packet.receive);
+    let t3 = packet.transmit;
+
+    // stratum 0 為 Kiss-o'-Death，只有 origin 相符時才採信，避免偽造封包觸發退避
+    if stratum == 0 {
+        if packet.origin != exchange.origin {
+            return Err(NtpError::new(
+                "INVALID_KOD",
+                format!(
+                    "Kiss-o'-Death {} 的 Origin Timestamp 不符 (sent={:.3}, recv={:.3})，已忽略",
+                    ref_id,
+                    exchange.origin.to_unix_ms(),
+                    origin_time
+                ),
+            ));
+        }
+        return Err(kod::kiss_error(&ref_id));
     }
 
-    let offset = ((t2 - t1) + (t3 - t4)) / 2.0;
-    let delay = (t4 - t1) - (t3 - t2);
+    let (t1, t4, peer_addr) = (exchange.t1, exchange.t4, exchange.peer_addr);
+
+    // 以 i128 奈秒計算，避免 f64 毫秒損失精度
+    let (offset_ns, delay_ns) = offset_and_delay(t1, t2, t3, t4);
+    let offset = offset_ns as f64 / 1_000_000.0;
+    let delay = delay_ns as f64 / 1_000_000.0;
 
     Ok(NtpResult {
         success: true,
         server: server.to_string(),
         server_ip: peer_addr.ip().to_string(),
-        t1,
-        t2,
-        t3,
-        t4,
+        t1: t1.to_unix_ms(),
+        t2: t2.to_unix_ms(),
+        t3: t3.to_unix_ms(),
+        t4: t4.to_unix_ms(),
         offset,
         delay,
         leap,
@@ -210,20 +714,606 @@ pub fn query_ntp(server: &str) -> Result<NtpResult, NtpError> {
         precision,
         root_delay,
         root_dispersion,
+        root_distance: root_distance(root_delay, root_dispersion, delay, Some(precision)),
+        smearing: false,
         ref_id,
+        ref_id_raw: packet.ref_id.to_hex(),
+        ref_id_ambiguous: packet.ref_id.is_ambiguous(stratum),
         ref_time,
+        nts: false,
+        auth_key_id: None,
+        address_family: address_family_name(&peer_addr).to_string(),
+        t1_source: exchange.t1_source,
+        t4_source: exchange.t4_source,
+        sample_mode: exchange.sample_mode,
+        extension_fields: extension::parse(response)
+            .map(|parsed| parsed.fields.into_iter().map(|(_, f)| f).collect())
+            .unwrap_or_default(),
+        protocol: TimeProtocol::Ntp,
+        zone_unknown: false,
     })
 }
 
+/// 只提供單一伺服器時間的來源 (Roughtime、HTTP、RFC 868/867)，以該時間同時作為 t2/t3，
+/// root_dispersion 為來源本身的不確定範圍；這些來源沒有 stratum，以 16 表示而非代表 KoD 的 0
+pub(crate) fn single_time_result(
+    server: &str,
+    server_addr: SocketAddr,
+    (t1, t4): (NtpTimestamp, NtpTimestamp),
+    server_time: NtpTimestamp,
+    uncertainty_ms: f64,
+    protocol: TimeProtocol,
+) -> NtpResult {
+    let (offset_ns, delay_ns) = offset_and_delay(t1, server_time, server_time, t4);
+    let delay = delay_ns as f64 / 1_000_000.0;
+
+    NtpResult {
+        success: true,
+        server: server.to_string(),
+        server_ip: server_addr.ip().to_string(),
+        t1: t1.to_unix_ms(),
+        t2: server_time.to_unix_ms(),
+        t3: server_time.to_unix_ms(),
+        t4: t4.to_unix_ms(),
+        offset: offset_ns as f64 / 1_000_000.0,
+        delay,
+        leap: 0,
+        version: 0,
+        mode: 4,
+        stratum: UNSYNCHRONIZED_STRATUM,
+        poll: 0,
+        precision: 0,
+        root_delay: 0.0,
+        root_dispersion: uncertainty_ms,
+        root_distance: root_distance(0.0, uncertainty_ms, delay, None),
+        smearing: false,
+        ref_id: protocol.as_str().to_ascii_uppercase(),
+        ref_id_raw: String::new(),
+        ref_id_ambiguous: false,
+        ref_time: server_time.to_unix_ms(),
+        nts: false,
+        auth_key_id: None,
+        address_family: address_family_name(&server_addr).to_string(),
+        t1_source: TimestampSource::User,
+        t4_source: TimestampSource::User,
+        sample_mode: SampleMode::Basic,
+        extension_fields: Vec::new(),
+        protocol,
+        zone_unknown: false,
+    }
+}
+
+pub fn query_ntp(server: &str, options: &QueryOptions) -> Result<NtpResult, NtpError> {
+    options.validate()?;
+    kod::check_allowed(server)?;
+
+    let result = with_retries(server, options, || query_once(server, options))
+        .and_then(|result| finish_result(result, options));
+
+    kod::record_result(server, &result);
+
+    result
+}
+
+fn query_once(server: &str, options: &QueryOptions) -> Result<NtpResult, NtpError> {
+    if http::is_http_url(server) {
+        http::query_http(server, options)
+    } else if legacy::is_legacy_url(server) {
+        legacy::query_legacy(server, options)
+    } else if ptp::is_ptp_url(server) {
+        ptp::query_ptp(server, options)
+    } else if broadcast::is_broadcast_url(server) {
+        broadcast::query_broadcast(server, options)
+    } else if options.nts {
+        nts::query_nts(server, options)
+    } else {
+        ServerSpec::parse(server, NTP_PORT)
+            .and_then(|spec| spec.resolve(options.address_family))
+            .and_then(|server_addr| query_ntp_addr(server, server_addr, options))
+    }
+}
+
+/// 伺服器已儲存的預設選項，沒有時使用預設值；未指定選項的查詢與背景同步都會套用
+pub fn server_defaults(server: &str) -> QueryOptions {
+    match db::load_server_options(server) {
+        Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            println!("[NTP] {} 的預設選項無法解析，改用預設值: {}", server, e);
+            QueryOptions::default()
+        }),
+        Ok(None) => QueryOptions::default(),
+        Err(e) => {
+            println!("[NTP] 無法讀取 {} 的預設選項: {}", server, e);
+            QueryOptions::default()
+        }
+    }
+}
+
+/// 附加擴充欄位與 MAC，MAC 必須在所有擴充欄位之後
+pub(crate) fn finish_request(
+    packet: &mut Vec<u8>,
+    options: &QueryOptions,
+    key: Option<&auth::NtpKey>,
+) {
+    extension::write_fields(packet, &options.extension_fields, key.is_some());
+    if let Some(key) = key {
+        auth::sign_packet(packet, key);
+    }
+}
+
+pub(crate) fn load_key(options: &QueryOptions) -> Result<Option<auth::NtpKey>, NtpError> {
+    match options.key_id {
+        Some(key_id) => auth::find_key(options.keys_file.as_deref(), key_id).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// 檢查 MAC、解析並驗證回應
+pub(crate) fn process_response(
+    server: &str,
+    server_addr: SocketAddr,
+    exchange: &Exchange,
+    response: &[u8],
+    key: Option<&auth::NtpKey>,
+    options: &QueryOptions,
+) -> Result<NtpResult, NtpError> {
+    let packet = NtpPacket::decode(response)?;
+    // 隱私模式下 nonce 是辨識回應的唯一依據，寬鬆模式也不放行
+    if options.privacy && packet.origin != exchange.origin {
+        return Err(NtpError::new(
+            "ORIGIN_MISMATCH",
+            "回應的 Origin Timestamp 與請求的 nonce 不符",
+        ));
+    }
+    // MAC 必須在解讀 Kiss-o'-Death 之前驗證，否則偽造的 KoD 就能讓伺服器退避或停用
+    if let Some(key) = key {
+        auth::verify_packet(response, key).map_err(|e| unauthenticated_kiss(&packet, e))?;
+    }
+    let mut result = parse_response(server, exchange, response)?;
+    validate_response(
+        response,
+        exchange.origin,
+        server_addr,
+        exchange.peer_addr,
+        options.lenient,
+    )?;
+    result.auth_key_id = key.map(|key| key.id);
+
+    Ok(result)
+}
+
+/// 設定認證時，未通過認證的 Kiss-o'-Death 改回報為不會觸發退避的錯誤
+pub(crate) fn unauthenticated_kiss(packet: &NtpPacket, error: NtpError) -> NtpError {
+    if packet.stratum != 0 {
+        return error;
+    }
+    NtpError::new(
+        "UNAUTHENTICATED_KOD",
+        format!(
+            "已忽略未通過認證的 Kiss-o'-Death {}: {}",
+            packet.ref_id.decode(0),
+            error.error
+        ),
+    )
+}
+
+/// 回應的 Origin 等於前一次的本地接收時間時為交錯模式回應，
+/// 其 Transmit Timestamp 是伺服器前一次回應實際送出的時間，改以前一次交換的 t1/t2/t4 計算
+fn apply_interleaved(exchange: &mut Exchange, response: &[u8], prev: &InterleavedState) {
+    if origin_matches(response, exchange.origin) || !origin_matches(response, prev.t4) {
+        return;
+    }
+    exchange.origin = prev.t4;
+    exchange.t1 = prev.t1;
+    exchange.t1_source = prev.t1_source;
+    exchange.t2 = Some(prev.t2);
+    exchange.t4 = prev.t4;
+    exchange.t4_source = prev.t4_source;
+    exchange.sample_mode = SampleMode::Interleaved;
+}
+
+fn update_interleaved(
+    server_addr: SocketAddr,
+    current: InterleavedState,
+    sent_interleaved: bool,
+    mode: SampleMode,
+) {
+    let mut states = INTERLEAVED_STATES.lock().unwrap();
+    let basic_replies = match states.get(&server_addr) {
+        Some(prev) if sent_interleaved && mode == SampleMode::Basic => prev.basic_replies + 1,
+        Some(prev) if !sent_interleaved => prev.basic_replies,
+        _ => 0,
+    };
+    if sent_interleaved && basic_replies == INTERLEAVED_MAX_BASIC_REPLIES {
+        println!("[NTP] {} 不支援交錯模式，改用基本模式", server_addr);
+    }
+    states.insert(
+        server_addr,
+        InterleavedState {
+            basic_replies,
+            ..current
+        },
+    );
+}
+
+/// 對已解析的單一位址進行查詢
+pub fn query_ntp_addr(
+    server: &str,
+    server_addr: SocketAddr,
+    options: &QueryOptions,
+) -> Result<NtpResult, NtpError> {
+    let key = load_key(options)?;
+
+    let socket = bind_socket(&server_addr, options)?;
+
+    let prev = match options.interleaved {
+        true => INTERLEAVED_STATES
+            .lock()
+            .unwrap()
+            .get(&server_addr)
+            .copied(),
+        false => None,
+    };
+    let prev = prev.filter(|p| p.supported());
+
+    let origin = options.request_origin();
+    let mut request = NtpPacket::request(options.version(), origin);
+    // 交錯模式請求：Origin 帶前一次回應的 Receive Timestamp，Receive 帶前一次的本地接收時間
+    if let Some(ref prev) = prev {
+        request.origin = prev.t2;
+        request.receive = prev.t4;
+    }
+    let mut ntp_packet = request.encode().to_vec();
+    finish_request(&mut ntp_packet, options, key.as_ref());
+
+    let mut response = [0u8; NTP_MAX_RESPONSE_SIZE];
+    let mut exchange = exchange(&socket, server_addr, &ntp_packet, origin, &mut response)?;
+    let response = &response[..exchange.size];
+
+    let current = NtpPacket::decode(response)
+        .ok()
+        .map(|packet| InterleavedState {
+            t1: exchange.t1,
+            t1_source: exchange.t1_source,
+            t2: packet.receive,
+            t4: exchange.t4,
+            t4_source: exchange.t4_source,
+            basic_replies: 0,
+        });
+    if let (Some(ref prev), Some(_)) = (prev, current) {
+        apply_interleaved(&mut exchange, response, prev);
+    }
+
+    let result = process_response(
+        server,
+        server_addr,
+        &exchange,
+        response,
+        key.as_ref(),
+        options,
+    )?;
+    if let (true, Some(current)) = (options.interleaved, current) {
+        update_interleaved(server_addr, current, prev.is_some(), result.sample_mode);
+    }
+
+    Ok(result)
+}
+
+const CONTROL_HEADER_SIZE: usize = 12;
+/// ntpq 預設以 NTPv2 送出控制訊息，相容性最好
+const CONTROL_VERSION: u8 = 2;
+const CONTROL_MAX_FRAGMENTS: usize = 24;
+const CONTROL_OP_READSTAT: u8 = 1;
+const CONTROL_OP_READVAR: u8 = 2;
+const CONTROL_RESPONSE: u8 = 0x80;
+const CONTROL_ERROR: u8 = 0x40;
+const CONTROL_MORE: u8 = 0x20;
+
+/// mode 6 peer 狀態字組中 select 欄位的名稱，與 ntpq 相同
+const PEER_SELECTIONS: [&str; 8] = [
+    "reject",
+    "falsetick",
+    "excess",
+    "outlier",
+    "candidate",
+    "backup",
+    "sys.peer",
+    "pps.peer",
+];
+
+/// 系統狀態字組中 clock source 欄位的名稱
+const CLOCK_SOURCES: [&str; 10] = [
+    "unspec",
+    "pps",
+    "lf_radio",
+    "hf_radio",
+    "uhf_radio",
+    "local",
+    "ntp",
+    "other",
+    "wristwatch",
+    "telephone",
+];
+
+/// READSTAT 回傳的 association 與其狀態
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerStatus {
+    pub association_id: u16,
+    pub status: u16,
+    pub configured: bool,
+    pub reachable: bool,
+    pub selection: String,
+    /// 只有查詢了該 association 的 READVAR 才會填入
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variables: Option<BTreeMap<String, String>>,
+}
+
+impl PeerStatus {
+    fn new(association_id: u16, status: u16) -> Self {
+        PeerStatus {
+            association_id,
+            status,
+            configured: status & 0x8000 != 0,
+            reachable: status & 0x1000 != 0,
+            selection: PEER_SELECTIONS[((status >> 8) & 0x07) as usize].to_string(),
+            variables: None,
+        }
+    }
+
+    fn is_sys_peer(&self) -> bool {
+        (self.status >> 8) & 0x07 == 6
+    }
+}
+
+/// 以 mode 6 讀取的伺服器自身狀態 (同 `ntpq -c rv -c peers`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerInspection {
+    pub success: bool,
+    pub server: String,
+    pub server_ip: String,
+    pub leap: u8,
+    pub clock_source: String,
+    /// 伺服器目前的同步來源 (sys.peer 的位址，或 refid)
+    pub sync_source: Option<String>,
+    /// 伺服器自身的系統偏差 (ms)
+    pub offset: Option<f64>,
+    /// 伺服器自身的系統抖動 (ms)
+    pub jitter: Option<f64>,
+    pub system: BTreeMap<String, String>,
+    pub peers: Vec<PeerStatus>,
+}
+
+fn build_control_request(
+    opcode: u8,
+    sequence: u16,
+    association_id: u16,
+) -> [u8; CONTROL_HEADER_SIZE] {
+    let mut packet = [0u8; CONTROL_HEADER_SIZE];
+    packet[0] = CONTROL_VERSION << 3 | 6;
+    packet[1] = opcode;
+    packet[2..4].copy_from_slice(&sequence.to_be_bytes());
+    packet[6..8].copy_from_slice(&association_id.to_be_bytes());
+    packet
+}
+
+fn control_error_message(code: u8) -> &'static str {
+    match code {
+        1 => "未指定的錯誤",
+        2 => "認證失敗",
+        3 => "請求格式錯誤",
+        4 => "不支援的操作碼",
+        5 => "未知的 association",
+        6 => "未知的變數",
+        7 => "無效的變數值",
+        8 => "伺服器禁止此查詢",
+        _ => "未知的錯誤",
+    }
+}
+
+/// 送出一個 mode 6 請求並重組分段回應，回傳 (狀態字組, 資料)
+fn control_request(
+    socket: &UdpSocket,
+    server_addr: SocketAddr,
+    opcode: u8,
+    association_id: u16,
+) -> Result<(u16, Vec<u8>), NtpError> {
+    let sequence = rand::random::<u16>();
+    socket
+        .send_to(
+            &build_control_request(opcode, sequence, association_id),
+            server_addr,
+        )
+        .map_err(|e| NtpError::new("SEND_ERROR", format!("無法發送控制請求: {}", e)))?;
+
+    // 依 offset 排序的分段，收到最後一段且中間沒有缺漏即完成
+    let mut fragments: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+    let mut total: Option<usize> = None;
+    let mut buf = [0u8; NTP_MAX_RESPONSE_SIZE];
+
+    loop {
+        let (size, peer) = socket
+            .recv_from(&mut buf)
+            .map_err(|e| match fragments.is_empty() {
+                true => NtpError::new(
+                    "RECV_ERROR",
+                    format!("未收到控制回應 (伺服器可能停用 mode 6 查詢): {}", e),
+                ),
+                // 缺少中間分段或最後一段誤設 more 旗標時，不回傳不完整的資料
+                false => NtpError::new(
+                    "INCOMPLETE",
+                    format!("控制回應缺少分段 (已收到 {} 段): {}", fragments.len(), e),
+                ),
+            })?;
+        let packet = &buf[..size];
+        if peer.ip() != server_addr.ip()
+            || size < CONTROL_HEADER_SIZE
+            || packet[0] & 0x07 != 6
+            || packet[1] & CONTROL_RESPONSE == 0
+            || packet[1] & 0x1F != opcode
+            || u16::from_be_bytes([packet[2], packet[3]]) != sequence
+            || u16::from_be_bytes([packet[6], packet[7]]) != association_id
+        {
+            continue;
+        }
+
+        let status = u16::from_be_bytes([packet[4], packet[5]]);
+        if packet[1] & CONTROL_ERROR != 0 {
+            let code = (status >> 8) as u8;
+            return Err(NtpError::new(
+                "CONTROL_ERROR",
+                format!("控制查詢失敗: {} ({})", control_error_message(code), code),
+            ));
+        }
+
+        let offset = u16::from_be_bytes([packet[8], packet[9]]) as usize;
+        let count = u16::from_be_bytes([packet[10], packet[11]]) as usize;
+        if CONTROL_HEADER_SIZE + count > size {
+            return Err(NtpError::new("INVALID_RESPONSE", "控制回應長度不符"));
+        }
+        if packet[1] & CONTROL_MORE == 0 {
+            total = Some(offset + count);
+        }
+        fragments.insert(
+            offset,
+            packet[CONTROL_HEADER_SIZE..CONTROL_HEADER_SIZE + count].to_vec(),
+        );
+        if fragments.len() > CONTROL_MAX_FRAGMENTS {
+            return Err(NtpError::new("INVALID_RESPONSE", "控制回應分段過多"));
+        }
+
+        let Some(total) = total else {
+            continue;
+        };
+        let mut data = Vec::with_capacity(total);
+        for (offset, fragment) in &fragments {
+            if *offset != data.len() {
+                break;
+            }
+            data.extend_from_slice(fragment);
+        }
+        if data.len() == total {
+            return Ok((status, data));
+        }
+    }
+}
+
+/// 解析 `name=value, name="quoted, value"` 格式的變數列表
+fn parse_control_variables(data: &[u8]) -> BTreeMap<String, String> {
+    let text = String::from_utf8_lossy(data);
+    let mut variables = BTreeMap::new();
+    let mut entry = String::new();
+    let mut quoted = false;
+
+    for c in text.chars().chain(std::iter::once(',')) {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                let item = std::mem::take(&mut entry);
+                let item = item.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+                if item.is_empty() {
+                    continue;
+                }
+                let (name, value) = item.split_once('=').unwrap_or((item, ""));
+                variables.insert(name.trim().to_string(), value.trim().to_string());
+            }
+            _ => entry.push(c),
+        }
+    }
+    variables
+}
+
+fn variable_ms(variables: &BTreeMap<String, String>, name: &str) -> Option<f64> {
+    variables.get(name).and_then(|value| value.parse().ok())
+}
+
+/// 以 READSTAT/READVAR 讀取伺服器的系統變數與 peer 列表
+pub fn inspect_server(
+    server: &str,
+    options: &QueryOptions,
+    include_peers: bool,
+) -> Result<ServerInspection, NtpError> {
+    let server_addr = ServerSpec::parse(server, NTP_PORT)?.resolve(options.address_family)?;
+    let socket = bind_socket(&server_addr, options)?;
+
+    let (status, data) = control_request(&socket, server_addr, CONTROL_OP_READSTAT, 0)?;
+    let mut peers: Vec<PeerStatus> = data
+        .chunks_exact(4)
+        .map(|c| {
+            PeerStatus::new(
+                u16::from_be_bytes([c[0], c[1]]),
+                u16::from_be_bytes([c[2], c[3]]),
+            )
+        })
+        .collect();
+
+    let (_, data) = control_request(&socket, server_addr, CONTROL_OP_READVAR, 0)?;
+    let system = parse_control_variables(&data);
+
+    for peer in peers.iter_mut() {
+        if include_peers || peer.is_sys_peer() {
+            let (_, data) = control_request(
+                &socket,
+                server_addr,
+                CONTROL_OP_READVAR,
+                peer.association_id,
+            )?;
+            peer.variables = Some(parse_control_variables(&data));
+        }
+    }
+
+    let sync_source = peers
+        .iter()
+        .find(|peer| peer.is_sys_peer())
+        .and_then(|peer| peer.variables.as_ref()?.get("srcadr").cloned())
+        .or_else(|| system.get("refid").cloned());
+
+    Ok(ServerInspection {
+        success: true,
+        server: server.to_string(),
+        server_ip: server_addr.ip().to_string(),
+        leap: (status >> 14) as u8,
+        clock_source: CLOCK_SOURCES
+            .get(((status >> 8) & 0x3F) as usize)
+            .unwrap_or(&"unknown")
+            .to_string(),
+        sync_source,
+        offset: variable_ms(&system, "offset"),
+        jitter: variable_ms(&system, "sys_jitter"),
+        system,
+        peers,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressQueryResult {
+    pub address: String,
+    pub address_family: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<NtpResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<NtpError>,
+}
+
 #[tauri::command]
-pub async fn query_ntp_udp(server: String) -> Result<String, String> {
+pub async fn query_ntp_udp(
+    server: String,
+    options: Option<QueryOptions>,
+) -> Result<String, String> {
     println!("[NTP] 查詢 {}", server);
 
-    match query_ntp(&server) {
+    let options = options.unwrap_or_else(|| server_defaults(&server));
+    match engine::query(&server, &options, options.timeout()).await {
         Ok(result) => {
             println!(
-                "[NTP] ✓ {} | offset={}ms delay={}ms stratum={}",
-                result.server_ip, result.offset, result.delay, result.stratum
+                "[NTP] ✓ {} ({}) | offset={}ms delay={}ms stratum={} nts={} mode={:?} timestamps={:?}/{:?}",
+                result.server_ip,
+                result.address_family,
+                result.offset,
+                result.delay,
+                result.stratum,
+                result.nts,
+                result.sample_mode,
+                result.t1_source,
+                result.t4_source
             );
             serde_json::to_string(&result).map_err(|e| e.to_string())
         }
@@ -233,3 +1323,504 @@ pub async fn query_ntp_udp(server: String) -> Result<String, String> {
         }
     }
 }
+
+#[tauri::command]
+pub async fn query_ntp_udp_all(
+    server: String,
+    options: Option<QueryOptions>,
+) -> Result<String, String> {
+    println!("[NTP] 查詢所有位址 {}", server);
+
+    let options = options.unwrap_or_else(|| server_defaults(&server));
+    match engine::query_all_addresses(&server, &options, options.timeout()).await {
+        Ok(results) => {
+            for entry in &results {
+                match (&entry.result, &entry.error) {
+                    (Some(r), _) => println!(
+                        "[NTP] ✓ {} | offset={}ms delay={}ms stratum={}",
+                        entry.address, r.offset, r.delay, r.stratum
+                    ),
+                    (_, Some(e)) => println!("[NTP] ✗ {} | {} ({})", entry.address, e.error, e.code),
+                    _ => {}
+                }
+            }
+            serde_json::to_string(&results).map_err(|e| e.to_string())
+        }
+        Err(error) => {
+            println!("[NTP] ✗ {} ({})", error.error, error.code);
+            serde_json::to_string(&error).map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn inspect_ntp_server(
+    server: String,
+    options: Option<QueryOptions>,
+    include_peers: Option<bool>,
+) -> Result<String, String> {
+    println!("[NTP] mode 6 查詢 {}", server);
+
+    let options = options.unwrap_or_else(|| server_defaults(&server));
+    let include_peers = include_peers.unwrap_or(false);
+    let task =
+        tokio::task::spawn_blocking(move || inspect_server(&server, &options, include_peers));
+    match task.await.map_err(|e| e.to_string())? {
+        Ok(inspection) => {
+            println!(
+                "[NTP] ✓ {} | source={} offset={:?}ms jitter={:?}ms peers={}",
+                inspection.server_ip,
+                inspection.sync_source.as_deref().unwrap_or("-"),
+                inspection.offset,
+                inspection.jitter,
+                inspection.peers.len()
+            );
+            serde_json::to_string(&inspection).map_err(|e| e.to_string())
+        }
+        Err(error) => {
+            println!("[NTP] ✗ {} ({})", error.error, error.code);
+            serde_json::to_string(&error).map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn get_server_options(server: String) -> Result<String, String> {
+    serde_json::to_string(&server_defaults(&server)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn set_server_options(server: String, options: QueryOptions) -> Result<String, String> {
+    if let Err(error) = options.validate() {
+        println!("[NTP] ✗ {} ({})", error.error, error.code);
+        return serde_json::to_string(&error).map_err(|e| e.to_string());
+    }
+
+    let json = serde_json::to_string(&options).map_err(|e| e.to_string())?;
+    db::save_server_options(&server, &json).map_err(|e| e.to_string())?;
+    println!("[NTP] 已儲存 {} 的預設選項", server);
+    Ok(json)
+}
+
+#[tauri::command]
+pub async fn delete_server_options(server: String) -> Result<bool, String> {
+    db::delete_server_options(&server).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::auth::{KeyAlgorithm, NtpKey};
+    use crate::core::packet::ReferenceId;
+
+    fn kiss_exchange(kiss_code: &[u8; 4]) -> (Exchange, Vec<u8>) {
+        let origin = NtpTimestamp::random();
+        let server_addr: SocketAddr = "192.0.2.1:123".parse().unwrap();
+        let packet = NtpPacket {
+            leap: 3,
+            version: 4,
+            mode: 4,
+            ref_id: ReferenceId(*kiss_code),
+            origin,
+            ..Default::default()
+        };
+        let exchange = Exchange {
+            origin,
+            t1: NtpTimestamp::now(),
+            t4: NtpTimestamp::now(),
+            t1_source: TimestampSource::User,
+            t4_source: TimestampSource::User,
+            t2: None,
+            sample_mode: SampleMode::Basic,
+            size: NTP_PACKET_SIZE,
+            peer_addr: server_addr,
+        };
+        (exchange, packet.encode().to_vec())
+    }
+
+    fn test_key() -> NtpKey {
+        NtpKey {
+            id: 7,
+            algorithm: KeyAlgorithm::Md5,
+            key: b"secret".to_vec(),
+        }
+    }
+
+    #[test]
+    fn unauthenticated_kiss_is_ignored_when_key_configured() {
+        let (exchange, response) = kiss_exchange(b"DENY");
+        let key = test_key();
+        let result = process_response(
+            "kod-forged.test",
+            exchange.peer_addr,
+            &exchange,
+            &response,
+            Some(&key),
+            &QueryOptions::default(),
+        );
+        assert_eq!(result.as_ref().unwrap_err().code, "UNAUTHENTICATED_KOD");
+
+        kod::record_result("kod-forged.test", &result);
+        assert!(!kod::is_disabled("kod-forged.test"));
+    }
+
+    #[test]
+    fn authenticated_kiss_is_honoured() {
+        let (exchange, mut response) = kiss_exchange(b"DENY");
+        let key = test_key();
+        auth::sign_packet(&mut response, &key);
+        let result = process_response(
+            "kod-signed.test",
+            exchange.peer_addr,
+            &exchange,
+            &response,
+            Some(&key),
+            &QueryOptions::default(),
+        );
+        assert_eq!(result.unwrap_err().code, "KOD_DENY");
+    }
+
+    #[test]
+    fn kiss_without_authentication_configured() {
+        let (exchange, response) = kiss_exchange(b"RATE");
+        let result = process_response(
+            "kod-plain.test",
+            exchange.peer_addr,
+            &exchange,
+            &response,
+            None,
+            &QueryOptions::default(),
+        );
+        assert_eq!(result.unwrap_err().code, "KOD_RATE");
+    }
+
+    #[test]
+    fn silent_server_times_out() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        let mut response = [0u8; NTP_PACKET_SIZE];
+        let result = exchange(
+            &socket,
+            silent.local_addr().unwrap(),
+            &[0x23; NTP_PACKET_SIZE],
+            NtpTimestamp::random(),
+            &mut response,
+        );
+        assert_eq!(result.err().unwrap().code, "TIMEOUT");
+    }
+
+    /// 本機 NTP 替身，回應 requests 次後回傳收到的請求；interleaved 時依 RFC 9769 回應交錯模式，
+    /// 每次回應的實際送出時間比封包內的 Transmit Timestamp 晚 7ms
+    fn serve_interleaved(
+        requests: usize,
+        interleaved: bool,
+    ) -> (SocketAddr, std::thread::JoinHandle<Vec<NtpPacket>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let handle = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            let mut last_rx = NtpTimestamp::default();
+            let mut last_tx = NtpTimestamp::default();
+            let mut buf = [0u8; NTP_MAX_RESPONSE_SIZE];
+            for _ in 0..requests {
+                let (size, peer) = socket.recv_from(&mut buf).unwrap();
+                let rx = NtpTimestamp::now();
+                let request = NtpPacket::decode(&buf[..size]).unwrap();
+                let now = NtpTimestamp::now();
+                let mut response = NtpPacket {
+                    version: 4,
+                    mode: 4,
+                    stratum: 2,
+                    ref_id: ReferenceId(*b"\x7f\x00\x00\x01"),
+                    ref_time: now,
+                    origin: request.transmit,
+                    receive: rx,
+                    transmit: now,
+                    ..Default::default()
+                };
+                if interleaved && !last_rx.is_zero() && request.origin == last_rx {
+                    response.origin = request.receive;
+                    response.transmit = last_tx;
+                }
+                socket.send_to(&response.encode(), peer).unwrap();
+                last_rx = rx;
+                last_tx = NtpTimestamp::from_unix_nanos(
+                    now.to_unix_nanos(crate::core::timestamp::DEFAULT_PIVOT_UNIX_SECS) + 7_000_000,
+                );
+                received.push(request);
+            }
+            received
+        });
+        (addr, handle)
+    }
+
+    fn interleaved_options() -> QueryOptions {
+        QueryOptions {
+            interleaved: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn interleaved_reply_uses_previous_transmit() {
+        let (addr, handle) = serve_interleaved(2, true);
+        let options = interleaved_options();
+
+        let first = query_ntp_addr("localhost", addr, &options).unwrap();
+        assert_eq!(first.sample_mode, SampleMode::Basic);
+        let second = query_ntp_addr("localhost", addr, &options).unwrap();
+        assert_eq!(second.sample_mode, SampleMode::Interleaved);
+
+        // 交錯模式樣本由前一次交換的 t1/t2/t4 與伺服器實際送出時間組成
+        assert_eq!(second.t1, first.t1);
+        assert_eq!(second.t2, first.t2);
+        assert_eq!(second.t4, first.t4);
+        assert!((second.t3 - (first.t3 + 7.0)).abs() < 0.001);
+        assert!((second.offset - (first.offset + 3.5)).abs() < 0.01);
+
+        let requests = handle.join().unwrap();
+        assert!(requests[0].origin.is_zero());
+        assert!(!requests[1].origin.is_zero());
+        assert!(!requests[1].receive.is_zero());
+    }
+
+    #[test]
+    fn interleaved_falls_back_to_basic() {
+        let requests = INTERLEAVED_MAX_BASIC_REPLIES as usize + 2;
+        let (addr, handle) = serve_interleaved(requests, false);
+        let options = interleaved_options();
+
+        for _ in 0..requests {
+            let result = query_ntp_addr("localhost", addr, &options).unwrap();
+            assert_eq!(result.sample_mode, SampleMode::Basic);
+        }
+
+        // 連續收到基本模式回應後不再送出交錯模式請求
+        let requests = handle.join().unwrap();
+        let interleaved: Vec<bool> = requests.iter().map(|r| !r.origin.is_zero()).collect();
+        assert_eq!(interleaved, vec![false, true, true, true, true, false]);
+        assert!(!INTERLEAVED_STATES.lock().unwrap()[&addr].supported());
+    }
+
+    /// mode 6 替身的分段方式
+    #[derive(Clone, Copy)]
+    enum Fragments {
+        /// 由後往前送出
+        Reversed,
+        /// 少送中間一段
+        DropMiddle,
+        /// 最後一段也設定 more 旗標
+        StrayMore,
+    }
+
+    const SYSTEM_VARIABLES: &str =
+        "version=\"ntpd 4.2.8p15, built for test\", processor=\"x86_64\", \
+        leap=00, stratum=2, precision=-24, rootdelay=1.234, rootdisp=5.678, refid=192.0.2.10, \
+        offset=-0.125, frequency=-12.345, sys_jitter=0.042, clk_jitter=0.010";
+
+    /// 依操作碼與 association 回傳 (狀態字組, 資料)：leap 0、clock source ntp，
+    /// association 1 為 sys.peer，association 2 為 candidate
+    fn control_reply(opcode: u8, association_id: u16) -> (u16, Vec<u8>) {
+        match (opcode, association_id) {
+            (CONTROL_OP_READSTAT, _) => (0x0618, vec![0, 1, 0x96, 0x14, 0, 2, 0x94, 0x24]),
+            (_, 0) => (0x0618, SYSTEM_VARIABLES.as_bytes().to_vec()),
+            (_, 1) => (
+                0x9614,
+                b"srcadr=192.0.2.10, srcport=123, offset=-0.120".to_vec(),
+            ),
+            _ => (0x9424, b"srcadr=192.0.2.20".to_vec()),
+        }
+    }
+
+    /// 回答 requests 個 mode 6 請求，每個回應切成 48 bytes 的分段
+    fn serve_control(requests: usize, plan: Fragments) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; NTP_MAX_RESPONSE_SIZE];
+            for _ in 0..requests {
+                let (_, peer) = socket.recv_from(&mut buf).unwrap();
+                let opcode = buf[1] & 0x1F;
+                let association_id = u16::from_be_bytes([buf[6], buf[7]]);
+                let (status, data) = control_reply(opcode, association_id);
+
+                let chunks: Vec<(usize, &[u8])> = data
+                    .chunks(48)
+                    .enumerate()
+                    .map(|(i, chunk)| (i * 48, chunk))
+                    .collect();
+                let last = chunks.len() - 1;
+                let mut packets: Vec<Vec<u8>> = chunks
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| !matches!(plan, Fragments::DropMiddle) || *i != last / 2)
+                    .map(|(i, (offset, chunk))| {
+                        let more = i != last || matches!(plan, Fragments::StrayMore);
+                        let mut packet = buf[..CONTROL_HEADER_SIZE].to_vec();
+                        packet[1] = CONTROL_RESPONSE | if more { CONTROL_MORE } else { 0 } | opcode;
+                        packet[4..6].copy_from_slice(&status.to_be_bytes());
+                        packet[8..10].copy_from_slice(&(*offset as u16).to_be_bytes());
+                        packet[10..12].copy_from_slice(&(chunk.len() as u16).to_be_bytes());
+                        packet.extend_from_slice(chunk);
+                        packet
+                    })
+                    .collect();
+                if matches!(plan, Fragments::Reversed) {
+                    packets.reverse();
+                }
+                for packet in packets {
+                    socket.send_to(&packet, peer).unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    fn control_socket() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        socket
+    }
+
+    #[test]
+    fn inspect_server_reassembles_fragments() {
+        // READSTAT、系統 READVAR、sys.peer 的 READVAR
+        let addr = serve_control(3, Fragments::Reversed);
+        let inspection =
+            inspect_server(&addr.to_string(), &QueryOptions::default(), false).unwrap();
+
+        assert_eq!(inspection.leap, 0);
+        assert_eq!(inspection.clock_source, "ntp");
+        assert_eq!(inspection.sync_source.as_deref(), Some("192.0.2.10"));
+        assert_eq!(inspection.offset, Some(-0.125));
+        assert_eq!(inspection.jitter, Some(0.042));
+        assert_eq!(
+            inspection.system["version"],
+            "ntpd 4.2.8p15, built for test"
+        );
+        assert_eq!(inspection.system["clk_jitter"], "0.010");
+
+        let peers = &inspection.peers;
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].association_id, 1);
+        assert!(peers[0].configured && peers[0].reachable);
+        assert_eq!(peers[0].selection, "sys.peer");
+        assert_eq!(peers[0].variables.as_ref().unwrap()["srcport"], "123");
+        assert_eq!(peers[1].association_id, 2);
+        assert_eq!(peers[1].selection, "candidate");
+        assert!(peers[1].variables.is_none());
+    }
+
+    #[test]
+    fn missing_fragment_is_incomplete() {
+        let addr = serve_control(1, Fragments::DropMiddle);
+        let error = control_request(&control_socket(), addr, CONTROL_OP_READVAR, 0).unwrap_err();
+        assert_eq!(error.code, "INCOMPLETE");
+    }
+
+    #[test]
+    fn stray_more_bit_is_incomplete() {
+        let addr = serve_control(1, Fragments::StrayMore);
+        let error = control_request(&control_socket(), addr, CONTROL_OP_READVAR, 0).unwrap_err();
+        assert_eq!(error.code, "INCOMPLETE");
+    }
+
+    #[test]
+    fn quoted_values_keep_commas() {
+        let variables = parse_control_variables(
+            b"version=\"ntpd 4.2.8, (1)\", ,refid=GPS,\r\nflag\0\0, empty=",
+        );
+        assert_eq!(variables["version"], "ntpd 4.2.8, (1)");
+        assert_eq!(variables["refid"], "GPS");
+        assert_eq!(variables["flag"], "");
+        assert_eq!(variables["empty"], "");
+        assert_eq!(variables.len(), 4);
+    }
+
+    /// 回答 requests 個請求並回傳收到的請求與來源連接埠，mangle_origin 時帶回錯誤的 Origin
+    fn serve_privacy(
+        requests: usize,
+        mangle_origin: bool,
+    ) -> (SocketAddr, std::thread::JoinHandle<Vec<(NtpPacket, u16)>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let handle = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            let mut buf = [0u8; NTP_MAX_RESPONSE_SIZE];
+            for _ in 0..requests {
+                let (size, peer) = socket.recv_from(&mut buf).unwrap();
+                let request = NtpPacket::decode(&buf[..size]).unwrap();
+                let origin = match mangle_origin {
+                    true => NtpTimestamp::random(),
+                    false => request.transmit,
+                };
+                let now = NtpTimestamp::now();
+                let response = NtpPacket {
+                    version: 4,
+                    mode: 4,
+                    stratum: 2,
+                    ref_id: ReferenceId(*b"\x7f\x00\x00\x01"),
+                    ref_time: now,
+                    origin,
+                    receive: now,
+                    transmit: now,
+                    ..Default::default()
+                };
+                socket.send_to(&response.encode(), peer).unwrap();
+                received.push((request, peer.port()));
+            }
+            received
+        });
+        (addr, handle)
+    }
+
+    fn privacy_options(lenient: bool) -> QueryOptions {
+        QueryOptions {
+            privacy: true,
+            lenient,
+            timeout_ms: Some(500),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn privacy_rejects_wrong_origin_even_when_lenient() {
+        let (addr, handle) = serve_privacy(1, true);
+        let error = query_ntp_addr("privacy", addr, &privacy_options(true)).unwrap_err();
+        assert_eq!(error.code, "ORIGIN_MISMATCH");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn privacy_request_carries_no_identifying_fields() {
+        let (addr, handle) = serve_privacy(2, false);
+        for _ in 0..2 {
+            query_ntp_addr("privacy", addr, &privacy_options(false)).unwrap();
+        }
+
+        let received = handle.join().unwrap();
+        let now =
+            NtpTimestamp::now().to_unix_nanos(crate::core::timestamp::DEFAULT_PIVOT_UNIX_SECS);
+        for (request, port) in &received {
+            assert_eq!(request.mode, 3);
+            assert_eq!((request.leap, request.stratum, request.poll), (0, 0, 0));
+            assert_eq!(request.precision, 0);
+            assert_eq!(request.ref_id, ReferenceId::default());
+            assert!(request.ref_time.is_zero());
+            assert!(request.origin.is_zero());
+            assert!(request.receive.is_zero());
+            // transmit 是隨機 nonce 而不是本地時間
+            let transmit = request
+                .transmit
+                .to_unix_nanos(crate::core::timestamp::DEFAULT_PIVOT_UNIX_SECS);
+            assert!((transmit - now).abs() > 60_000_000_000);
+            assert!(PRIVACY_PORT_RANGE.contains(port));
+        }
+        assert_ne!(received[0].0.transmit, received[1].0.transmit);
+    }
+}
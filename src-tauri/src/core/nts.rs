@@ -0,0 +1,839 @@
+use aes::Aes128;
+use cmac::{Cmac, Mac};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use rand::RngCore;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+
+use crate::core::extension;
+use crate::core::ntp::{self, NtpError, NtpResult, QueryOptions, ServerSpec};
+use crate::core::packet::NtpPacket;
+
+const NTS_KE_PORT: u16 = 4460;
+const NTS_KE_ALPN: &[u8] = b"ntske/1";
+const NTS_EXPORTER_LABEL: &[u8] = b"EXPORTER-network-time-security";
+const NTS_PROTOCOL_NTPV4: u16 = 0;
+const AEAD_AES_SIV_CMAC_256: u16 = 15;
+const NTS_MAX_COOKIES: usize = 8;
+const NTS_NONCE_LEN: usize = 16;
+const NTS_UNIQUE_ID_LEN: usize = 32;
+const NTS_MAX_RESPONSE_SIZE: usize = 2048;
+
+const KE_END_OF_MESSAGE: u16 = 0;
+const KE_NEXT_PROTOCOL: u16 = 1;
+const KE_ERROR: u16 = 2;
+const KE_WARNING: u16 = 3;
+const KE_AEAD_ALGORITHM: u16 = 4;
+const KE_NEW_COOKIE: u16 = 5;
+const KE_SERVER: u16 = 6;
+const KE_PORT: u16 = 7;
+const KE_CRITICAL: u16 = 0x8000;
+
+const EF_UNIQUE_IDENTIFIER: u16 = 0x0104;
+const EF_NTS_COOKIE: u16 = 0x0204;
+const EF_NTS_COOKIE_PLACEHOLDER: u16 = 0x0304;
+const EF_NTS_AUTHENTICATOR: u16 = 0x0404;
+
+struct NtsSession {
+    ntp_host: String,
+    ntp_port: u16,
+    c2s_key: [u8; 32],
+    s2c_key: [u8; 32],
+    cookies: Vec<Vec<u8>>,
+}
+
+lazy_static::lazy_static! {
+    static ref NTS_SESSIONS: Mutex<HashMap<String, NtsSession>> = Mutex::new(HashMap::new());
+}
+
+fn tls_config(options: &QueryOptions) -> Result<Arc<rustls::ClientConfig>, NtpError> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+    if let Some(ref ca_file) = options.nts_ca_file {
+        let certs = CertificateDer::pem_file_iter(ca_file)
+            .map_err(|e| NtpError::new("NTS_KE_TLS", format!("無法讀取 CA 憑證: {}", e)))?;
+        for cert in certs {
+            let cert = cert
+                .map_err(|e| NtpError::new("NTS_KE_TLS", format!("CA 憑證格式錯誤: {}", e)))?;
+            roots
+                .add(cert)
+                .map_err(|e| NtpError::new("NTS_KE_TLS", format!("無法加入 CA 憑證: {}", e)))?;
+        }
+    }
+
+    // NTS-KE 規定只能使用 TLS 1.3
+    let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])
+    .map_err(|e| NtpError::new("NTS_KE_TLS", format!("TLS 設定錯誤: {}", e)))?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    config.alpn_protocols = vec![NTS_KE_ALPN.to_vec()];
+
+    Ok(Arc::new(config))
+}
+
+fn write_ke_record(buf: &mut Vec<u8>, record_type: u16, body: &[u8]) {
+    buf.extend_from_slice(&(record_type | KE_CRITICAL).to_be_bytes());
+    buf.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    buf.extend_from_slice(body);
+}
+
+fn read_ke_record<R: Read>(stream: &mut R) -> Result<(bool, u16, Vec<u8>), NtpError> {
+    let mut header = [0u8; 4];
+    stream
+        .read_exact(&mut header)
+        .map_err(|e| NtpError::new("NTS_KE_PROTOCOL", format!("NTS-KE 回應中斷: {}", e)))?;
+
+    let raw_type = u16::from_be_bytes([header[0], header[1]]);
+    let body_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let mut body = vec![0u8; body_len];
+    stream
+        .read_exact(&mut body)
+        .map_err(|e| NtpError::new("NTS_KE_PROTOCOL", format!("NTS-KE 回應中斷: {}", e)))?;
+
+    Ok((raw_type & KE_CRITICAL != 0, raw_type & !KE_CRITICAL, body))
+}
+
+fn read_u16_body(body: &[u8]) -> Option<u16> {
+    if body.len() < 2 {
+        return None;
+    }
+    Some(u16::from_be_bytes([body[0], body[1]]))
+}
+
+fn key_exchange(server: &str, options: &QueryOptions) -> Result<NtsSession, NtpError> {
+    let spec = ServerSpec::parse(server, NTS_KE_PORT)?;
+    let addr = spec.resolve(options.address_family)?;
+
+    let timeout = options.timeout();
+    let tcp = TcpStream::connect_timeout(&addr, timeout)
+        .map_err(|e| NtpError::new("NTS_KE_CONNECT", format!("無法連線 NTS-KE 伺服器: {}", e)))?;
+    tcp.set_read_timeout(Some(timeout)).ok();
+    tcp.set_write_timeout(Some(timeout)).ok();
+
+    let server_name = ServerName::try_from(spec.host.clone())
+        .map_err(|e| NtpError::new("NTS_KE_TLS", format!("無效的伺服器名稱: {}", e)))?;
+    let conn = rustls::ClientConnection::new(tls_config(options)?, server_name)
+        .map_err(|e| NtpError::new("NTS_KE_TLS", format!("無法建立 TLS 連線: {}", e)))?;
+    let mut tls = rustls::StreamOwned::new(conn, tcp);
+
+    let mut request = Vec::new();
+    write_ke_record(&mut request, KE_NEXT_PROTOCOL, &NTS_PROTOCOL_NTPV4.to_be_bytes());
+    write_ke_record(&mut request, KE_AEAD_ALGORITHM, &AEAD_AES_SIV_CMAC_256.to_be_bytes());
+    write_ke_record(&mut request, KE_END_OF_MESSAGE, &[]);
+
+    tls.write_all(&request)
+        .and_then(|_| tls.flush())
+        .map_err(|e| NtpError::new("NTS_KE_TLS", format!("NTS-KE 交握失敗: {}", e)))?;
+
+    if tls.conn.alpn_protocol() != Some(NTS_KE_ALPN) {
+        return Err(NtpError::new("NTS_KE_PROTOCOL", "伺服器不支援 ntske/1 ALPN"));
+    }
+
+    let mut next_protocol = None;
+    let mut aead = None;
+    let mut cookies = Vec::new();
+    let mut ntp_host = spec.host.clone();
+    let mut ntp_port = ntp::NTP_PORT;
+
+    loop {
+        let (critical, record_type, body) = read_ke_record(&mut tls)?;
+        match record_type {
+            KE_END_OF_MESSAGE => break,
+            KE_NEXT_PROTOCOL => next_protocol = read_u16_body(&body),
+            KE_AEAD_ALGORITHM => aead = read_u16_body(&body),
+            KE_NEW_COOKIE => cookies.push(body),
+            KE_SERVER => ntp_host = String::from_utf8_lossy(&body).to_string(),
+            KE_PORT => ntp_port = read_u16_body(&body).unwrap_or(ntp::NTP_PORT),
+            KE_ERROR => {
+                return Err(NtpError::new(
+                    "NTS_KE_REJECTED",
+                    format!("NTS-KE 伺服器回報錯誤: {:?}", read_u16_body(&body)),
+                ))
+            }
+            KE_WARNING => {
+                println!("[NTS] 警告: NTS-KE 伺服器警告 {:?}", read_u16_body(&body));
+            }
+            _ if critical => {
+                return Err(NtpError::new(
+                    "NTS_KE_PROTOCOL",
+                    format!("無法處理的 NTS-KE 記錄類型: {}", record_type),
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    if next_protocol != Some(NTS_PROTOCOL_NTPV4) {
+        return Err(NtpError::new("NTS_KE_PROTOCOL", "伺服器未接受 NTPv4 協定"));
+    }
+    if aead != Some(AEAD_AES_SIV_CMAC_256) {
+        return Err(NtpError::new("NTS_KE_PROTOCOL", "伺服器未接受 AEAD_AES_SIV_CMAC_256"));
+    }
+    if cookies.is_empty() {
+        return Err(NtpError::new("NTS_KE_PROTOCOL", "NTS-KE 未取得任何 cookie"));
+    }
+
+    let mut context = [0u8; 5];
+    context[..2].copy_from_slice(&NTS_PROTOCOL_NTPV4.to_be_bytes());
+    context[2..4].copy_from_slice(&AEAD_AES_SIV_CMAC_256.to_be_bytes());
+    let c2s_key = tls
+        .conn
+        .export_keying_material([0u8; 32], NTS_EXPORTER_LABEL, Some(&context))
+        .map_err(|e| NtpError::new("NTS_KE_TLS", format!("無法匯出金鑰: {}", e)))?;
+    context[4] = 0x01;
+    let s2c_key = tls
+        .conn
+        .export_keying_material([0u8; 32], NTS_EXPORTER_LABEL, Some(&context))
+        .map_err(|e| NtpError::new("NTS_KE_TLS", format!("無法匯出金鑰: {}", e)))?;
+
+    tls.conn.send_close_notify();
+    let _ = tls.flush();
+
+    println!(
+        "[NTS] NTS-KE 完成: {} -> {}:{} ({} cookies)",
+        server,
+        ntp_host,
+        ntp_port,
+        cookies.len()
+    );
+
+    Ok(NtsSession {
+        ntp_host,
+        ntp_port,
+        c2s_key,
+        s2c_key,
+        cookies,
+    })
+}
+
+fn dbl(block: [u8; 16]) -> [u8; 16] {
+    let mut out = [0u8; 16];
+    for i in 0..16 {
+        out[i] = block[i] << 1;
+        if i < 15 {
+            out[i] |= block[i + 1] >> 7;
+        }
+    }
+    if block[0] & 0x80 != 0 {
+        out[15] ^= 0x87;
+    }
+    out
+}
+
+fn cmac(key: &[u8], data: &[u8]) -> [u8; 16] {
+    let mut mac = <Cmac<Aes128> as Mac>::new_from_slice(key).expect("CMAC key length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// RFC 5297 S2V，最後一個元件為明文
+fn s2v(key: &[u8], components: &[&[u8]], plaintext: &[u8]) -> [u8; 16] {
+    let mut d = cmac(key, &[0u8; 16]);
+    for component in components {
+        let mac = cmac(key, component);
+        d = dbl(d);
+        for i in 0..16 {
+            d[i] ^= mac[i];
+        }
+    }
+
+    if plaintext.len() >= 16 {
+        let mut t = plaintext.to_vec();
+        let start = t.len() - 16;
+        for i in 0..16 {
+            t[start + i] ^= d[i];
+        }
+        cmac(key, &t)
+    } else {
+        let mut t = dbl(d);
+        for (i, b) in plaintext.iter().enumerate() {
+            t[i] ^= b;
+        }
+        t[plaintext.len()] ^= 0x80;
+        cmac(key, &t)
+    }
+}
+
+fn siv_ctr(key: &[u8], siv: &[u8; 16], data: &mut [u8]) {
+    let mut iv = *siv;
+    iv[8] &= 0x7f;
+    iv[12] &= 0x7f;
+    let mut cipher = ctr::Ctr128BE::<Aes128>::new(key.into(), &iv.into());
+    cipher.apply_keystream(data);
+}
+
+fn siv_encrypt(key: &[u8; 32], components: &[&[u8]], plaintext: &[u8]) -> Vec<u8> {
+    let siv = s2v(&key[..16], components, plaintext);
+    let mut output = siv.to_vec();
+    output.extend_from_slice(plaintext);
+    siv_ctr(&key[16..], &siv, &mut output[16..]);
+    output
+}
+
+fn siv_decrypt(key: &[u8; 32], components: &[&[u8]], ciphertext: &[u8]) -> Option<Vec<u8>> {
+    if ciphertext.len() < 16 {
+        return None;
+    }
+    let mut siv = [0u8; 16];
+    siv.copy_from_slice(&ciphertext[..16]);
+    let mut plaintext = ciphertext[16..].to_vec();
+    siv_ctr(&key[16..], &siv, &mut plaintext);
+
+    let expected = s2v(&key[..16], components, &plaintext);
+    let diff = expected
+        .iter()
+        .zip(siv.iter())
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+    if diff == 0 {
+        Some(plaintext)
+    } else {
+        None
+    }
+}
+
+fn read_padded(body: &[u8], offset: usize, len: usize) -> Option<&[u8]> {
+    body.get(offset..offset + len)
+}
+
+/// 驗證回應的 NTS 擴充欄位，回傳伺服器加密送回的新 cookies
+fn verify_response(
+    response: &[u8],
+    unique_id: &[u8],
+    s2c_key: &[u8; 32],
+) -> Result<Vec<Vec<u8>>, NtpError> {
+    let fields = extension::parse(response)
+        .map_err(|_| NtpError::new("NTS_INVALID_RESPONSE", "NTS 擴充欄位長度錯誤"))?
+        .fields;
+    let mut unique_id_ok = false;
+
+    for (offset, field) in fields {
+        let body = field.value.as_slice();
+        match field.field_type {
+            EF_UNIQUE_IDENTIFIER => unique_id_ok = body == unique_id,
+            EF_NTS_AUTHENTICATOR => {
+                if !unique_id_ok {
+                    return Err(NtpError::new(
+                        "NTS_UNIQUE_ID_MISMATCH",
+                        "NTS Unique Identifier 不符",
+                    ));
+                }
+
+                let lengths = read_u16_body(body).zip(body.get(2..).and_then(read_u16_body));
+                let parts = lengths.and_then(|(nonce_len, ciphertext_len)| {
+                    let nonce_len = nonce_len as usize;
+                    let ciphertext_offset = 4 + nonce_len.div_ceil(4) * 4;
+                    Some((
+                        read_padded(body, 4, nonce_len)?,
+                        read_padded(body, ciphertext_offset, ciphertext_len as usize)?,
+                    ))
+                });
+                let Some((nonce, ciphertext)) = parts else {
+                    return Err(NtpError::new("NTS_INVALID_RESPONSE", "NTS Authenticator 格式錯誤"));
+                };
+
+                let plaintext = siv_decrypt(s2c_key, &[&response[..offset], nonce], ciphertext)
+                    .ok_or_else(|| NtpError::new("NTS_AUTH_FAILED", "NTS 回應認證失敗"))?;
+
+                let cookies = extension::parse_fields(&plaintext)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|(_, inner)| inner.field_type == EF_NTS_COOKIE)
+                    .map(|(_, inner)| inner.value)
+                    .collect();
+
+                // Authenticator 之後的欄位未受保護，直接忽略
+                return Ok(cookies);
+            }
+            _ => {}
+        }
+    }
+
+    Err(NtpError::new("NTS_AUTH_FAILED", "回應缺少 NTS Authenticator"))
+}
+
+/// 未認證的 NTS NAK 必須帶回請求的 Unique Identifier 才能採信 (RFC 8915 §5.7)，
+/// 否則任何猜中 origin 的偽造封包都能讓 session 失效
+fn is_nts_nak(packet: &NtpPacket, response: &[u8], unique_id: &[u8]) -> bool {
+    packet.stratum == 0
+        && packet.ref_id.decode(0) == "NTSN"
+        && extension::parse(response).is_ok_and(|parsed| {
+            parsed.fields.iter().any(|(_, field)| {
+                field.field_type == EF_UNIQUE_IDENTIFIER && field.value == unique_id
+            })
+        })
+}
+
+pub fn query_nts(server: &str, options: &QueryOptions) -> Result<NtpResult, NtpError> {
+    let cached = NTS_SESSIONS.lock().unwrap().remove(server);
+    let mut session = match cached {
+        Some(session) if !session.cookies.is_empty() => session,
+        _ => key_exchange(server, options)?,
+    };
+
+    let ntp_spec = ServerSpec {
+        host: session.ntp_host.clone(),
+        port: session.ntp_port,
+    };
+    let server_addr = match ntp_spec.resolve(options.address_family) {
+        Ok(addr) => addr,
+        Err(e) => {
+            NTS_SESSIONS.lock().unwrap().insert(server.to_string(), session);
+            return Err(e);
+        }
+    };
+
+    let cookie = session.cookies.pop().unwrap_or_default();
+    let placeholders = NTS_MAX_COOKIES.saturating_sub(session.cookies.len() + 1);
+
+    let mut unique_id = [0u8; NTS_UNIQUE_ID_LEN];
+    let mut nonce = [0u8; NTS_NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut unique_id);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let socket = ntp::bind_socket(&server_addr, options)?;
+    let origin = options.request_origin();
+
+    // NTS 只定義於 NTPv4
+    let mut packet = ntp::build_request(origin, ntp::NTP_VERSION).to_vec();
+    extension::write_field(&mut packet, EF_UNIQUE_IDENTIFIER, &unique_id);
+    extension::write_field(&mut packet, EF_NTS_COOKIE, &cookie);
+    for _ in 0..placeholders {
+        extension::write_field(&mut packet, EF_NTS_COOKIE_PLACEHOLDER, &vec![0u8; cookie.len()]);
+    }
+
+    let ciphertext = siv_encrypt(&session.c2s_key, &[&packet, &nonce], &[]);
+    let mut authenticator = Vec::new();
+    authenticator.extend_from_slice(&(NTS_NONCE_LEN as u16).to_be_bytes());
+    authenticator.extend_from_slice(&(ciphertext.len() as u16).to_be_bytes());
+    authenticator.extend_from_slice(&nonce);
+    authenticator.extend_from_slice(&ciphertext);
+    extension::write_field(&mut packet, EF_NTS_AUTHENTICATOR, &authenticator);
+
+    let mut response = [0u8; NTS_MAX_RESPONSE_SIZE];
+    let exchange = match ntp::exchange(&socket, server_addr, &packet, origin, &mut response) {
+        Ok(exchange) => exchange,
+        Err(e) => {
+            // cookie 已消耗，其餘 cookies 仍可於下次查詢使用
+            NTS_SESSIONS.lock().unwrap().insert(server.to_string(), session);
+            return Err(e);
+        }
+    };
+    let response = &response[..exchange.size];
+
+    // Authenticator 必須先驗證，RFC 8915 §5.7 只允許採信未認證的 NTS NAK
+    let verified = verify_response(response, &unique_id, &session.s2c_key);
+    let parsed = match (&verified, NtpPacket::decode(response)) {
+        (Ok(_), _) => ntp::parse_response(server, &exchange, response),
+        (Err(_), Ok(packet)) if is_nts_nak(&packet, response, &unique_id) => {
+            ntp::parse_response(server, &exchange, response)
+        }
+        (Err(e), Ok(packet)) => Err(ntp::unauthenticated_kiss(&packet, e.clone())),
+        (Err(_), Err(e)) => Err(e),
+    };
+
+    let mut result = match parsed {
+        Ok(result) => result,
+        // NTS NAK: 伺服器無法解讀 cookie，捨棄 session 讓下次查詢重新進行 NTS-KE
+        Err(e) if e.code == "KOD_NTSN" => return Err(e),
+        Err(e) => {
+            NTS_SESSIONS.lock().unwrap().insert(server.to_string(), session);
+            return Err(e);
+        }
+    };
+
+    let validated = ntp::validate_response(
+        response,
+        origin,
+        server_addr,
+        exchange.peer_addr,
+        options.lenient,
+    );
+    if let Ok(new_cookies) = verified {
+        session.cookies.extend(new_cookies);
+    }
+    NTS_SESSIONS.lock().unwrap().insert(server.to_string(), session);
+    validated?;
+
+    result.nts = true;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::timestamp::NtpTimestamp;
+    use rustls::pki_types::PrivateKeyDer;
+    use std::net::{TcpListener, UdpSocket};
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/nts");
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn key(hex: &str) -> [u8; 32] {
+        from_hex(hex).try_into().unwrap()
+    }
+
+    /// RFC 5297 A.1 Deterministic Authenticated Encryption
+    #[test]
+    fn aes_siv_rfc5297_deterministic() {
+        let key = key("fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff");
+        let ad = from_hex("101112131415161718191a1b1c1d1e1f2021222324252627");
+        let plaintext = from_hex("112233445566778899aabbccddee");
+        let expected = from_hex("85632d07c6e8f37f950acd320a2ecc9340c02b9690c4dc04daef7f6afe5c");
+
+        let ciphertext = siv_encrypt(&key, &[&ad], &plaintext);
+        assert_eq!(ciphertext, expected);
+        assert_eq!(siv_decrypt(&key, &[&ad], &ciphertext), Some(plaintext));
+    }
+
+    /// RFC 5297 A.2 Nonce-Based Authenticated Encryption
+    #[test]
+    fn aes_siv_rfc5297_nonce_based() {
+        let key = key("7f7e7d7c7b7a79787776757473727170404142434445464748494a4b4c4d4e4f");
+        let ad1 = from_hex(
+            "00112233445566778899aabbccddeeffdeaddadadeaddadaffeeddccbbaa99887766554433221100",
+        );
+        let ad2 = from_hex("102030405060708090a0");
+        let nonce = from_hex("09f911029d74e35bd84156c5635688c0");
+        let plaintext = from_hex(
+            "7468697320697320736f6d6520706c61696e7465787420746f20656e6372797074207573696e67205349562d414553",
+        );
+        let expected = from_hex(
+            "7bdb6e3b432667eb06f4d14bff2fbd0fcb900f2fddbe404326601965c889bf17\
+             dba77ceb094fa663b7a3f748ba8af829ea64ad544a272e9c485b62a3fd5c0d",
+        );
+
+        let ciphertext = siv_encrypt(&key, &[&ad1, &ad2, &nonce], &plaintext);
+        assert_eq!(ciphertext, expected);
+        assert_eq!(
+            siv_decrypt(&key, &[&ad1, &ad2, &nonce], &ciphertext),
+            Some(plaintext)
+        );
+    }
+
+    #[test]
+    fn aes_siv_rejects_tampering() {
+        let key = [7u8; 32];
+        let mut ciphertext = siv_encrypt(&key, &[b"header", b"nonce"], b"cookie");
+        assert!(siv_decrypt(&key, &[b"header", b"nonce"], &ciphertext).is_some());
+        assert!(siv_decrypt(&key, &[b"header", b"other"], &ciphertext).is_none());
+        ciphertext[20] ^= 1;
+        assert!(siv_decrypt(&key, &[b"header", b"nonce"], &ciphertext).is_none());
+        assert!(siv_decrypt(&key, &[], &ciphertext[..15]).is_none());
+    }
+
+    /// 以原始長度寫入欄位，用於建構不合規範的封包
+    fn raw_field(packet: &mut Vec<u8>, field_type: u16, value: &[u8]) {
+        packet.extend_from_slice(&field_type.to_be_bytes());
+        packet.extend_from_slice(&((value.len() + 4) as u16).to_be_bytes());
+        packet.extend_from_slice(value);
+    }
+
+    #[test]
+    fn malformed_authenticator_is_rejected() {
+        let unique_id = [3u8; NTS_UNIQUE_ID_LEN];
+        let header = NtpPacket::request(4, NtpTimestamp::random()).encode();
+
+        for value in [
+            &[][..],
+            &[0, 16, 0, 0][..],
+            &[0, 16, 0, 32, 1, 2, 3, 4, 5, 6, 7, 8][..],
+        ] {
+            let mut response = header.to_vec();
+            extension::write_field(&mut response, EF_UNIQUE_IDENTIFIER, &unique_id);
+            raw_field(&mut response, EF_NTS_AUTHENTICATOR, value);
+            // 避免 Authenticator 位於結尾時被當成 MAC
+            extension::write_field(&mut response, 0x0F00, &[0u8; 28]);
+            let error = verify_response(&response, &unique_id, &[0u8; 32]).unwrap_err();
+            assert_eq!(error.code, "NTS_INVALID_RESPONSE", "{:?}", value);
+        }
+    }
+
+    fn server_tls_config() -> Arc<rustls::ServerConfig> {
+        let certs = CertificateDer::pem_file_iter(format!("{}/server.pem", FIXTURES))
+            .unwrap()
+            .map(|cert| cert.unwrap())
+            .collect();
+        let key = PrivateKeyDer::from_pem_file(format!("{}/server.key", FIXTURES)).unwrap();
+        let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .unwrap();
+        config.alpn_protocols = vec![NTS_KE_ALPN.to_vec()];
+        Arc::new(config)
+    }
+
+    /// 本機 NTS-KE 伺服器：回應兩個 cookie 與 NTP 伺服器位址，回傳匯出的 (c2s, s2c) 金鑰
+    fn serve_key_exchange(listener: TcpListener, ntp_port: u16) -> ([u8; 32], [u8; 32]) {
+        let (tcp, _) = listener.accept().unwrap();
+        let conn = rustls::ServerConnection::new(server_tls_config()).unwrap();
+        let mut tls = rustls::StreamOwned::new(conn, tcp);
+
+        let mut received = Vec::new();
+        loop {
+            let (_, record_type, body) = read_ke_record(&mut tls).unwrap();
+            if record_type == KE_END_OF_MESSAGE {
+                break;
+            }
+            received.push((record_type, body));
+        }
+        assert!(received.contains(&(KE_NEXT_PROTOCOL, NTS_PROTOCOL_NTPV4.to_be_bytes().to_vec())));
+        assert!(received.contains(&(
+            KE_AEAD_ALGORITHM,
+            AEAD_AES_SIV_CMAC_256.to_be_bytes().to_vec()
+        )));
+
+        let mut reply = Vec::new();
+        write_ke_record(
+            &mut reply,
+            KE_NEXT_PROTOCOL,
+            &NTS_PROTOCOL_NTPV4.to_be_bytes(),
+        );
+        write_ke_record(
+            &mut reply,
+            KE_AEAD_ALGORITHM,
+            &AEAD_AES_SIV_CMAC_256.to_be_bytes(),
+        );
+        write_ke_record(&mut reply, KE_NEW_COOKIE, &[1u8; 64]);
+        write_ke_record(&mut reply, KE_NEW_COOKIE, &[2u8; 64]);
+        write_ke_record(&mut reply, KE_SERVER, b"127.0.0.1");
+        write_ke_record(&mut reply, KE_PORT, &ntp_port.to_be_bytes());
+        write_ke_record(&mut reply, KE_END_OF_MESSAGE, &[]);
+        tls.write_all(&reply).unwrap();
+        tls.flush().unwrap();
+
+        let mut context = [0u8; 5];
+        context[..2].copy_from_slice(&NTS_PROTOCOL_NTPV4.to_be_bytes());
+        context[2..4].copy_from_slice(&AEAD_AES_SIV_CMAC_256.to_be_bytes());
+        let c2s = tls
+            .conn
+            .export_keying_material([0u8; 32], NTS_EXPORTER_LABEL, Some(&context))
+            .unwrap();
+        context[4] = 0x01;
+        let s2c = tls
+            .conn
+            .export_keying_material([0u8; 32], NTS_EXPORTER_LABEL, Some(&context))
+            .unwrap();
+        // 等待 client 的 close_notify
+        let _ = tls.read(&mut [0u8; 1]);
+        (c2s, s2c)
+    }
+
+    /// 本機 NTS NTP 伺服器：驗證請求的 Authenticator，並以 s2c 金鑰加密送回一個新 cookie
+    fn serve_ntp(socket: UdpSocket, keys: ([u8; 32], [u8; 32])) -> Vec<u8> {
+        let (c2s, s2c) = keys;
+        let mut buf = [0u8; NTS_MAX_RESPONSE_SIZE];
+        let (size, peer) = socket.recv_from(&mut buf).unwrap();
+        let t2 = NtpTimestamp::now();
+        let request = &buf[..size];
+        let header = NtpPacket::decode(request).unwrap();
+        let fields = extension::parse(request).unwrap().fields;
+
+        let (_, unique_id) = fields
+            .iter()
+            .find(|(_, f)| f.field_type == EF_UNIQUE_IDENTIFIER)
+            .unwrap();
+        let cookie = fields
+            .iter()
+            .find(|(_, f)| f.field_type == EF_NTS_COOKIE)
+            .map(|(_, f)| f.value.clone())
+            .unwrap();
+        let (offset, authenticator) = fields
+            .iter()
+            .find(|(_, f)| f.field_type == EF_NTS_AUTHENTICATOR)
+            .unwrap();
+        let body = authenticator.value.as_slice();
+        let nonce_len = read_u16_body(body).unwrap() as usize;
+        let ciphertext_len = read_u16_body(&body[2..]).unwrap() as usize;
+        let nonce = &body[4..4 + nonce_len];
+        let ciphertext = &body[4 + nonce_len..4 + nonce_len + ciphertext_len];
+        assert_eq!(
+            siv_decrypt(&c2s, &[&request[..*offset], nonce], ciphertext),
+            Some(Vec::new())
+        );
+
+        let mut response = NtpPacket {
+            version: 4,
+            mode: 4,
+            stratum: 1,
+            precision: -20,
+            ref_id: crate::core::packet::ReferenceId(*b"NTS\0"),
+            ref_time: t2,
+            origin: header.transmit,
+            receive: t2,
+            transmit: NtpTimestamp::now(),
+            ..Default::default()
+        }
+        .encode()
+        .to_vec();
+        extension::write_field(&mut response, EF_UNIQUE_IDENTIFIER, &unique_id.value);
+
+        let mut plaintext = Vec::new();
+        extension::write_field(&mut plaintext, EF_NTS_COOKIE, &[9u8; 64]);
+        let nonce = [5u8; NTS_NONCE_LEN];
+        let ciphertext = siv_encrypt(&s2c, &[&response, &nonce], &plaintext);
+        let mut authenticator = Vec::new();
+        authenticator.extend_from_slice(&(NTS_NONCE_LEN as u16).to_be_bytes());
+        authenticator.extend_from_slice(&(ciphertext.len() as u16).to_be_bytes());
+        authenticator.extend_from_slice(&nonce);
+        authenticator.extend_from_slice(&ciphertext);
+        extension::write_field(&mut response, EF_NTS_AUTHENTICATOR, &authenticator);
+
+        socket.send_to(&response, peer).unwrap();
+        cookie
+    }
+
+    #[test]
+    fn key_exchange_and_query_against_local_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ke_port = listener.local_addr().unwrap().port();
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let ntp_port = udp.local_addr().unwrap().port();
+
+        let ke = std::thread::spawn(move || serve_key_exchange(listener, ntp_port));
+        let server = format!("localhost:{}", ke_port);
+        let options = QueryOptions {
+            nts: true,
+            nts_ca_file: Some(format!("{}/ca.pem", FIXTURES)),
+            address_family: ntp::AddressFamilyPreference::PreferIpv4,
+            ..Default::default()
+        };
+
+        let session = key_exchange(&server, &options).unwrap();
+        let keys = ke.join().unwrap();
+        assert_eq!((session.c2s_key, session.s2c_key), keys);
+        assert_eq!(session.ntp_host, "127.0.0.1");
+        assert_eq!(session.ntp_port, ntp_port);
+        assert_eq!(session.cookies, vec![vec![1u8; 64], vec![2u8; 64]]);
+
+        NTS_SESSIONS.lock().unwrap().insert(server.clone(), session);
+        let ntp = std::thread::spawn(move || serve_ntp(udp, keys));
+        let result = query_nts(&server, &options).unwrap();
+        let used_cookie = ntp.join().unwrap();
+
+        assert!(result.nts);
+        assert_eq!(result.ref_id, "NTS");
+        assert!(result.offset.abs() < 1000.0);
+        assert_eq!(used_cookie, vec![2u8; 64]);
+        let sessions = NTS_SESSIONS.lock().unwrap();
+        assert_eq!(
+            sessions[&server].cookies,
+            vec![vec![1u8; 64], vec![9u8; 64]]
+        );
+    }
+
+    #[test]
+    fn key_exchange_rejects_untrusted_certificate() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let conn = rustls::ServerConnection::new(server_tls_config()).unwrap();
+            let mut tls = rustls::StreamOwned::new(conn, tcp);
+            let _ = tls.read(&mut [0u8; 1]);
+        });
+        let options = QueryOptions {
+            address_family: ntp::AddressFamilyPreference::PreferIpv4,
+            ..Default::default()
+        };
+
+        let error = key_exchange(&format!("localhost:{}", port), &options)
+            .err()
+            .unwrap();
+        assert_eq!(error.code, "NTS_KE_TLS");
+        handle.join().unwrap();
+    }
+
+    /// 回應未認證的 Kiss-o'-Death，origin 與請求相符；echo_uid 為 false 時帶回錯誤的 Unique Identifier
+    fn serve_kiss(socket: UdpSocket, kiss_code: [u8; 4], echo_uid: bool) {
+        let mut buf = [0u8; NTS_MAX_RESPONSE_SIZE];
+        let (size, peer) = socket.recv_from(&mut buf).unwrap();
+        let request = NtpPacket::decode(&buf[..size]).unwrap();
+        let unique_id = extension::parse(&buf[..size])
+            .unwrap()
+            .fields
+            .into_iter()
+            .find(|(_, field)| field.field_type == EF_UNIQUE_IDENTIFIER)
+            .map(|(_, field)| field.value)
+            .unwrap();
+        let mut response = NtpPacket {
+            leap: 3,
+            version: 4,
+            mode: 4,
+            ref_id: crate::core::packet::ReferenceId(kiss_code),
+            origin: request.transmit,
+            ..Default::default()
+        }
+        .encode()
+        .to_vec();
+        match echo_uid {
+            true => extension::write_field(&mut response, EF_UNIQUE_IDENTIFIER, &unique_id),
+            false => extension::write_field(&mut response, EF_UNIQUE_IDENTIFIER, &[0xEE; 32]),
+        }
+        socket.send_to(&response, peer).unwrap();
+    }
+
+    fn query_kiss(server: &str, kiss_code: [u8; 4], echo_uid: bool) -> Result<NtpResult, NtpError> {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let session = NtsSession {
+            ntp_host: "127.0.0.1".to_string(),
+            ntp_port: udp.local_addr().unwrap().port(),
+            c2s_key: [1u8; 32],
+            s2c_key: [2u8; 32],
+            cookies: vec![vec![1u8; 64], vec![2u8; 64]],
+        };
+        NTS_SESSIONS.lock().unwrap().insert(server.to_string(), session);
+        let handle = std::thread::spawn(move || serve_kiss(udp, kiss_code, echo_uid));
+        let options = QueryOptions {
+            nts: true,
+            ..Default::default()
+        };
+        let result = ntp::query_ntp(server, &options);
+        handle.join().unwrap();
+        result
+    }
+
+    #[test]
+    fn unauthenticated_kiss_is_ignored() {
+        let server = "nts-forged-deny.test";
+        let error = query_kiss(server, *b"DENY", true).unwrap_err();
+        assert_eq!(error.code, "UNAUTHENTICATED_KOD");
+        assert!(!crate::core::kod::is_disabled(server));
+        assert!(NTS_SESSIONS.lock().unwrap().contains_key(server));
+    }
+
+    #[test]
+    fn unauthenticated_nts_nak_drops_session() {
+        let server = "nts-nak.test";
+        let error = query_kiss(server, *b"NTSN", true).unwrap_err();
+        assert_eq!(error.code, "KOD_NTSN");
+        assert!(!NTS_SESSIONS.lock().unwrap().contains_key(server));
+    }
+
+    #[test]
+    fn nts_nak_with_wrong_unique_id_is_ignored() {
+        let server = "nts-forged-nak.test";
+        let error = query_kiss(server, *b"NTSN", false).unwrap_err();
+        assert_eq!(error.code, "UNAUTHENTICATED_KOD");
+        assert!(NTS_SESSIONS.lock().unwrap().contains_key(server));
+        NTS_SESSIONS.lock().unwrap().remove(server);
+    }
+
+}
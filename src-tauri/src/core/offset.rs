@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use std::process::Command;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::core::ntp;
+use crate::core::{engine, http, kod, leap, ntp, server};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SetTimeResult {
@@ -369,6 +369,14 @@ pub struct SyncResult {
     pub t4: f64,
     pub pre_sync_offset: f64,
     pub post_sync_offset: f64,
+    /// 樣本同步距離的中位數 (ms)，即同步後時間的誤差上限
+    pub root_distance: f64,
+    /// 即將發生閏秒或閏秒資料有問題時的提醒
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leap_warning: Option<String>,
+    /// 本次同步解析主機名稱的耗時 (毫秒)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_ms: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub code: Option<String>,
 }
@@ -380,61 +388,161 @@ pub struct SyncError {
     pub code: String,
 }
 
-#[tauri::command]
-pub async fn sync_ntp_time(server: String) -> Result<String, String> {
-    println!("[SYNC] 開始同步: {}", server);
+/// 一次成功測量的 offset、delay 與同步距離 (ms)
+struct Sample {
+    offset: f64,
+    delay: f64,
+    root_distance: f64,
+}
 
-    let previous_time = get_current_time_ms();
+/// 各欄位分別取中位數
+fn median(samples: &[Sample], field: impl Fn(&Sample) -> f64) -> f64 {
+    let mut values: Vec<f64> = samples.iter().map(field).collect();
+    values.sort_by(f64::total_cmp);
+    values[values.len() / 2]
+}
 
-    let mut offsets: Vec<f64> = Vec::new();
-    let mut delays: Vec<f64> = Vec::new();
+/// leap smear 伺服器在閏秒前後提供的不是 UTC，扣除 smear 量後才能與其他來源比較
+fn remove_smear(result: &mut ntp::NtpResult) {
+    let smear = match result.smearing {
+        true => leap::smear_offset_ms(result.t4 + result.offset),
+        false => 0.0,
+    };
+    if smear != 0.0 {
+        println!(
+            "[SYNC] {} 正在 leap smear，扣除 {:.3}ms",
+            result.server_ip, smear
+        );
+        result.offset -= smear;
+    }
+}
+
+/// 解析一次主機名稱後連續測量 5 次，回傳工作階段、成功的樣本與最後一次結果；
+/// 同步距離超過上限的樣本由查詢本身拒絕
+async fn measure(
+    server: &str,
+    options: &ntp::QueryOptions,
+    timeout: std::time::Duration,
+) -> (Option<engine::Session>, Vec<Sample>, Option<ntp::NtpResult>) {
+    let mut samples: Vec<Sample> = Vec::new();
     let mut last_result: Option<ntp::NtpResult> = None;
 
+    let mut session = match engine::Session::resolve(server, options).await {
+        Ok(session) => session,
+        Err(e) => {
+            println!("[SYNC] 無法解析 {}: {}", server, e.error);
+            return (None, samples, last_result);
+        }
+    };
+
     for i in 1..=5 {
-        std::thread::sleep(std::time::Duration::from_millis(50));
-        match ntp::query_ntp(&server) {
-            Ok(r) => {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        match session.query(options, timeout).await {
+            Ok(r) if r.zone_unknown => {
+                println!("[SYNC] 測量 {}/5: {} 未標示時區，不用於同步", i, r.server);
+            }
+            Ok(mut r) => {
+                remove_smear(&mut r);
+                // 位址切換後捨棄先前的樣本，中位數只取自同一個位址
+                if last_result
+                    .as_ref()
+                    .is_some_and(|last| last.server_ip != r.server_ip)
+                {
+                    println!(
+                        "[SYNC] 已改用 {}，捨棄先前 {} 筆樣本",
+                        r.server_ip,
+                        samples.len()
+                    );
+                    samples.clear();
+                }
                 println!(
-                    "[SYNC] 測量 {}/5: offset={:.3}ms delay={:.3}ms",
-                    i, r.offset, r.delay
+                    "[SYNC] 測量 {}/5: offset={:.3}ms delay={:.3}ms 誤差上限=±{:.3}ms",
+                    i, r.offset, r.delay, r.root_distance
                 );
-                offsets.push(r.offset);
-                delays.push(r.delay);
+                samples.push(Sample {
+                    offset: r.offset,
+                    delay: r.delay,
+                    root_distance: r.root_distance,
+                });
                 last_result = Some(r);
             }
             Err(e) => {
                 println!("[SYNC] 測量 {}/5 失敗: {}", i, e.error);
+                // Kiss-o'-Death 或伺服器已停用時不再繼續查詢
+                if e.code.starts_with("KOD_") || e.code == "SERVER_DISABLED" {
+                    break;
+                }
             }
         }
     }
 
-    if offsets.is_empty() {
+    (Some(session), samples, last_result)
+}
+
+/// NTP 全部失敗時改用的 HTTP 來源，未指定 http_fallback 時使用預設網址，指定空字串時停用；
+/// 伺服器以 Kiss-o'-Death 拒絕服務後也不改用其他來源
+fn http_fallback(server: &str, options: &ntp::QueryOptions) -> Option<String> {
+    if http::is_http_url(server) || kod::is_disabled(server) {
+        return None;
+    }
+    match options.http_fallback.as_deref().map(str::trim) {
+        None => Some(http::DEFAULT_FALLBACK_URL.to_string()),
+        Some("") => None,
+        Some(url) => Some(url.to_string()),
+    }
+}
+
+/// 備援來源的量化誤差遠大於 NTP，偏差在誤差範圍內時調整時間不會更準確
+fn within_uncertainty(result: &ntp::NtpResult, offset: f64, root_distance: f64) -> bool {
+    result.protocol != ntp::TimeProtocol::Ntp && offset.abs() <= root_distance
+}
+
+#[tauri::command]
+pub async fn sync_ntp_time(server: String, options: Option<ntp::QueryOptions>) -> Result<String, String> {
+    println!("[SYNC] 開始同步: {}", server);
+
+    let options = options.unwrap_or_else(|| ntp::server_defaults(&server));
+    let timeout = options.timeout();
+
+    let previous_time = get_current_time_ms();
+
+    let mut source = server.clone();
+    let (mut session, mut samples, mut last_result) = measure(&source, &options, timeout).await;
+
+    // UDP 123 被封鎖時改用 HTTP Date 標頭
+    if let Some(fallback) = http_fallback(&server, &options).filter(|_| samples.is_empty()) {
+        source = fallback;
+        println!("[SYNC] 所有 NTP 查詢都失敗，改用 HTTP 時間來源: {}", source);
+        (session, samples, last_result) = measure(&source, &options, timeout).await;
+    }
+
+    let Some(mut session) = session.filter(|_| !samples.is_empty()) else {
         return serde_json::to_string(&SyncError {
             success: false,
             error: "所有 NTP 查詢都失敗".to_string(),
             code: "NTP_ERROR".to_string(),
         })
         .map_err(|e| e.to_string());
-    }
+    };
 
-    offsets.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    delays.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    let median_offset = offsets[offsets.len() / 2];
-    let median_delay = delays[delays.len() / 2];
+    let median_offset = median(&samples, |s| s.offset);
+    let median_delay = median(&samples, |s| s.delay);
+    let median_distance = median(&samples, |s| s.root_distance);
     let ntp_result = last_result.unwrap();
 
     println!(
-        "[SYNC] 中位數: offset={:.3}ms delay={:.3}ms (共{}次測量)",
-        median_offset, median_delay, offsets.len()
+        "[SYNC] 中位數: offset={:.3}ms delay={:.3}ms 誤差上限=±{:.3}ms (共{}次測量)",
+        median_offset, median_delay, median_distance, samples.len()
     );
 
-    fn do_sync(target_ms: f64, wait_until_local: f64) -> Result<(), SetTimeError> {
+    async fn do_sync(target_ms: f64, wait_until_local: f64) -> Result<(), SetTimeError> {
         let wait_ms = wait_until_local - get_current_time_ms();
 
         if wait_ms > 5.0 && wait_ms < 2000.0 {
             let sleep_ms = wait_ms - 2.0;
             if sleep_ms > 0.0 {
-                std::thread::sleep(std::time::Duration::from_micros((sleep_ms * 1000.0) as u64));
+                tokio::time::sleep(std::time::Duration::from_micros((sleep_ms * 1000.0) as u64))
+                    .await;
             }
             loop {
                 if get_current_time_ms() >= wait_until_local {
@@ -459,7 +567,29 @@ pub async fn sync_ntp_time(server: String) -> Result<String, String> {
         wait_until_local - now_local
     );
 
-    let sync_error = do_sync(next_second, wait_until_local).err();
+    let leap_warning = leap::status().warning;
+    if let Some(ref warning) = leap_warning {
+        println!("[SYNC] 閏秒提醒: {}", warning);
+    }
+
+    let skip_step = within_uncertainty(&ntp_result, median_offset, median_distance);
+    if skip_step {
+        println!(
+            "[SYNC] 偏差 {:.3}ms 在誤差範圍 ±{:.3}ms 內，不調整時間",
+            median_offset, median_distance
+        );
+    }
+
+    // 閏秒前後不調整時間，避免與伺服器的閏秒處理重複或抵觸
+    let sync_error = match leap::step_blackout() {
+        _ if skip_step => None,
+        Some(e) => Some(SetTimeError {
+            success: false,
+            error: e.error,
+            code: e.code,
+        }),
+        None => do_sync(next_second, wait_until_local).await.err(),
+    };
     let permission_denied = sync_error
         .as_ref()
         .map(|e| e.code == "PERMISSION_DENIED")
@@ -479,10 +609,11 @@ pub async fn sync_ntp_time(server: String) -> Result<String, String> {
     }
 
     let new_time = get_current_time_ms();
-    let post_sync_offset = if sync_error.is_none() {
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        match ntp::query_ntp(&server) {
-            Ok(r) => {
+    let post_sync_offset = if sync_error.is_none() && !skip_step {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        match session.query(&options, timeout).await {
+            Ok(mut r) => {
+                remove_smear(&mut r);
                 println!("[SYNC] 驗證: offset={:.3}ms delay={:.3}ms", r.offset, r.delay);
                 r.offset
             }
@@ -492,16 +623,19 @@ pub async fn sync_ntp_time(server: String) -> Result<String, String> {
         median_offset
     };
 
-    if sync_error.is_none() {
+    if sync_error.is_none() && !skip_step {
         println!(
             "[SYNC] 完成: 原始偏差={:.3}ms 最終偏差={:.3}ms",
             median_offset, post_sync_offset
         );
+        server::record_sync(&ntp_result, median_delay, post_sync_offset);
     }
 
     serde_json::to_string(&SyncResult {
         success: sync_error.is_none(),
-        message: if sync_error.is_none() {
+        message: if skip_step {
+            format!("偏差在誤差範圍 ±{:.0}ms 內，未調整時間", median_distance)
+        } else if sync_error.is_none() {
             "同步完成 (5次測量中位數)".to_string()
         } else {
             sync_error.as_ref().map(|e| e.error.clone()).unwrap_or_default()
@@ -518,13 +652,91 @@ pub async fn sync_ntp_time(server: String) -> Result<String, String> {
         t4: ntp_result.t4,
         pre_sync_offset: median_offset,
         post_sync_offset,
+        root_distance: median_distance,
+        leap_warning,
+        dns_ms: session.dns_ms,
         code: if permission_denied {
             Some("PERMISSION_DENIED".to_string())
         } else if sidecar_not_installed {
             Some("SIDECAR_NOT_INSTALLED".to_string())
+        } else if skip_step {
+            Some("WITHIN_UNCERTAINTY".to_string())
         } else {
-            None
+            sync_error
+                .as_ref()
+                .filter(|e| e.code == "LEAP_SECOND_WINDOW")
+                .map(|e| e.code.clone())
         },
     })
     .map_err(|e| e.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::timestamp::NtpTimestamp;
+
+    fn result(protocol: ntp::TimeProtocol) -> ntp::NtpResult {
+        let now = NtpTimestamp::now();
+        let addr = "127.0.0.1:80".parse().unwrap();
+        ntp::single_time_result("local", addr, (now, now), now, 500.0, protocol)
+    }
+
+    #[test]
+    fn http_fallback_by_default() {
+        let mut options = ntp::QueryOptions::default();
+        assert_eq!(
+            http_fallback("fallback.test", &options).as_deref(),
+            Some(http::DEFAULT_FALLBACK_URL)
+        );
+
+        options.http_fallback = Some(String::new());
+        assert_eq!(http_fallback("fallback.test", &options), None);
+
+        options.http_fallback = Some("http://127.0.0.1/".to_string());
+        assert_eq!(
+            http_fallback("fallback.test", &options).as_deref(),
+            Some("http://127.0.0.1/")
+        );
+        assert_eq!(http_fallback("http://127.0.0.1/", &options), None);
+    }
+
+    #[test]
+    fn no_http_fallback_after_deny() {
+        let server = "deny.fallback.test";
+        let options = ntp::QueryOptions {
+            http_fallback: Some("http://127.0.0.1/".to_string()),
+            ..Default::default()
+        };
+        kod::record_result(server, &Err(kod::kiss_error("DENY")));
+        assert_eq!(http_fallback(server, &options), None);
+        assert_eq!(http_fallback(server, &ntp::QueryOptions::default()), None);
+        kod::reenable(server);
+    }
+
+    #[test]
+    fn fallback_offset_within_uncertainty_is_not_stepped() {
+        let http = result(ntp::TimeProtocol::Http);
+        assert!(within_uncertainty(&http, 120.0, 500.0));
+        assert!(within_uncertainty(&http, -500.0, 500.0));
+        assert!(!within_uncertainty(&http, 1500.0, 500.0));
+
+        let ntp = result(ntp::TimeProtocol::Ntp);
+        assert!(!within_uncertainty(&ntp, 1.0, 20.0));
+    }
+
+    #[test]
+    fn median_does_not_panic_on_nan() {
+        let samples: Vec<Sample> = [3.0, f64::NAN, 1.0, 2.0]
+            .into_iter()
+            .map(|offset| Sample {
+                offset,
+                delay: 0.0,
+                root_distance: 0.0,
+            })
+            .collect();
+        // total_cmp 把正 NaN 排在最後
+        assert_eq!(median(&samples, |s| s.offset), 3.0);
+        assert_eq!(median(&samples, |s| s.delay), 0.0);
+    }
+}
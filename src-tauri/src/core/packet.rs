@@ -0,0 +1,384 @@
+use md5::{Digest, Md5};
+use std::net::IpAddr;
+
+use crate::core::ntp::NtpError;
+use crate::core::timestamp::{NtpShort, NtpTimestamp};
+
+/// NTP 標頭長度，擴充欄位與 MAC 接在其後
+pub const HEADER_SIZE: usize = 48;
+
+/// Reference ID，解讀方式取決於 stratum
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReferenceId(pub [u8; 4]);
+
+impl ReferenceId {
+    /// RFC 5905: IPv4 上游使用位址本身，IPv6 上游使用位址 MD5 的前 4 bytes
+    pub fn from_ip(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(v4) => ReferenceId(v4.octets()),
+            IpAddr::V6(v6) => {
+                let digest = Md5::digest(v6.octets());
+                ReferenceId([digest[0], digest[1], digest[2], digest[3]])
+            }
+        }
+    }
+
+    /// stratum 0 (Kiss Code) 與 1 (參考時鐘) 為 ASCII，其餘為上游位址，全為 0 表示沒有上游；
+    /// 不可能是上游 IPv4 位址的值 (0.0.0.0/8、多播與保留位址) 必定是 IPv6 上游的 MD5 雜湊，
+    /// 以 `md5:` 加十六進位顯示，其餘雜湊無法從封包本身分辨
+    pub fn decode(self, stratum: u8) -> String {
+        if stratum == 0 || stratum == 1 {
+            self.0
+                .iter()
+                .filter(|&&b| (0x20..=0x7E).contains(&b))
+                .map(|&b| b as char)
+                .collect::<String>()
+                .trim()
+                .to_string()
+        } else if self.0 == [0; 4] {
+            String::new()
+        } else if self.0[0] == 0 || self.0[0] >= 224 {
+            self.hash_text()
+        } else {
+            format!("{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+        }
+    }
+
+    /// stratum 2 以上顯示為 IPv4 位址的值也可能是 IPv6 上游的 MD5 雜湊，無法從封包本身分辨
+    pub fn is_ambiguous(self, stratum: u8) -> bool {
+        stratum >= 2 && self.0 != [0; 4] && self.0[0] != 0 && self.0[0] < 224
+    }
+
+    /// 原始的 4 bytes，以十六進位表示
+    pub fn to_hex(self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// 已知為 IPv6 上游的 MD5 雜湊時的顯示方式，避免被誤認為 IPv4 位址
+    pub fn hash_text(self) -> String {
+        format!(
+            "md5:{:02x}{:02x}{:02x}{:02x}",
+            self.0[0], self.0[1], self.0[2], self.0[3]
+        )
+    }
+
+    /// 依上游位址的類型顯示：IPv4 為位址本身，IPv6 為雜湊
+    pub fn describe(self, upstream: IpAddr) -> String {
+        match upstream {
+            IpAddr::V4(_) => self.decode(2),
+            IpAddr::V6(_) => self.hash_text(),
+        }
+    }
+}
+
+/// NTP 封包標頭 (RFC 5905 §7.3)
+///
+/// 編解碼器是 app crate 內的模組而非獨立 crate：錯誤沿用 NtpError、時間戳沿用 NtpTimestamp，
+/// 查詢、伺服器模式與測試替身直接共用
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NtpPacket {
+    pub leap: u8,
+    pub version: u8,
+    pub mode: u8,
+    pub stratum: u8,
+    pub poll: i8,
+    pub precision: i8,
+    pub root_delay: NtpShort,
+    pub root_dispersion: NtpShort,
+    pub ref_id: ReferenceId,
+    pub ref_time: NtpTimestamp,
+    pub origin: NtpTimestamp,
+    pub receive: NtpTimestamp,
+    pub transmit: NtpTimestamp,
+}
+
+impl NtpPacket {
+    /// mode 3 請求，transmit 即伺服器應在 Origin Timestamp 帶回的值
+    pub fn request(version: u8, transmit: NtpTimestamp) -> Self {
+        NtpPacket {
+            version,
+            mode: 3,
+            transmit,
+            ..Default::default()
+        }
+    }
+
+    /// 只解析標頭，之後的擴充欄位與 MAC 由 extension 模組處理
+    pub fn decode(bytes: &[u8]) -> Result<Self, NtpError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(NtpError::new(
+                "INCOMPLETE",
+                format!("回應不完整: {} bytes", bytes.len()),
+            ));
+        }
+
+        Ok(NtpPacket {
+            leap: bytes[0] >> 6,
+            version: (bytes[0] >> 3) & 0x07,
+            mode: bytes[0] & 0x07,
+            stratum: bytes[1],
+            poll: bytes[2] as i8,
+            precision: bytes[3] as i8,
+            root_delay: NtpShort::read(bytes, 4),
+            root_dispersion: NtpShort::read(bytes, 8),
+            ref_id: ReferenceId([bytes[12], bytes[13], bytes[14], bytes[15]]),
+            ref_time: NtpTimestamp::read(bytes, 16),
+            origin: NtpTimestamp::read(bytes, 24),
+            receive: NtpTimestamp::read(bytes, 32),
+            transmit: NtpTimestamp::read(bytes, 40),
+        })
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0u8; HEADER_SIZE];
+        bytes[0] = (self.leap & 0x03) << 6 | (self.version & 0x07) << 3 | self.mode & 0x07;
+        bytes[1] = self.stratum;
+        bytes[2] = self.poll as u8;
+        bytes[3] = self.precision as u8;
+        self.root_delay.write(&mut bytes, 4);
+        self.root_dispersion.write(&mut bytes, 8);
+        bytes[12..16].copy_from_slice(&self.ref_id.0);
+        self.ref_time.write(&mut bytes, 16);
+        self.origin.write(&mut bytes, 24);
+        self.receive.write(&mut bytes, 32);
+        self.transmit.write(&mut bytes, 40);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    struct Golden {
+        file: &'static str,
+        leap: u8,
+        version: u8,
+        stratum: u8,
+        precision: i8,
+        ref_id: &'static str,
+        /// decode 的結果可能其實是 IPv6 上游的雜湊
+        ambiguous: bool,
+        root_delay_ms: f64,
+        root_dispersion_ms: f64,
+    }
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/ntp");
+
+    /// 以本程式的伺服器模式在 loopback 上實際擷取的 mode 4 回應
+    const GOLDEN: &[Golden] = &[
+        Golden {
+            file: "unsynchronized",
+            leap: 3,
+            version: 4,
+            stratum: 16,
+            precision: -20,
+            ref_id: "",
+            ambiguous: false,
+            root_delay_ms: 0.0,
+            root_dispersion_ms: 0.0,
+        },
+        Golden {
+            file: "ipv4-upstream",
+            leap: 0,
+            version: 4,
+            stratum: 2,
+            precision: -20,
+            ref_id: "192.0.2.10",
+            ambiguous: true,
+            root_delay_ms: 8.5,
+            root_dispersion_ms: 1.75,
+        },
+        Golden {
+            file: "ipv6-upstream",
+            leap: 0,
+            version: 4,
+            stratum: 3,
+            precision: -20,
+            // 2001:db8::123 的 MD5 雜湊，但從封包本身看來與 IPv4 位址無異
+            ref_id: "201.117.206.204",
+            ambiguous: true,
+            root_delay_ms: 8.5,
+            root_dispersion_ms: 1.75,
+        },
+        Golden {
+            file: "leap-insert",
+            leap: 1,
+            version: 4,
+            stratum: 2,
+            precision: -20,
+            ref_id: "192.0.2.10",
+            ambiguous: true,
+            root_delay_ms: 8.5,
+            root_dispersion_ms: 1.75,
+        },
+        Golden {
+            file: "ntpv3-request",
+            leap: 1,
+            version: 3,
+            stratum: 2,
+            precision: -20,
+            ref_id: "192.0.2.10",
+            ambiguous: true,
+            root_delay_ms: 8.5,
+            root_dispersion_ms: 1.75,
+        },
+        Golden {
+            file: "kod-rate",
+            leap: 3,
+            version: 4,
+            stratum: 0,
+            precision: -20,
+            ref_id: "RATE",
+            ambiguous: false,
+            root_delay_ms: 0.0,
+            root_dispersion_ms: 0.0,
+        },
+    ];
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    /// 讀取 `#` 開頭為說明、其餘為十六進位封包內容的擷取檔
+    fn fixture(file: &str) -> Vec<u8> {
+        let content = std::fs::read_to_string(format!("{}/{}.hex", FIXTURES, file)).unwrap();
+        let hex: String = content
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect();
+        from_hex(&hex)
+    }
+
+    #[test]
+    fn golden_responses_decode() {
+        for golden in GOLDEN {
+            let bytes = fixture(golden.file);
+            assert_eq!(bytes.len(), HEADER_SIZE, "{}", golden.file);
+            let packet = NtpPacket::decode(&bytes).unwrap();
+            assert_eq!(packet.leap, golden.leap, "{}", golden.file);
+            assert_eq!(packet.version, golden.version, "{}", golden.file);
+            assert_eq!(packet.mode, 4, "{}", golden.file);
+            assert_eq!(packet.stratum, golden.stratum, "{}", golden.file);
+            assert_eq!(packet.poll, 0, "{}", golden.file);
+            assert_eq!(packet.precision, golden.precision, "{}", golden.file);
+            assert_eq!(
+                packet.ref_id.decode(packet.stratum),
+                golden.ref_id,
+                "{}",
+                golden.file
+            );
+            assert_eq!(
+                packet.ref_id.is_ambiguous(packet.stratum),
+                golden.ambiguous,
+                "{}",
+                golden.file
+            );
+            assert!(
+                (packet.root_delay.to_ms() - golden.root_delay_ms).abs() < 0.02,
+                "{}",
+                golden.file
+            );
+            assert!(
+                (packet.root_dispersion.to_ms() - golden.root_dispersion_ms).abs() < 0.02,
+                "{}",
+                golden.file
+            );
+        }
+    }
+
+    #[test]
+    fn golden_responses_round_trip() {
+        for golden in GOLDEN {
+            let bytes = fixture(golden.file);
+            let packet = NtpPacket::decode(&bytes).unwrap();
+            assert_eq!(
+                packet.encode().as_slice(),
+                bytes.as_slice(),
+                "{}",
+                golden.file
+            );
+            assert_eq!(
+                NtpPacket::decode(&packet.encode()).unwrap(),
+                packet,
+                "{}",
+                golden.file
+            );
+        }
+    }
+
+    #[test]
+    fn decode_ignores_trailing_data() {
+        let mut bytes = fixture("ipv4-upstream");
+        let header = NtpPacket::decode(&bytes).unwrap();
+        bytes.extend_from_slice(&[0xAB; 20]);
+        assert_eq!(NtpPacket::decode(&bytes).unwrap(), header);
+    }
+
+    #[test]
+    fn decode_rejects_short_packet() {
+        let bytes = fixture("ipv4-upstream");
+        let error = NtpPacket::decode(&bytes[..HEADER_SIZE - 1]).unwrap_err();
+        assert_eq!(error.code, "INCOMPLETE");
+    }
+
+    #[test]
+    fn request_layout() {
+        let transmit = NtpTimestamp::random();
+        let bytes = NtpPacket::request(4, transmit).encode();
+        assert_eq!(bytes[0], 0x23);
+        assert!(bytes[1..40].iter().all(|&b| b == 0));
+        assert_eq!(NtpTimestamp::read(&bytes, 40), transmit);
+        assert_eq!(NtpPacket::request(3, transmit).encode()[0], 0x1B);
+    }
+
+    #[test]
+    fn reference_id_from_ip() {
+        let v4 = Ipv4Addr::new(192, 0, 2, 10).into();
+        let refid = ReferenceId::from_ip(v4);
+        assert_eq!(refid.0, [192, 0, 2, 10]);
+        assert_eq!(refid.decode(2), "192.0.2.10");
+        assert_eq!(refid.describe(v4), "192.0.2.10");
+
+        let v6: Ipv6Addr = "2001:db8::123".parse().unwrap();
+        let hashed = ReferenceId::from_ip(v6.into());
+        assert_eq!(hashed.0, [0xC9, 0x75, 0xCE, 0xCC]);
+        assert_eq!(hashed.describe(v6.into()), "md5:c975cecc");
+        // 不知道上游位址時只能保留原始值並標示無法分辨
+        assert_eq!(hashed.to_hex(), "c975cecc");
+        assert!(hashed.is_ambiguous(3));
+        assert!(!hashed.is_ambiguous(1));
+        assert!(!ReferenceId([0xE9, 0x01, 0x02, 0x03]).is_ambiguous(2));
+        assert!(!ReferenceId::default().is_ambiguous(2));
+        assert_eq!(
+            hashed,
+            NtpPacket::decode(&fixture("ipv6-upstream")).unwrap().ref_id
+        );
+    }
+
+    #[test]
+    fn reference_id_impossible_ipv4_is_hash() {
+        assert_eq!(
+            ReferenceId([0xE9, 0x01, 0x02, 0x03]).decode(2),
+            "md5:e9010203"
+        );
+        assert_eq!(
+            ReferenceId([0x00, 0xAB, 0xCD, 0xEF]).decode(3),
+            "md5:00abcdef"
+        );
+        assert_eq!(ReferenceId([0xDF, 0x01, 0x02, 0x03]).decode(2), "223.1.2.3");
+    }
+
+    #[test]
+    fn reference_id_ascii_for_low_strata() {
+        assert_eq!(ReferenceId(*b"GPS\0").decode(1), "GPS");
+        assert_eq!(ReferenceId(*b"DENY").decode(0), "DENY");
+        assert_eq!(ReferenceId(*b"DENY").decode(2), "68.69.78.89");
+        assert_eq!(ReferenceId([b' ', b'P', 0x01, b'S']).decode(1), "PS");
+    }
+}
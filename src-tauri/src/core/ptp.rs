@@ -0,0 +1,823 @@
+use rand::RngCore;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::core::leap;
+use crate::core::ntp::{self, NtpError, NtpResult, QueryOptions, ServerSpec, TimeProtocol};
+use crate::core::timestamp::{offset_and_delay, NtpTimestamp};
+use crate::core::timestamping::{self, KernelTimestamping, TimestampSource};
+
+const PTP_EVENT_PORT: u16 = 319;
+const PTP_GENERAL_PORT: u16 = 320;
+/// IEEE 1588 預設 IPv4 multicast 群組 (primary domain)
+const PTP_PRIMARY_MULTICAST: Ipv4Addr = Ipv4Addr::new(224, 0, 1, 129);
+const PTP_VERSION: u8 = 2;
+const PTP_HEADER_SIZE: usize = 34;
+const PTP_MAX_MESSAGE_SIZE: usize = 1500;
+/// 接收執行緒檢查停止旗標的間隔
+const RECV_POLL_MS: u64 = 100;
+
+const MSG_SYNC: u8 = 0x0;
+const MSG_DELAY_REQ: u8 = 0x1;
+const MSG_FOLLOW_UP: u8 = 0x8;
+const MSG_DELAY_RESP: u8 = 0x9;
+const MSG_ANNOUNCE: u8 = 0xB;
+
+const FLAG_TWO_STEP: u16 = 0x0200;
+const FLAG_UNICAST: u16 = 0x0400;
+const FLAG_LEAP_61: u16 = 0x0001;
+const FLAG_LEAP_59: u16 = 0x0002;
+const FLAG_UTC_OFFSET_VALID: u16 = 0x0004;
+const FLAG_PTP_TIMESCALE: u16 = 0x0008;
+
+/// clockClass 6/7：與主要參考源 (GNSS 等) 同步中或剛失去同步
+const CLOCK_CLASS_LOCKED_MAX: u8 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct PortIdentity {
+    clock_identity: [u8; 8],
+    port_number: u16,
+}
+
+impl PortIdentity {
+    fn read(data: &[u8], offset: usize) -> Self {
+        let mut clock_identity = [0u8; 8];
+        clock_identity.copy_from_slice(&data[offset..offset + 8]);
+        PortIdentity {
+            clock_identity,
+            port_number: u16::from_be_bytes([data[offset + 8], data[offset + 9]]),
+        }
+    }
+
+    fn write(&self, data: &mut [u8], offset: usize) {
+        data[offset..offset + 8].copy_from_slice(&self.clock_identity);
+        data[offset + 8..offset + 10].copy_from_slice(&self.port_number.to_be_bytes());
+    }
+}
+
+impl std::fmt::Display for PortIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let id = &self.clock_identity;
+        write!(
+            f,
+            "{:02x}{:02x}{:02x}.{:02x}{:02x}.{:02x}{:02x}{:02x}-{}",
+            id[0], id[1], id[2], id[3], id[4], id[5], id[6], id[7], self.port_number
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    message_type: u8,
+    domain: u8,
+    flags: u16,
+    /// 奈秒 * 2^16
+    correction: i64,
+    source: PortIdentity,
+    sequence_id: u16,
+    log_interval: i8,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < PTP_HEADER_SIZE || data[1] & 0x0F != PTP_VERSION {
+            return None;
+        }
+        let length = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if length < PTP_HEADER_SIZE || length > data.len() {
+            return None;
+        }
+        Some(Header {
+            message_type: data[0] & 0x0F,
+            domain: data[4],
+            flags: u16::from_be_bytes([data[6], data[7]]),
+            correction: i64::from_be_bytes(data[8..16].try_into().unwrap()),
+            source: PortIdentity::read(data, 20),
+            sequence_id: u16::from_be_bytes([data[30], data[31]]),
+            log_interval: data[33] as i8,
+        })
+    }
+
+    fn correction_nanos(&self) -> i128 {
+        self.correction as i128 >> 16
+    }
+}
+
+/// PTP 時間戳 (48 位元秒 + 32 位元奈秒)，PTP timescale 下為 TAI
+#[derive(Debug, Clone, Copy)]
+struct PtpTimestamp {
+    seconds: u64,
+    nanos: u32,
+}
+
+impl PtpTimestamp {
+    fn read(data: &[u8], offset: usize) -> Option<Self> {
+        let bytes = data.get(offset..offset + 10)?;
+        let mut seconds = [0u8; 8];
+        seconds[2..].copy_from_slice(&bytes[..6]);
+        Some(PtpTimestamp {
+            seconds: u64::from_be_bytes(seconds),
+            nanos: u32::from_be_bytes(bytes[6..10].try_into().unwrap()),
+        })
+    }
+
+    fn write(&self, data: &mut [u8], offset: usize) {
+        data[offset..offset + 6].copy_from_slice(&self.seconds.to_be_bytes()[2..]);
+        data[offset + 6..offset + 10].copy_from_slice(&self.nanos.to_be_bytes());
+    }
+
+    fn nanos(&self) -> i128 {
+        self.seconds as i128 * 1_000_000_000 + self.nanos as i128
+    }
+}
+
+/// Announce 訊息中與最佳主時鐘選擇 (BMCA) 相關的欄位
+#[derive(Debug, Clone, Copy)]
+struct Announce {
+    header: Header,
+    utc_offset: i16,
+    priority1: u8,
+    clock_class: u8,
+    clock_accuracy: u8,
+    variance: u16,
+    priority2: u8,
+    grandmaster: [u8; 8],
+    steps_removed: u16,
+}
+
+impl Announce {
+    fn parse(header: Header, data: &[u8]) -> Option<Self> {
+        if data.len() < 64 {
+            return None;
+        }
+        Some(Announce {
+            header,
+            utc_offset: i16::from_be_bytes([data[44], data[45]]),
+            priority1: data[47],
+            clock_class: data[48],
+            clock_accuracy: data[49],
+            variance: u16::from_be_bytes([data[50], data[51]]),
+            priority2: data[52],
+            grandmaster: data[53..61].try_into().unwrap(),
+            steps_removed: u16::from_be_bytes([data[61], data[62]]),
+        })
+    }
+
+    /// 依 IEEE 1588 資料集比較順序排列，數值越小越好
+    #[allow(clippy::type_complexity)]
+    fn dataset(&self) -> (u8, u8, u8, u16, u8, [u8; 8], u16, PortIdentity) {
+        (
+            self.priority1,
+            self.clock_class,
+            self.clock_accuracy,
+            self.variance,
+            self.priority2,
+            self.grandmaster,
+            self.steps_removed,
+            self.header.source,
+        )
+    }
+
+    /// PTP timescale 為 TAI，需扣除 currentUtcOffset 才是 UTC；
+    /// 主時鐘未標示 currentUtcOffsetValid 時改用 leap-seconds.list 的 TAI-UTC
+    fn utc_offset_nanos(&self) -> Result<i128, NtpError> {
+        self.utc_offset_nanos_or(leap::tai_utc_at(chrono::Utc::now().timestamp_millis()))
+    }
+
+    fn utc_offset_nanos_or(&self, tai_utc: Option<i32>) -> Result<i128, NtpError> {
+        let flags = self.header.flags;
+        if flags & FLAG_PTP_TIMESCALE == 0 {
+            return Ok(0);
+        }
+        let seconds = match (flags & FLAG_UTC_OFFSET_VALID != 0, tai_utc) {
+            (true, _) => self.utc_offset as i128,
+            (false, Some(tai_utc)) => tai_utc as i128,
+            (false, None) => {
+                return Err(NtpError::new(
+                    "PTP_UTC_OFFSET_UNKNOWN",
+                    format!(
+                        "主時鐘 {} 使用 TAI 但未標示有效的 currentUtcOffset，且沒有可用的 leap-seconds.list",
+                        self.header.source
+                    ),
+                ))
+            }
+        };
+        Ok(seconds * 1_000_000_000)
+    }
+
+    fn leap(&self) -> u8 {
+        match self.header.flags {
+            f if f & FLAG_LEAP_61 != 0 => 1,
+            f if f & FLAG_LEAP_59 != 0 => 2,
+            _ => 0,
+        }
+    }
+}
+
+struct ForeignMaster {
+    announce: Announce,
+    last_seen: Instant,
+}
+
+impl ForeignMaster {
+    /// 超過 4 個 Announce 週期沒有收到即視為失效
+    fn expired(&self) -> bool {
+        let interval = 2f64.powi(self.announce.header.log_interval.clamp(-3, 4) as i32);
+        self.last_seen.elapsed() > Duration::from_secs_f64(interval * 4.0)
+    }
+}
+
+lazy_static::lazy_static! {
+    /// 各 domain 的 foreign master 資料集，跨查詢保留讓最佳主時鐘選擇不必每次重新收集
+    static ref FOREIGN_MASTERS: Mutex<HashMap<u8, HashMap<PortIdentity, ForeignMaster>>> =
+        Mutex::new(HashMap::new());
+}
+
+struct Target {
+    /// Delay_Req 的目的位址 (multicast 群組或單播主時鐘)
+    event_addr: SocketAddr,
+    multicast: bool,
+    domain: u8,
+    /// 本機接收 Sync/Delay_Req 的 event 連接埠與接收 Announce/Follow_Up/Delay_Resp 的 general 連接埠
+    event_port: u16,
+    general_port: u16,
+}
+
+pub fn is_ptp_url(server: &str) -> bool {
+    let server = server.trim();
+    server
+        .get(..6)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("ptp://"))
+}
+
+/// `ptp://[host[:port]][/domain]`，未指定 host 時使用預設 multicast 群組
+fn parse_target(server: &str, options: &QueryOptions) -> Result<Target, NtpError> {
+    let rest = server.trim()[6..].trim_end_matches('/');
+    let invalid = || NtpError::new("INVALID_SERVER", format!("無效的 PTP 位址: {}", server));
+
+    let (host, domain) = match rest.rsplit_once('/') {
+        Some((host, domain)) => (host, domain.parse::<u8>().map_err(|_| invalid())?),
+        None => (rest, 0),
+    };
+
+    let event_addr = if host.is_empty() {
+        SocketAddr::new(IpAddr::V4(PTP_PRIMARY_MULTICAST), PTP_EVENT_PORT)
+    } else {
+        ServerSpec::parse(host, PTP_EVENT_PORT)?.resolve(options.address_family)?
+    };
+
+    Ok(Target {
+        event_addr,
+        multicast: event_addr.ip().is_multicast(),
+        domain,
+        event_port: PTP_EVENT_PORT,
+        general_port: PTP_GENERAL_PORT,
+    })
+}
+
+struct Received {
+    data: Vec<u8>,
+    peer: SocketAddr,
+    timestamp: NtpTimestamp,
+    source: TimestampSource,
+}
+
+/// 接收執行緒，結束時設定停止旗標並等待執行緒釋放 socket
+struct Receivers {
+    stop: Arc<AtomicBool>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Receivers {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn spawn_receiver(
+    socket: UdpSocket,
+    mode: KernelTimestamping,
+    sender: mpsc::Sender<Received>,
+    stop: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut buf = [0u8; PTP_MAX_MESSAGE_SIZE];
+        while !stop.load(Ordering::Relaxed) {
+            let Ok((size, peer, timestamp, source)) =
+                timestamping::recv_from(&socket, &mut buf, mode)
+            else {
+                continue;
+            };
+            let received = Received {
+                data: buf[..size].to_vec(),
+                peer,
+                timestamp,
+                source,
+            };
+            if sender.send(received).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+fn bind(target: &Target, port: u16) -> Result<UdpSocket, NtpError> {
+    let local: IpAddr = match target.event_addr {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind((local, port)).map_err(|e| {
+        NtpError::new(
+            "SOCKET_BIND",
+            format!("無法綁定 PTP 連接埠 {} (需要系統管理員權限): {}", port, e),
+        )
+    })?;
+
+    if let IpAddr::V4(group) = target.event_addr.ip() {
+        if target.multicast {
+            socket
+                .join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)
+                .map_err(|e| {
+                    NtpError::new(
+                        "SOCKET_BIND",
+                        format!("無法加入 multicast 群組 {}: {}", group, e),
+                    )
+                })?;
+        }
+    }
+    socket
+        .set_read_timeout(Some(Duration::from_millis(RECV_POLL_MS)))
+        .map_err(|e| NtpError::new("SOCKET_TIMEOUT", format!("無法設定超時: {}", e)))?;
+    Ok(socket)
+}
+
+fn build_delay_req(target: &Target, identity: PortIdentity, sequence_id: u16) -> [u8; 44] {
+    let mut packet = [0u8; 44];
+    packet[0] = MSG_DELAY_REQ;
+    packet[1] = PTP_VERSION;
+    packet[2..4].copy_from_slice(&44u16.to_be_bytes());
+    packet[4] = target.domain;
+    if !target.multicast {
+        packet[6..8].copy_from_slice(&FLAG_UNICAST.to_be_bytes());
+    }
+    identity.write(&mut packet, 20);
+    packet[30..32].copy_from_slice(&sequence_id.to_be_bytes());
+    packet[32] = 1;
+    packet[33] = 0x7F;
+
+    let now = NtpTimestamp::now().to_unix_nanos(crate::core::timestamp::DEFAULT_PIVOT_UNIX_SECS);
+    PtpTimestamp {
+        seconds: (now / 1_000_000_000) as u64,
+        nanos: (now % 1_000_000_000) as u32,
+    }
+    .write(&mut packet, 34);
+    packet
+}
+
+/// 更新 foreign master 資料集並回傳目前最佳的主時鐘
+fn update_masters(domain: u8, announce: Option<Announce>) -> Option<Announce> {
+    let mut all = FOREIGN_MASTERS.lock().unwrap();
+    let masters = all.entry(domain).or_default();
+    if let Some(announce) = announce {
+        masters.insert(
+            announce.header.source,
+            ForeignMaster {
+                announce,
+                last_seen: Instant::now(),
+            },
+        );
+    }
+    masters.retain(|_, master| !master.expired());
+    masters
+        .values()
+        .map(|master| master.announce)
+        .min_by_key(Announce::dataset)
+}
+
+/// two-step Sync，等待對應的 Follow_Up 補上 preciseOriginTimestamp
+struct PendingSync {
+    sequence_id: u16,
+    master_addr: SocketAddr,
+    correction: i128,
+    t2: NtpTimestamp,
+    t2_source: TimestampSource,
+}
+
+/// 一次 Sync/Follow_Up + Delay_Req/Delay_Resp 交換的四個時間點，皆已換算為 UTC
+struct Measurement {
+    master: Announce,
+    /// Sync 的來源，即主時鐘的 event 位址
+    master_addr: SocketAddr,
+    /// 主時鐘送出 Sync
+    t1: NtpTimestamp,
+    /// 本機收到 Sync
+    t2: NtpTimestamp,
+    t2_source: TimestampSource,
+    /// 本機送出 Delay_Req
+    t3: NtpTimestamp,
+    t3_source: TimestampSource,
+    /// 主時鐘收到 Delay_Req
+    t4: NtpTimestamp,
+}
+
+fn measure(target: &Target, timeout: Duration) -> Result<Measurement, NtpError> {
+    let event = bind(target, target.event_port)?;
+    let general = bind(target, target.general_port)?;
+    let mode = timestamping::enable(&event);
+
+    let clone_error =
+        |e: std::io::Error| NtpError::new("SOCKET_BIND", format!("無法複製 socket: {}", e));
+    let (sender, receiver) = mpsc::channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let _receivers = Receivers {
+        handles: vec![
+            spawn_receiver(
+                event.try_clone().map_err(clone_error)?,
+                mode,
+                sender.clone(),
+                stop.clone(),
+            ),
+            spawn_receiver(general, KernelTimestamping::None, sender, stop.clone()),
+        ],
+        stop,
+    };
+
+    let mut clock_identity = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut clock_identity);
+    let identity = PortIdentity {
+        clock_identity,
+        port_number: 1,
+    };
+
+    let deadline = Instant::now() + timeout;
+    let mut best = update_masters(target.domain, None);
+    let mut pending: Option<PendingSync> = None;
+    // Delay_Req 送出前，measurement 的 t3/t4 尚未填入
+    let mut sync: Option<Measurement> = None;
+    let mut delay_req_id: Option<u16> = None;
+    let mut sequence_id = u16::from_be_bytes([clock_identity[0], clock_identity[1]]);
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let message = match receiver.recv_timeout(remaining) {
+            Ok(message) => message,
+            Err(_) => {
+                let stage = match (&best, &sync) {
+                    (None, _) => "未收到任何主時鐘的 Announce",
+                    (Some(_), None) => "未收到主時鐘的 Sync/Follow_Up",
+                    (Some(_), Some(_)) => "未收到 Delay_Resp",
+                };
+                return Err(NtpError::new(
+                    "TIMEOUT",
+                    format!("PTP 查詢逾時 (domain {}): {}", target.domain, stage),
+                ));
+            }
+        };
+
+        let Some(header) = Header::parse(&message.data) else {
+            continue;
+        };
+        if header.domain != target.domain || header.source == identity {
+            continue;
+        }
+        // 單播模式只接受指定主機的訊息
+        if !target.multicast && message.peer.ip() != target.event_addr.ip() {
+            continue;
+        }
+
+        if header.message_type == MSG_ANNOUNCE {
+            if let Some(announce) = Announce::parse(header, &message.data) {
+                best = update_masters(target.domain, Some(announce));
+            }
+            continue;
+        }
+
+        let Some(master) = best else {
+            continue;
+        };
+        if header.source != master.header.source {
+            continue;
+        }
+        let Some(timestamp) = PtpTimestamp::read(&message.data, PTP_HEADER_SIZE) else {
+            continue;
+        };
+        let utc_offset = master.utc_offset_nanos()?;
+
+        match header.message_type {
+            MSG_SYNC if sync.is_none() => {
+                if header.flags & FLAG_TWO_STEP != 0 {
+                    pending = Some(PendingSync {
+                        sequence_id: header.sequence_id,
+                        master_addr: message.peer,
+                        correction: header.correction_nanos(),
+                        t2: message.timestamp,
+                        t2_source: message.source,
+                    });
+                    continue;
+                }
+                let t1 = timestamp.nanos() + header.correction_nanos() - utc_offset;
+                sync = Some(Measurement {
+                    master,
+                    master_addr: message.peer,
+                    t1: NtpTimestamp::from_unix_nanos(t1),
+                    t2: message.timestamp,
+                    t2_source: message.source,
+                    t3: message.timestamp,
+                    t3_source: TimestampSource::User,
+                    t4: message.timestamp,
+                });
+            }
+            MSG_FOLLOW_UP if sync.is_none() => {
+                let Some(two_step) = pending.take_if(|p| p.sequence_id == header.sequence_id)
+                else {
+                    continue;
+                };
+                let t1 = timestamp.nanos() + two_step.correction + header.correction_nanos()
+                    - utc_offset;
+                sync = Some(Measurement {
+                    master,
+                    master_addr: two_step.master_addr,
+                    t1: NtpTimestamp::from_unix_nanos(t1),
+                    t2: two_step.t2,
+                    t2_source: two_step.t2_source,
+                    t3: two_step.t2,
+                    t3_source: TimestampSource::User,
+                    t4: two_step.t2,
+                });
+            }
+            MSG_DELAY_RESP => {
+                let (Some(seq), Some(mut m)) = (delay_req_id, sync.take()) else {
+                    continue;
+                };
+                if message.data.len() < 54
+                    || header.sequence_id != seq
+                    || PortIdentity::read(&message.data, 44) != identity
+                {
+                    sync = Some(m);
+                    continue;
+                }
+                let t4 = timestamp.nanos() - header.correction_nanos() - utc_offset;
+                m.t4 = NtpTimestamp::from_unix_nanos(t4);
+                return Ok(m);
+            }
+            _ => continue,
+        }
+
+        // 取得 Sync 的 t1/t2 後送出 Delay_Req
+        if let (Some(m), None) = (sync.as_mut(), delay_req_id) {
+            sequence_id = sequence_id.wrapping_add(1);
+            let destination = if target.multicast {
+                target.event_addr
+            } else {
+                m.master_addr
+            };
+            let packet = build_delay_req(target, identity, sequence_id);
+            let user_t3 = NtpTimestamp::now();
+            event
+                .send_to(&packet, destination)
+                .map_err(|e| NtpError::new("SEND_ERROR", format!("無法發送 Delay_Req: {}", e)))?;
+            (m.t3, m.t3_source) = match timestamping::tx_timestamp(&event, mode) {
+                Some(ts) => (ts, TimestampSource::Kernel),
+                None => (user_t3, TimestampSource::User),
+            };
+            delay_req_id = Some(sequence_id);
+        }
+    }
+}
+
+/// 以軟體時間戳的 ordinary clock slave 進行一次量測
+///
+/// Delay_Req/Delay_Resp 對應 NTP 的請求方向、Sync 對應回應方向，
+/// 因此 NtpResult 的 t1~t4 依序為 Delay_Req 送出、主時鐘收到 Delay_Req、主時鐘送出 Sync、本機收到 Sync，
+/// delay 為 meanPathDelay 的兩倍
+pub fn query_ptp(server: &str, options: &QueryOptions) -> Result<NtpResult, NtpError> {
+    let target = parse_target(server, options)?;
+    let m = measure(&target, options.timeout())?;
+
+    let (offset_ns, delay_ns) = offset_and_delay(m.t3, m.t4, m.t1, m.t2);
+    println!(
+        "[PTP] 主時鐘 {} ({}) clockClass={} offset={:.3}ms meanPathDelay={:.3}ms",
+        m.master.header.source,
+        m.master_addr,
+        m.master.clock_class,
+        offset_ns as f64 / 1_000_000.0,
+        delay_ns as f64 / 2_000_000.0
+    );
+
+    let mut result = ntp::single_time_result(
+        server,
+        m.master_addr,
+        (m.t3, m.t2),
+        m.t1,
+        0.0,
+        TimeProtocol::Ptp,
+    );
+    result.t2 = m.t4.to_unix_ms();
+    result.offset = offset_ns as f64 / 1_000_000.0;
+    result.delay = delay_ns as f64 / 1_000_000.0;
+    result.root_distance = ntp::root_distance(0.0, 0.0, result.delay, None);
+    result.leap = m.master.leap();
+    result.version = PTP_VERSION;
+    result.stratum = if m.master.clock_class <= CLOCK_CLASS_LOCKED_MAX {
+        (m.master.steps_removed + 1).min(15) as u8
+    } else {
+        16
+    };
+    result.poll = m.master.header.log_interval;
+    result.t1_source = m.t3_source;
+    result.t4_source = m.t2_source;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::timestamp::DEFAULT_PIVOT_UNIX_SECS;
+
+    /// 主時鐘比本機快 250ms，單程路徑延遲宣告為 5ms
+    const OFFSET_NS: i128 = 250_000_000;
+    const PATH_DELAY_NS: i128 = 5_000_000;
+    const TAI_UTC: i16 = 37;
+
+    fn identity(id: u8) -> PortIdentity {
+        PortIdentity {
+            clock_identity: [id; 8],
+            port_number: 1,
+        }
+    }
+
+    fn message(
+        message_type: u8,
+        length: usize,
+        domain: u8,
+        flags: u16,
+        source: PortIdentity,
+        sequence_id: u16,
+    ) -> Vec<u8> {
+        let mut data = vec![0u8; length];
+        data[0] = message_type;
+        data[1] = PTP_VERSION;
+        data[2..4].copy_from_slice(&(length as u16).to_be_bytes());
+        data[4] = domain;
+        data[6..8].copy_from_slice(&flags.to_be_bytes());
+        source.write(&mut data, 20);
+        data[30..32].copy_from_slice(&sequence_id.to_be_bytes());
+        data
+    }
+
+    fn announce(domain: u8, source: PortIdentity, priority1: u8) -> Vec<u8> {
+        let flags = FLAG_PTP_TIMESCALE | FLAG_UTC_OFFSET_VALID;
+        let mut data = message(MSG_ANNOUNCE, 64, domain, flags, source, 1);
+        data[44..46].copy_from_slice(&TAI_UTC.to_be_bytes());
+        data[47] = priority1;
+        data[48] = 6;
+        data[49] = 0x21;
+        data[52] = 128;
+        data[53..61].copy_from_slice(&source.clock_identity);
+        data
+    }
+
+    /// 主時鐘的 TAI 時間 (奈秒)，加上 extra 後寫入時間戳
+    fn master_time(extra: i128) -> PtpTimestamp {
+        let utc = NtpTimestamp::now().to_unix_nanos(DEFAULT_PIVOT_UNIX_SECS);
+        let tai = utc + TAI_UTC as i128 * 1_000_000_000 + OFFSET_NS + extra;
+        PtpTimestamp {
+            seconds: (tai / 1_000_000_000) as u64,
+            nanos: (tai % 1_000_000_000) as u32,
+        }
+    }
+
+    fn free_port() -> u16 {
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// 本機 two-step 主時鐘替身：宣告兩個主時鐘，較差的一方送出錯誤時間的 Sync，
+    /// 回應 Delay_Req 前先送出序號錯誤與 requestingPortIdentity 錯誤的 Delay_Resp
+    fn serve_grandmaster(domain: u8, client: (u16, u16)) -> (Target, JoinHandle<()>) {
+        let event = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let general = UdpSocket::bind("127.0.0.1:0").unwrap();
+        event
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let target = Target {
+            event_addr: event.local_addr().unwrap(),
+            multicast: false,
+            domain,
+            event_port: client.0,
+            general_port: client.1,
+        };
+        let client_event = SocketAddr::from((Ipv4Addr::LOCALHOST, client.0));
+        let client_general = SocketAddr::from((Ipv4Addr::LOCALHOST, client.1));
+        let (worse, better) = (identity(0xA1), identity(0xB1));
+
+        let handle = std::thread::spawn(move || {
+            let pause = || std::thread::sleep(Duration::from_millis(50));
+            pause();
+            general
+                .send_to(&announce(domain, worse, 200), client_general)
+                .unwrap();
+            general
+                .send_to(&announce(domain, better, 100), client_general)
+                .unwrap();
+            pause();
+
+            for (source, extra) in [(worse, 100_000_000_000), (better, -PATH_DELAY_NS)] {
+                let sync = message(MSG_SYNC, 44, domain, FLAG_TWO_STEP, source, 7);
+                event.send_to(&sync, client_event).unwrap();
+                let mut follow_up = message(MSG_FOLLOW_UP, 44, domain, 0, source, 7);
+                master_time(extra).write(&mut follow_up, 34);
+                pause();
+                general.send_to(&follow_up, client_general).unwrap();
+            }
+
+            let mut buf = [0u8; PTP_MAX_MESSAGE_SIZE];
+            let (size, _) = event.recv_from(&mut buf).unwrap();
+            let request = Header::parse(&buf[..size]).unwrap();
+            assert_eq!(request.message_type, MSG_DELAY_REQ);
+            let receive = master_time(PATH_DELAY_NS);
+
+            let delay_resp =
+                |sequence_id: u16, requester: PortIdentity, timestamp: PtpTimestamp| {
+                    let mut data = message(MSG_DELAY_RESP, 54, domain, 0, better, sequence_id);
+                    timestamp.write(&mut data, 34);
+                    requester.write(&mut data, 44);
+                    data
+                };
+            let wrong = master_time(100_000_000_000);
+            for data in [
+                delay_resp(request.sequence_id.wrapping_add(1), request.source, wrong),
+                delay_resp(request.sequence_id, identity(0xEE), wrong),
+                delay_resp(request.sequence_id, request.source, receive),
+            ] {
+                general.send_to(&data, client_general).unwrap();
+            }
+        });
+        (target, handle)
+    }
+
+    #[test]
+    fn two_step_exchange_with_grandmaster() {
+        let (target, handle) = serve_grandmaster(91, (free_port(), free_port()));
+        let m = measure(&target, Duration::from_secs(3)).unwrap();
+        handle.join().unwrap();
+
+        // BMCA 選擇 priority1 較小的主時鐘
+        assert_eq!(m.master.header.source, identity(0xB1));
+        assert_eq!(m.master.priority1, 100);
+
+        let (offset_ns, delay_ns) = offset_and_delay(m.t3, m.t4, m.t1, m.t2);
+        let mean_path_delay = delay_ns / 2;
+        assert!(
+            (offset_ns - OFFSET_NS).abs() < 2_000_000,
+            "offset={}",
+            offset_ns
+        );
+        assert!(
+            (mean_path_delay - PATH_DELAY_NS).abs() < 2_000_000,
+            "meanPathDelay={}",
+            mean_path_delay
+        );
+    }
+
+    #[test]
+    fn tai_without_valid_utc_offset() {
+        let header = Header::parse(&announce(0, identity(1), 128)).unwrap();
+        let valid = Announce::parse(header, &announce(0, identity(1), 128)).unwrap();
+        assert_eq!(valid.utc_offset_nanos_or(None).unwrap(), 37_000_000_000);
+
+        let invalid = Announce {
+            header: Header {
+                flags: FLAG_PTP_TIMESCALE,
+                ..header
+            },
+            utc_offset: 0,
+            ..valid
+        };
+        assert_eq!(
+            invalid.utc_offset_nanos_or(Some(37)).unwrap(),
+            37_000_000_000
+        );
+        assert_eq!(
+            invalid.utc_offset_nanos_or(None).unwrap_err().code,
+            "PTP_UTC_OFFSET_UNKNOWN"
+        );
+
+        let arbitrary = Announce {
+            header: Header { flags: 0, ..header },
+            ..valid
+        };
+        assert_eq!(arbitrary.utc_offset_nanos_or(None).unwrap(), 0);
+    }
+}
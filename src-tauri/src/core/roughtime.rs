@@ -0,0 +1,619 @@
+use base64::Engine;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use std::collections::HashMap;
+
+use crate::core::ntp::{self, NtpError, NtpResult, QueryOptions, ServerSpec, TimeProtocol};
+use crate::core::timestamp::NtpTimestamp;
+
+const ROUGHTIME_PORT: u16 = 2002;
+/// 請求需補齊至 1024 bytes，避免被用於放大攻擊
+const ROUGHTIME_REQUEST_SIZE: usize = 1024;
+const ROUGHTIME_MAX_RESPONSE_SIZE: usize = 4096;
+const NONCE_LEN: usize = 64;
+const HASH_LEN: usize = 64;
+const PUBLIC_KEY_LEN: usize = 32;
+
+const DELEGATION_CONTEXT: &[u8] = b"RoughTime v1 delegation signature--\x00";
+const RESPONSE_CONTEXT: &[u8] = b"RoughTime v1 response signature\x00";
+
+const TAG_SIG: u32 = u32::from_le_bytes(*b"SIG\x00");
+const TAG_NONC: u32 = u32::from_le_bytes(*b"NONC");
+const TAG_PAD: u32 = u32::from_le_bytes(*b"PAD\xff");
+const TAG_SREP: u32 = u32::from_le_bytes(*b"SREP");
+const TAG_CERT: u32 = u32::from_le_bytes(*b"CERT");
+const TAG_INDX: u32 = u32::from_le_bytes(*b"INDX");
+const TAG_PATH: u32 = u32::from_le_bytes(*b"PATH");
+const TAG_ROOT: u32 = u32::from_le_bytes(*b"ROOT");
+const TAG_MIDP: u32 = u32::from_le_bytes(*b"MIDP");
+const TAG_RADI: u32 = u32::from_le_bytes(*b"RADI");
+const TAG_DELE: u32 = u32::from_le_bytes(*b"DELE");
+const TAG_PUBK: u32 = u32::from_le_bytes(*b"PUBK");
+const TAG_MINT: u32 = u32::from_le_bytes(*b"MINT");
+const TAG_MAXT: u32 = u32::from_le_bytes(*b"MAXT");
+
+/// Roughtime 伺服器與其長期公鑰 (base64 或 hex)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoughtimeServer {
+    pub address: String,
+    pub public_key: String,
+}
+
+/// 依序查詢時，後查詢的伺服器時間早於先查詢者的不確定範圍，表示至少一台伺服器有誤
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoughtimeConflict {
+    pub earlier: String,
+    pub later: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoughtimeChainResult {
+    pub results: Vec<NtpResult>,
+    pub conflicts: Vec<RoughtimeConflict>,
+}
+
+/// 依 tag 排序組成 Roughtime 訊息，所有整數皆為 little-endian
+fn encode_message(fields: &[(u32, &[u8])]) -> Vec<u8> {
+    let mut message = Vec::new();
+    message.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+
+    let mut offset = 0u32;
+    for (_, value) in fields.iter().take(fields.len().saturating_sub(1)) {
+        offset += value.len() as u32;
+        message.extend_from_slice(&offset.to_le_bytes());
+    }
+    for (tag, _) in fields {
+        message.extend_from_slice(&tag.to_le_bytes());
+    }
+    for (_, value) in fields {
+        message.extend_from_slice(value);
+    }
+    message
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn decode_message(data: &[u8]) -> Result<HashMap<u32, &[u8]>, String> {
+    let count = read_u32(data, 0).ok_or("訊息過短")? as usize;
+    let header_len = count
+        .checked_mul(8)
+        .filter(|len| *len <= data.len())
+        .ok_or_else(|| format!("標頭長度錯誤 ({} 個 tag)", count))?;
+    if count == 0 {
+        return Ok(HashMap::new());
+    }
+
+    let values = &data[header_len..];
+    let mut fields = HashMap::new();
+    let mut previous_tag = None;
+    for i in 0..count {
+        let start = if i == 0 {
+            0
+        } else {
+            read_u32(data, 4 * i).unwrap() as usize
+        };
+        let end = if i + 1 == count {
+            values.len()
+        } else {
+            read_u32(data, 4 * (i + 1)).unwrap() as usize
+        };
+        if !start.is_multiple_of(4) || start > end || end > values.len() {
+            return Err(format!("欄位位移錯誤 ({}..{})", start, end));
+        }
+
+        let tag = read_u32(data, 4 * count + 4 * i).unwrap();
+        if previous_tag.is_some_and(|previous| tag <= previous) {
+            return Err("tag 未依序排列".to_string());
+        }
+        previous_tag = Some(tag);
+        fields.insert(tag, &values[start..end]);
+    }
+    Ok(fields)
+}
+
+fn invalid_response(reason: impl std::fmt::Display) -> NtpError {
+    NtpError::new(
+        "ROUGHTIME_INVALID_RESPONSE",
+        format!("Roughtime 回應格式錯誤: {}", reason),
+    )
+}
+
+fn tag_name(tag: u32) -> String {
+    String::from_utf8_lossy(&tag.to_le_bytes())
+        .trim_end_matches(['\0', '\u{fffd}'])
+        .to_string()
+}
+
+fn get_field<'a>(
+    fields: &HashMap<u32, &'a [u8]>,
+    tag: u32,
+    len: Option<usize>,
+) -> Result<&'a [u8], NtpError> {
+    let value = fields
+        .get(&tag)
+        .copied()
+        .ok_or_else(|| invalid_response(format!("缺少 {}", tag_name(tag))))?;
+    match len {
+        Some(len) if value.len() != len => Err(invalid_response(format!(
+            "{} 長度錯誤 ({})",
+            tag_name(tag),
+            value.len()
+        ))),
+        _ => Ok(value),
+    }
+}
+
+fn get_u64(fields: &HashMap<u32, &[u8]>, tag: u32) -> Result<u64, NtpError> {
+    let value = get_field(fields, tag, Some(8))?;
+    Ok(u64::from_le_bytes(value.try_into().unwrap()))
+}
+
+fn parse_public_key(key: &str) -> Result<VerifyingKey, NtpError> {
+    let key = key.trim();
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(key)
+        .ok()
+        .filter(|b| b.len() == PUBLIC_KEY_LEN)
+        .or_else(|| decode_hex(key))
+        .ok_or_else(|| NtpError::new("ROUGHTIME_INVALID_KEY", "無效的 Roughtime 公鑰"))?;
+
+    VerifyingKey::from_bytes(&bytes.try_into().unwrap())
+        .map_err(|_| NtpError::new("ROUGHTIME_INVALID_KEY", "無效的 Roughtime 公鑰"))
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if value.len() != PUBLIC_KEY_LEN * 2 {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn verify_signature(key: &VerifyingKey, context: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+    key.verify(&[context, message].concat(), &signature).is_ok()
+}
+
+fn hash_leaf(nonce: &[u8]) -> [u8; HASH_LEN] {
+    Sha512::new()
+        .chain_update([0x00])
+        .chain_update(nonce)
+        .finalize()
+        .into()
+}
+
+fn hash_node(left: &[u8], right: &[u8]) -> [u8; HASH_LEN] {
+    Sha512::new()
+        .chain_update([0x01])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+/// 以 PATH 與 INDX 從 nonce 重算 Merkle root
+fn merkle_root(nonce: &[u8], path: &[u8], mut index: u32) -> Result<[u8; HASH_LEN], NtpError> {
+    if !path.len().is_multiple_of(HASH_LEN) || path.len() / HASH_LEN > 32 {
+        return Err(invalid_response(format!("PATH 長度錯誤 ({})", path.len())));
+    }
+
+    let mut hash = hash_leaf(nonce);
+    for node in path.chunks(HASH_LEN) {
+        hash = if index & 1 == 0 {
+            hash_node(&hash, node)
+        } else {
+            hash_node(node, &hash)
+        };
+        index >>= 1;
+    }
+    if index != 0 {
+        return Err(invalid_response("INDX 超出 PATH 範圍"));
+    }
+    Ok(hash)
+}
+
+/// 驗證回應並回傳 (midpoint, radius)，單位為微秒
+fn verify_response(
+    response: &[u8],
+    nonce: &[u8],
+    public_key: &VerifyingKey,
+) -> Result<(u64, u32), NtpError> {
+    let message = decode_message(response).map_err(invalid_response)?;
+    let signature = get_field(&message, TAG_SIG, Some(64))?;
+    let srep_bytes = get_field(&message, TAG_SREP, None)?;
+    let cert_bytes = get_field(&message, TAG_CERT, None)?;
+    let index = read_u32(get_field(&message, TAG_INDX, Some(4))?, 0).unwrap();
+    let path = get_field(&message, TAG_PATH, None)?;
+
+    let cert = decode_message(cert_bytes).map_err(invalid_response)?;
+    let dele_bytes = get_field(&cert, TAG_DELE, None)?;
+    let cert_signature = get_field(&cert, TAG_SIG, Some(64))?;
+    if !verify_signature(public_key, DELEGATION_CONTEXT, dele_bytes, cert_signature) {
+        return Err(NtpError::new(
+            "ROUGHTIME_AUTH_FAILED",
+            "Roughtime 委派憑證簽章驗證失敗",
+        ));
+    }
+
+    let dele = decode_message(dele_bytes).map_err(invalid_response)?;
+    let delegated_key = VerifyingKey::from_bytes(
+        get_field(&dele, TAG_PUBK, Some(PUBLIC_KEY_LEN))?
+            .try_into()
+            .unwrap(),
+    )
+    .map_err(|_| invalid_response("PUBK 無效"))?;
+    let (min_time, max_time) = (get_u64(&dele, TAG_MINT)?, get_u64(&dele, TAG_MAXT)?);
+
+    if !verify_signature(&delegated_key, RESPONSE_CONTEXT, srep_bytes, signature) {
+        return Err(NtpError::new(
+            "ROUGHTIME_AUTH_FAILED",
+            "Roughtime 回應簽章驗證失敗",
+        ));
+    }
+
+    let srep = decode_message(srep_bytes).map_err(invalid_response)?;
+    let root = get_field(&srep, TAG_ROOT, Some(HASH_LEN))?;
+    let midpoint = get_u64(&srep, TAG_MIDP)?;
+    let radius = read_u32(get_field(&srep, TAG_RADI, Some(4))?, 0).unwrap();
+
+    if merkle_root(nonce, path, index)? != root {
+        return Err(NtpError::new(
+            "ROUGHTIME_AUTH_FAILED",
+            "Roughtime Merkle proof 與 ROOT 不符",
+        ));
+    }
+    if midpoint < min_time || midpoint > max_time {
+        return Err(NtpError::new(
+            "ROUGHTIME_CERT_EXPIRED",
+            format!(
+                "Roughtime 委派金鑰不在有效期間 (MIDP={} MINT={} MAXT={})",
+                midpoint, min_time, max_time
+            ),
+        ));
+    }
+
+    Ok((midpoint, radius))
+}
+
+/// 以指定 nonce 查詢，回傳結果與原始回應 (供下一台伺服器串接 nonce)
+fn query_with_nonce(
+    server: &RoughtimeServer,
+    nonce: &[u8; NONCE_LEN],
+    options: &QueryOptions,
+) -> Result<(NtpResult, Vec<u8>), NtpError> {
+    let public_key = parse_public_key(&server.public_key)?;
+    let server_addr =
+        ServerSpec::parse(&server.address, ROUGHTIME_PORT)?.resolve(options.address_family)?;
+    let socket = ntp::bind_socket(&server_addr, options)?;
+
+    let padding = vec![0u8; ROUGHTIME_REQUEST_SIZE - NONCE_LEN - 16];
+    let request = encode_message(&[(TAG_NONC, nonce), (TAG_PAD, &padding)]);
+
+    let mut response = [0u8; ROUGHTIME_MAX_RESPONSE_SIZE];
+    let exchange = ntp::exchange(
+        &socket,
+        server_addr,
+        &request,
+        NtpTimestamp::now(),
+        &mut response,
+    )?;
+    if exchange.peer_addr != server_addr {
+        return Err(NtpError::new(
+            "UNEXPECTED_SOURCE",
+            format!(
+                "回應來源 {} 與請求目標 {} 不符",
+                exchange.peer_addr, server_addr
+            ),
+        ));
+    }
+    let response = &response[..exchange.size];
+
+    let (midpoint_us, radius_us) = verify_response(response, nonce, &public_key)?;
+    let midpoint = NtpTimestamp::from_unix_nanos(midpoint_us as i128 * 1000);
+
+    // root_dispersion 為伺服器宣告的不確定半徑
+    let mut result = ntp::single_time_result(
+        &server.address,
+        server_addr,
+        (exchange.t1, exchange.t4),
+        midpoint,
+        radius_us as f64 / 1000.0,
+        TimeProtocol::Roughtime,
+    );
+    result.t1_source = exchange.t1_source;
+    result.t4_source = exchange.t4_source;
+    Ok((result, response.to_vec()))
+}
+
+/// 依序查詢多台伺服器，每次的 nonce 由前一個回應雜湊而來，
+/// 回應順序與時間互相矛盾時可作為伺服器說謊的證據；options 的位址族、逾時與來源位址套用於每台伺服器
+pub fn query_roughtime_chain(
+    servers: &[RoughtimeServer],
+    options: &QueryOptions,
+) -> Result<RoughtimeChainResult, NtpError> {
+    let mut results: Vec<NtpResult> = Vec::new();
+    let mut previous_response: Option<Vec<u8>> = None;
+
+    for server in servers {
+        let mut blind = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut blind);
+        let nonce: [u8; NONCE_LEN] = match &previous_response {
+            Some(previous) => Sha512::new()
+                .chain_update(previous)
+                .chain_update(blind)
+                .finalize()
+                .into(),
+            None => blind,
+        };
+
+        let (result, response) = query_with_nonce(server, &nonce, options)?;
+        results.push(result);
+        previous_response = Some(response);
+    }
+
+    let mut conflicts = Vec::new();
+    for (i, earlier) in results.iter().enumerate() {
+        for later in &results[i + 1..] {
+            if later.t2 + later.root_dispersion < earlier.t2 - earlier.root_dispersion {
+                conflicts.push(RoughtimeConflict {
+                    earlier: earlier.server.clone(),
+                    later: later.server.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(RoughtimeChainResult { results, conflicts })
+}
+
+#[tauri::command]
+pub async fn query_roughtime_servers(
+    servers: Vec<RoughtimeServer>,
+    options: Option<QueryOptions>,
+) -> Result<String, String> {
+    println!("[Roughtime] 查詢 {} 台伺服器", servers.len());
+
+    let options = options.unwrap_or_default();
+    let task = tokio::task::spawn_blocking(move || query_roughtime_chain(&servers, &options));
+    match task.await.map_err(|e| e.to_string())? {
+        Ok(chain) => {
+            for r in &chain.results {
+                println!(
+                    "[Roughtime] ✓ {} ({}) | offset={}ms radius={}ms",
+                    r.server, r.server_ip, r.offset, r.root_dispersion
+                );
+            }
+            for c in &chain.conflicts {
+                println!("[Roughtime] ⚠ {} 與 {} 的時間互相矛盾", c.earlier, c.later);
+            }
+            serde_json::to_string(&chain).map_err(|e| e.to_string())
+        }
+        Err(error) => {
+            println!("[Roughtime] ✗ {} ({})", error.error, error.code);
+            serde_json::to_string(&error).map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::net::UdpSocket;
+
+    /// 測試回應中刻意製造的錯誤
+    #[derive(Clone, Copy, PartialEq)]
+    enum Fault {
+        None,
+        DelegationSignature,
+        ResponseSignature,
+        WrongIndex,
+        OutsideValidity,
+    }
+
+    /// 以長期金鑰委派線上金鑰的本機 Roughtime 替身，nonce 放在 4 個葉節點 Merkle tree 的第 2 個位置
+    struct Responder {
+        long_term: SigningKey,
+        skew_us: i64,
+        fault: Fault,
+    }
+
+    const LEAVES: usize = 4;
+    const NONCE_INDEX: usize = 2;
+
+    impl Responder {
+        fn new(seed: u8, skew_us: i64, fault: Fault) -> Self {
+            Responder {
+                long_term: SigningKey::from_bytes(&[seed; 32]),
+                skew_us,
+                fault,
+            }
+        }
+
+        fn server(&self, address: String) -> RoughtimeServer {
+            RoughtimeServer {
+                address,
+                public_key: base64::engine::general_purpose::STANDARD
+                    .encode(self.long_term.verifying_key().as_bytes()),
+            }
+        }
+
+        fn respond(&self, request: &[u8]) -> Vec<u8> {
+            let fields = decode_message(request).unwrap();
+            let nonce = fields[&TAG_NONC];
+
+            let mut level: Vec<[u8; HASH_LEN]> = (0..LEAVES)
+                .map(|i| match i {
+                    NONCE_INDEX => hash_leaf(nonce),
+                    _ => hash_leaf(&[i as u8; NONCE_LEN]),
+                })
+                .collect();
+            let (mut path, mut index) = (Vec::new(), NONCE_INDEX);
+            while level.len() > 1 {
+                path.extend_from_slice(&level[index ^ 1]);
+                level = level
+                    .chunks(2)
+                    .map(|pair| hash_node(&pair[0], &pair[1]))
+                    .collect();
+                index >>= 1;
+            }
+
+            let now_us = chrono::Utc::now().timestamp_micros();
+            let midpoint = (now_us + self.skew_us) as u64;
+            let (min_time, max_time) = match self.fault {
+                Fault::OutsideValidity => (midpoint + 1, midpoint + 3_600_000_000),
+                _ => (midpoint - 3_600_000_000, midpoint + 3_600_000_000),
+            };
+
+            let online = SigningKey::from_bytes(&[0x42; 32]);
+            let dele = message(vec![
+                (TAG_PUBK, online.verifying_key().as_bytes().to_vec()),
+                (TAG_MINT, min_time.to_le_bytes().to_vec()),
+                (TAG_MAXT, max_time.to_le_bytes().to_vec()),
+            ]);
+            let mut dele_signature = sign(&self.long_term, DELEGATION_CONTEXT, &dele);
+            let srep = message(vec![
+                (TAG_ROOT, level[0].to_vec()),
+                (TAG_MIDP, midpoint.to_le_bytes().to_vec()),
+                (TAG_RADI, 1_000_000u32.to_le_bytes().to_vec()),
+            ]);
+            let mut srep_signature = sign(&online, RESPONSE_CONTEXT, &srep);
+
+            match self.fault {
+                Fault::DelegationSignature => dele_signature[0] ^= 1,
+                Fault::ResponseSignature => srep_signature[0] ^= 1,
+                _ => {}
+            }
+            let index = match self.fault {
+                Fault::WrongIndex => NONCE_INDEX as u32 + 1,
+                _ => NONCE_INDEX as u32,
+            };
+
+            let cert = message(vec![(TAG_SIG, dele_signature), (TAG_DELE, dele)]);
+            message(vec![
+                (TAG_SIG, srep_signature),
+                (TAG_PATH, path),
+                (TAG_SREP, srep),
+                (TAG_CERT, cert),
+                (TAG_INDX, index.to_le_bytes().to_vec()),
+            ])
+        }
+    }
+
+    fn sign(key: &SigningKey, context: &[u8], message: &[u8]) -> Vec<u8> {
+        key.sign(&[context, message].concat()).to_bytes().to_vec()
+    }
+
+    fn message(mut fields: Vec<(u32, Vec<u8>)>) -> Vec<u8> {
+        fields.sort_by_key(|(tag, _)| *tag);
+        let fields: Vec<(u32, &[u8])> = fields.iter().map(|(t, v)| (*t, v.as_slice())).collect();
+        encode_message(&fields)
+    }
+
+    /// 在 127.0.0.1 回答一次請求，回傳伺服器設定與收到請求的長度
+    fn serve(responder: Responder) -> (RoughtimeServer, std::thread::JoinHandle<usize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = responder.server(socket.local_addr().unwrap().to_string());
+        let handle = std::thread::spawn(move || {
+            let mut buf = [0u8; ROUGHTIME_MAX_RESPONSE_SIZE];
+            let (size, peer) = socket.recv_from(&mut buf).unwrap();
+            socket
+                .send_to(&responder.respond(&buf[..size]), peer)
+                .unwrap();
+            size
+        });
+        (server, handle)
+    }
+
+    fn query(fault: Fault) -> Result<RoughtimeChainResult, NtpError> {
+        let (server, handle) = serve(Responder::new(7, 0, fault));
+        let result = query_roughtime_chain(&[server], &QueryOptions::default());
+        assert_eq!(handle.join().unwrap(), ROUGHTIME_REQUEST_SIZE);
+        result
+    }
+
+    #[test]
+    fn verified_response() {
+        let chain = query(Fault::None).unwrap();
+        let result = &chain.results[0];
+        assert_eq!(result.protocol, TimeProtocol::Roughtime);
+        assert_eq!(result.root_dispersion, 1000.0);
+        assert!(result.offset.abs() < 1000.0);
+        assert!(chain.conflicts.is_empty());
+    }
+
+    #[test]
+    fn bad_delegation_signature() {
+        let error = query(Fault::DelegationSignature).unwrap_err();
+        assert_eq!(error.code, "ROUGHTIME_AUTH_FAILED");
+        assert!(error.error.contains("委派"));
+    }
+
+    #[test]
+    fn bad_response_signature() {
+        let error = query(Fault::ResponseSignature).unwrap_err();
+        assert_eq!(error.code, "ROUGHTIME_AUTH_FAILED");
+        assert!(error.error.contains("回應簽章"));
+    }
+
+    #[test]
+    fn wrong_merkle_index() {
+        let error = query(Fault::WrongIndex).unwrap_err();
+        assert_eq!(error.code, "ROUGHTIME_AUTH_FAILED");
+        assert!(error.error.contains("Merkle"));
+    }
+
+    #[test]
+    fn midpoint_outside_delegation() {
+        let error = query(Fault::OutsideValidity).unwrap_err();
+        assert_eq!(error.code, "ROUGHTIME_CERT_EXPIRED");
+    }
+
+    #[test]
+    fn index_beyond_path_is_rejected() {
+        let path = [0u8; HASH_LEN * 2];
+        let error = merkle_root(&[0u8; NONCE_LEN], &path, 4).unwrap_err();
+        assert_eq!(error.code, "ROUGHTIME_INVALID_RESPONSE");
+    }
+
+    #[test]
+    fn chain_detects_conflict() {
+        // 第二台伺服器的時間比第一台早 60 秒，半徑只有 1 秒
+        let (first, first_handle) = serve(Responder::new(1, 0, Fault::None));
+        let (second, second_handle) = serve(Responder::new(2, -60_000_000, Fault::None));
+        let chain =
+            query_roughtime_chain(&[first.clone(), second.clone()], &QueryOptions::default())
+                .unwrap();
+        first_handle.join().unwrap();
+        second_handle.join().unwrap();
+
+        assert_eq!(chain.results.len(), 2);
+        assert_eq!(chain.conflicts.len(), 1);
+        assert_eq!(chain.conflicts[0].earlier, first.address);
+        assert_eq!(chain.conflicts[0].later, second.address);
+    }
+
+    #[test]
+    fn options_timeout_is_used() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server =
+            Responder::new(3, 0, Fault::None).server(silent.local_addr().unwrap().to_string());
+        let options = QueryOptions {
+            timeout_ms: Some(100),
+            ..Default::default()
+        };
+        let started = std::time::Instant::now();
+        let error = query_roughtime_chain(&[server], &options).unwrap_err();
+        assert_eq!(error.code, "TIMEOUT");
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,583 @@
+use chrono::Datelike;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::Interest;
+
+use crate::core::engine;
+use crate::core::ntp::{
+    NtpError, NtpResult, TimeProtocol, MAX_DISTANCE_MS, NTP_MAX_RESPONSE_SIZE, NTP_PACKET_SIZE,
+    NTP_PORT, PHI, UNSYNCHRONIZED_STRATUM,
+};
+use crate::core::packet::{NtpPacket, ReferenceId};
+use crate::core::timestamp::{NtpShort, NtpTimestamp};
+use crate::core::timestamping;
+
+/// 回應中宣告的時鐘精度 (2^-20 s，約 1µs)
+const SERVER_PRECISION: i8 = -20;
+/// 速率限制表的上限，超過時清除閒置的客戶端
+const MAX_TRACKED_CLIENTS: usize = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub port: u16,
+    /// 允許查詢的位址或網段 (CIDR)，空白表示不限制
+    pub allow: Vec<String>,
+    /// 每個客戶端可連續發送的請求數
+    pub rate_limit_burst: u32,
+    /// 每個客戶端平均每秒可發送的請求數，超過時回應 Kiss-o'-Death RATE
+    pub rate_limit_per_sec: f64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: "0.0.0.0".to_string(),
+            port: NTP_PORT,
+            allow: Vec::new(),
+            rate_limit_burst: 8,
+            rate_limit_per_sec: 1.0,
+        }
+    }
+}
+
+/// 最近一次成功同步的上游資訊
+#[derive(Debug, Clone)]
+struct Upstream {
+    /// 上游回應的 leap indicator，閏秒預告只在同步當月有效
+    leap: u8,
+    stratum: u8,
+    upstream_ip: IpAddr,
+    ref_id: ReferenceId,
+    /// 上游 root delay 加上我們量測的 delay (ms)
+    root_delay: f64,
+    /// 上游 root dispersion 加上同步後的殘餘偏差 (ms)
+    root_dispersion: f64,
+    ref_time: NtpTimestamp,
+    synced_at: Instant,
+}
+
+impl Upstream {
+    /// 依經過時間累加 PHI 漂移後的 root dispersion
+    fn root_dispersion_now(&self) -> f64 {
+        self.root_dispersion + self.synced_at.elapsed().as_secs_f64() * PHI * 1000.0
+    }
+
+    fn is_synchronized(&self) -> bool {
+        self.leap != 3 && self.root_delay / 2.0 + self.root_dispersion_now() < MAX_DISTANCE_MS
+    }
+
+    /// 閏秒發生在月底，跨月後不再轉述上游的預告
+    fn leap_now(&self) -> u8 {
+        let month = |ms: f64| {
+            chrono::DateTime::from_timestamp_millis(ms as i64).map(|t| (t.year(), t.month()))
+        };
+        if month(self.ref_time.to_unix_ms()) == month(NtpTimestamp::now().to_unix_ms()) {
+            self.leap
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ServerStats {
+    requests: AtomicU64,
+    responses: AtomicU64,
+    rate_limited: AtomicU64,
+    denied: AtomicU64,
+}
+
+struct RunningServer {
+    config: ServerConfig,
+    local_addr: SocketAddr,
+    stats: Arc<ServerStats>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerStatus {
+    pub running: bool,
+    pub local_addr: Option<String>,
+    pub config: Option<ServerConfig>,
+    pub synchronized: bool,
+    pub stratum: u8,
+    pub ref_id: String,
+    pub root_delay: f64,
+    pub root_dispersion: f64,
+    pub requests: u64,
+    pub responses: u64,
+    pub rate_limited: u64,
+    pub denied: u64,
+}
+
+lazy_static::lazy_static! {
+    static ref UPSTREAM: Mutex<Option<Upstream>> = Mutex::new(None);
+    static ref RUNNING_SERVER: Mutex<Option<RunningServer>> = Mutex::new(None);
+}
+
+/// 系統時間設定成功後記錄上游資訊，作為伺服器模式的時間來源
+///
+/// 只有真正的 NTP 回應帶有 stratum 與 leap 資訊，HTTP、Time/Daytime、Roughtime 等
+/// 備援來源同步後仍以未同步 (LI=3, stratum 16) 回應
+pub fn record_sync(result: &NtpResult, delay_ms: f64, residual_offset_ms: f64) {
+    let upstream_ip = result.server_ip.parse::<IpAddr>();
+    let from_ntp = result.protocol == TimeProtocol::Ntp
+        && (1..UNSYNCHRONIZED_STRATUM).contains(&result.stratum);
+    let (true, Ok(upstream_ip)) = (from_ntp, upstream_ip) else {
+        println!(
+            "[SERVER] 時間來源 {} 非 NTP 伺服器，伺服器模式維持未同步",
+            result.protocol.as_str()
+        );
+        *UPSTREAM.lock().unwrap() = None;
+        return;
+    };
+
+    *UPSTREAM.lock().unwrap() = Some(Upstream {
+        leap: result.leap,
+        stratum: (result.stratum + 1).min(UNSYNCHRONIZED_STRATUM),
+        upstream_ip,
+        ref_id: ReferenceId::from_ip(upstream_ip),
+        root_delay: result.root_delay + delay_ms.max(0.0),
+        root_dispersion: result.root_dispersion + residual_offset_ms.abs(),
+        ref_time: NtpTimestamp::now(),
+        synced_at: Instant::now(),
+    });
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AllowEntry {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl AllowEntry {
+    fn parse(entry: &str) -> Result<Self, NtpError> {
+        let invalid = || NtpError::new("INVALID_CONFIG", format!("無效的允許清單項目: {}", entry));
+        let (addr, prefix) = match entry.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>().map_err(|_| invalid())?)),
+            None => (entry.trim(), None),
+        };
+        let network: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max_prefix);
+        if prefix > max_prefix {
+            return Err(invalid());
+        }
+        Ok(AllowEntry { network, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// 每個客戶端一個 token bucket
+struct RateLimiter {
+    burst: f64,
+    per_sec: f64,
+    clients: HashMap<IpAddr, (f64, Instant)>,
+}
+
+impl RateLimiter {
+    fn allow(&mut self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        if self.clients.len() >= MAX_TRACKED_CLIENTS {
+            let idle = Duration::from_secs_f64(self.burst / self.per_sec.max(f64::EPSILON));
+            self.clients.retain(|_, (_, last)| now.duration_since(*last) < idle);
+        }
+
+        let (tokens, last) = self.clients.entry(ip).or_insert((self.burst, now));
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * self.per_sec).min(self.burst);
+        *last = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// 將 IPv4-mapped IPv6 位址還原為 IPv4，讓綁定 [::] 時也能比對 IPv4 網段
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    }
+}
+
+fn kiss_of_death(request: &NtpPacket, kiss_code: &[u8; 4]) -> [u8; NTP_PACKET_SIZE] {
+    let now = NtpTimestamp::now();
+    NtpPacket {
+        leap: 3,
+        version: request.version,
+        mode: 4,
+        poll: request.poll,
+        precision: SERVER_PRECISION,
+        ref_id: ReferenceId(*kiss_code),
+        origin: request.transmit,
+        receive: now,
+        transmit: now,
+        ..Default::default()
+    }
+    .encode()
+}
+
+/// 依上游狀態組成 mode 4 回應，不支援的版本回傳 None
+fn build_response(request: &NtpPacket, t2: NtpTimestamp) -> Option<[u8; NTP_PACKET_SIZE]> {
+    if !(1..=4).contains(&request.version) {
+        return None;
+    }
+
+    let mut response = NtpPacket {
+        leap: 3,
+        version: request.version,
+        mode: 4,
+        stratum: UNSYNCHRONIZED_STRATUM,
+        poll: request.poll,
+        precision: SERVER_PRECISION,
+        origin: request.transmit,
+        receive: t2,
+        ..Default::default()
+    };
+
+    if let Some(upstream) = UPSTREAM.lock().unwrap().clone() {
+        response.stratum = upstream.stratum;
+        response.root_delay = NtpShort::from(Duration::from_secs_f64(upstream.root_delay / 1000.0));
+        response.root_dispersion = NtpShort::from(Duration::from_secs_f64(
+            upstream.root_dispersion_now() / 1000.0,
+        ));
+        response.ref_id = upstream.ref_id;
+        response.ref_time = upstream.ref_time;
+        if upstream.is_synchronized() {
+            response.leap = upstream.leap_now();
+        }
+    }
+
+    response.transmit = NtpTimestamp::now();
+    Some(response.encode())
+}
+
+async fn serve(
+    socket: tokio::net::UdpSocket,
+    raw: std::net::UdpSocket,
+    mode: timestamping::KernelTimestamping,
+    allow: Vec<AllowEntry>,
+    mut limiter: RateLimiter,
+    stats: Arc<ServerStats>,
+) {
+    let mut buf = [0u8; NTP_MAX_RESPONSE_SIZE];
+    loop {
+        if let Err(e) = socket.readable().await {
+            println!("[SERVER] socket 錯誤: {}", e);
+            return;
+        }
+        let (size, peer, t2, _) = match socket.try_io(Interest::READABLE, || {
+            timestamping::recv_from(&raw, &mut buf, mode)
+        }) {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+        // 只回應 client 模式的請求，避免與其他伺服器互相回應形成迴圈
+        let Ok(request) = NtpPacket::decode(&buf[..size]) else {
+            continue;
+        };
+        if request.mode != 3 {
+            continue;
+        }
+        stats.requests.fetch_add(1, Ordering::Relaxed);
+
+        let client = canonical_ip(peer.ip());
+        if !allow.is_empty() && !allow.iter().any(|entry| entry.contains(client)) {
+            stats.denied.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+
+        let response = if limiter.allow(client) {
+            build_response(&request, t2)
+        } else {
+            stats.rate_limited.fetch_add(1, Ordering::Relaxed);
+            Some(kiss_of_death(&request, b"RATE"))
+        };
+
+        if let Some(response) = response {
+            if socket.send_to(&response, peer).await.is_ok() {
+                stats.responses.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+pub fn start(config: ServerConfig) -> Result<ServerStatus, NtpError> {
+    let mut running = RUNNING_SERVER.lock().unwrap();
+    if let Some(server) = running.take() {
+        server.task.abort();
+    }
+
+    let ip: IpAddr = config.bind_address.trim().parse().map_err(|_| {
+        NtpError::new(
+            "INVALID_CONFIG",
+            format!("無效的綁定位址: {}", config.bind_address),
+        )
+    })?;
+    let allow = config
+        .allow
+        .iter()
+        .map(|entry| AllowEntry::parse(entry))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let bind_error =
+        |e: std::io::Error| NtpError::new("SOCKET_BIND", format!("無法綁定 NTP 伺服器 socket: {}", e));
+    let std_socket =
+        std::net::UdpSocket::bind(SocketAddr::new(ip, config.port)).map_err(bind_error)?;
+    let local_addr = std_socket.local_addr().map_err(bind_error)?;
+    let (socket, raw, mode) = engine::async_socket(std_socket).map_err(bind_error)?;
+
+    let limiter = RateLimiter {
+        burst: config.rate_limit_burst.max(1) as f64,
+        per_sec: config.rate_limit_per_sec.max(0.0),
+        clients: HashMap::new(),
+    };
+    let stats = Arc::new(ServerStats::default());
+    let task = tokio::spawn(serve(socket, raw, mode, allow, limiter, stats.clone()));
+
+    println!("[SERVER] NTP 伺服器已啟動於 {}", local_addr);
+    *running = Some(RunningServer {
+        config,
+        local_addr,
+        stats,
+        task,
+    });
+    drop(running);
+
+    Ok(status())
+}
+
+pub fn stop() -> bool {
+    match RUNNING_SERVER.lock().unwrap().take() {
+        Some(server) => {
+            server.task.abort();
+            println!("[SERVER] NTP 伺服器已停止");
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn status() -> ServerStatus {
+    let upstream = UPSTREAM.lock().unwrap().clone();
+    let running = RUNNING_SERVER.lock().unwrap();
+    let stats = running.as_ref().map(|s| s.stats.clone()).unwrap_or_default();
+
+    ServerStatus {
+        running: running.is_some(),
+        local_addr: running.as_ref().map(|s| s.local_addr.to_string()),
+        config: running.as_ref().map(|s| s.config.clone()),
+        synchronized: upstream.as_ref().map(|u| u.is_synchronized()).unwrap_or(false),
+        stratum: upstream
+            .as_ref()
+            .map(|u| u.stratum)
+            .unwrap_or(UNSYNCHRONIZED_STRATUM),
+        ref_id: upstream
+            .as_ref()
+            .map(|u| u.ref_id.describe(u.upstream_ip))
+            .unwrap_or_default(),
+        root_delay: upstream.as_ref().map(|u| u.root_delay).unwrap_or(0.0),
+        root_dispersion: upstream
+            .as_ref()
+            .map(|u| u.root_dispersion_now())
+            .unwrap_or(0.0),
+        requests: stats.requests.load(Ordering::Relaxed),
+        responses: stats.responses.load(Ordering::Relaxed),
+        rate_limited: stats.rate_limited.load(Ordering::Relaxed),
+        denied: stats.denied.load(Ordering::Relaxed),
+    }
+}
+
+#[tauri::command]
+pub async fn start_ntp_server(config: Option<ServerConfig>) -> Result<String, String> {
+    match start(config.unwrap_or_default()) {
+        Ok(status) => serde_json::to_string(&status).map_err(|e| e.to_string()),
+        Err(error) => {
+            println!("[SERVER] ✗ {} ({})", error.error, error.code);
+            serde_json::to_string(&error).map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn stop_ntp_server() -> Result<bool, String> {
+    Ok(stop())
+}
+
+#[tauri::command]
+pub async fn get_ntp_server_status() -> Result<String, String> {
+    serde_json::to_string(&status()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ntp::{self, QueryOptions};
+
+    fn upstream_result(protocol: TimeProtocol, stratum: u8, leap: u8) -> NtpResult {
+        upstream_from("192.0.2.1:123", protocol, stratum, leap)
+    }
+
+    fn upstream_from(addr: &str, protocol: TimeProtocol, stratum: u8, leap: u8) -> NtpResult {
+        let now = NtpTimestamp::now();
+        let addr: SocketAddr = addr.parse().unwrap();
+        let mut result = ntp::single_time_result("upstream", addr, (now, now), now, 0.5, protocol);
+        result.stratum = stratum;
+        result.leap = leap;
+        result
+    }
+
+    /// 伺服器與上游狀態都是全域的，測試需依序執行
+    static SERVER_LOCK: Mutex<()> = Mutex::new(());
+
+    /// 在 127.0.0.1 的隨機埠啟動伺服器，runtime 需保留到測試結束
+    fn start_local(config: ServerConfig) -> (tokio::runtime::Runtime, SocketAddr) {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let config = ServerConfig {
+            bind_address: "127.0.0.1".to_string(),
+            port: 0,
+            ..config
+        };
+        let started = runtime.block_on(async { start(config) }).unwrap();
+        let addr = started.local_addr.unwrap().parse().unwrap();
+        (runtime, addr)
+    }
+
+    fn query(addr: SocketAddr) -> Result<NtpResult, NtpError> {
+        let options = QueryOptions {
+            timeout_ms: Some(300),
+            ..Default::default()
+        };
+        ntp::query_ntp_addr("localhost", addr, &options)
+    }
+
+    #[test]
+    fn loopback_client_and_server() {
+        let _lock = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let (_runtime, addr) = start_local(ServerConfig::default());
+
+        *UPSTREAM.lock().unwrap() = None;
+        assert_eq!(query(addr).unwrap_err().code, "INVALID_STRATUM");
+
+        // 備援來源同步後不得宣告為 stratum 1
+        record_sync(&upstream_result(TimeProtocol::Http, 0, 0), 10.0, 0.1);
+        assert_eq!(query(addr).unwrap_err().code, "INVALID_STRATUM");
+
+        record_sync(&upstream_result(TimeProtocol::Ntp, 2, 0), 10.0, 0.1);
+        let synced = query(addr).unwrap();
+        assert_eq!((synced.leap, synced.stratum), (0, 3));
+        assert_eq!(synced.ref_id, "192.0.2.1");
+        assert!((synced.root_delay - 10.0).abs() < 0.1);
+
+        // IPv6 上游的 Reference ID 是雜湊，狀態中不顯示成 IPv4 位址
+        record_sync(
+            &upstream_from("[2001:db8::123]:123", TimeProtocol::Ntp, 2, 0),
+            10.0,
+            0.1,
+        );
+        assert_eq!(status().ref_id, "md5:c975cecc");
+
+        assert!(stop());
+        *UPSTREAM.lock().unwrap() = None;
+    }
+
+    #[test]
+    fn leap_indicator_is_passed_through() {
+        let _lock = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let (_runtime, addr) = start_local(ServerConfig::default());
+
+        record_sync(&upstream_result(TimeProtocol::Ntp, 1, 1), 10.0, 0.1);
+        assert_eq!(query(addr).unwrap().leap, 1);
+
+        record_sync(&upstream_result(TimeProtocol::Ntp, 1, 2), 10.0, 0.1);
+        assert_eq!(query(addr).unwrap().leap, 2);
+
+        // 上個月取得的預告在閏秒之後已失效
+        let last_month = NtpTimestamp::from_unix_nanos(
+            (chrono::Utc::now() - chrono::Duration::days(40))
+                .timestamp_nanos_opt()
+                .unwrap() as i128,
+        );
+        UPSTREAM.lock().unwrap().as_mut().unwrap().ref_time = last_month;
+        assert_eq!(query(addr).unwrap().leap, 0);
+
+        // 上游未同步時不轉述為已同步
+        record_sync(&upstream_result(TimeProtocol::Ntp, 1, 3), 10.0, 0.1);
+        assert_eq!(query(addr).unwrap_err().code, "SERVER_UNSYNCHRONIZED");
+
+        assert!(stop());
+        *UPSTREAM.lock().unwrap() = None;
+    }
+
+    #[test]
+    fn rate_limited_client_gets_kiss_of_death() {
+        let _lock = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let config = ServerConfig {
+            rate_limit_burst: 2,
+            rate_limit_per_sec: 0.0,
+            ..Default::default()
+        };
+        let (_runtime, addr) = start_local(config);
+        record_sync(&upstream_result(TimeProtocol::Ntp, 1, 0), 10.0, 0.1);
+
+        assert!(query(addr).is_ok());
+        assert!(query(addr).is_ok());
+        assert_eq!(query(addr).unwrap_err().code, "KOD_RATE");
+        assert_eq!(status().rate_limited, 1);
+
+        assert!(stop());
+        *UPSTREAM.lock().unwrap() = None;
+    }
+
+    #[test]
+    fn allow_list_drops_other_clients() {
+        let _lock = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        record_sync(&upstream_result(TimeProtocol::Ntp, 1, 0), 10.0, 0.1);
+
+        let config = ServerConfig {
+            allow: vec!["192.0.2.0/24".to_string(), "2001:db8::/32".to_string()],
+            ..Default::default()
+        };
+        let (_runtime, addr) = start_local(config);
+        assert_eq!(query(addr).unwrap_err().code, "TIMEOUT");
+        let denied = status();
+        assert_eq!((denied.denied, denied.responses), (1, 0));
+        assert!(stop());
+
+        let config = ServerConfig {
+            allow: vec!["127.0.0.0/8".to_string()],
+            ..Default::default()
+        };
+        let (_runtime, addr) = start_local(config);
+        assert!(query(addr).is_ok());
+        assert_eq!(status().denied, 0);
+
+        assert!(stop());
+        *UPSTREAM.lock().unwrap() = None;
+    }
+}
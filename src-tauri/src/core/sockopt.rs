@@ -0,0 +1,72 @@
+use std::io;
+use std::net::UdpSocket;
+
+#[cfg(unix)]
+fn set_option(
+    socket: &UdpSocket,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: *const libc::c_void,
+    len: usize,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            value,
+            len as libc::socklen_t,
+        )
+    };
+    match ret {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// SO_BINDTODEVICE：收發都限定在指定的網路介面，需要 CAP_NET_RAW
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn bind_to_device(socket: &UdpSocket, interface: &str) -> io::Result<()> {
+    set_option(
+        socket,
+        libc::SOL_SOCKET,
+        libc::SO_BINDTODEVICE,
+        interface.as_ptr() as *const libc::c_void,
+        interface.len(),
+    )
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn bind_to_device(_socket: &UdpSocket, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "此平台不支援 SO_BINDTODEVICE",
+    ))
+}
+
+/// 設定 IPv4 TOS 或 IPv6 traffic class 的 DSCP 欄位 (高 6 位元)
+#[cfg(unix)]
+pub fn set_dscp(socket: &UdpSocket, ipv6: bool, dscp: u8) -> io::Result<()> {
+    let tos: libc::c_int = (dscp as libc::c_int) << 2;
+    let (level, name) = match ipv6 {
+        true => (libc::IPPROTO_IPV6, libc::IPV6_TCLASS),
+        false => (libc::IPPROTO_IP, libc::IP_TOS),
+    };
+    set_option(
+        socket,
+        level,
+        name,
+        &tos as *const _ as *const libc::c_void,
+        std::mem::size_of_val(&tos),
+    )
+}
+
+#[cfg(not(unix))]
+pub fn set_dscp(_socket: &UdpSocket, _ipv6: bool, _dscp: u8) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "此平台不支援設定 DSCP",
+    ))
+}
@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::core::leap;
+use crate::core::ntp::NtpError;
+
+/// GPS 紀元 1980-01-06 00:00:00 UTC (Unix 秒)
+const GPS_EPOCH_UNIX_SECS: i64 = 315_964_800;
+/// TAI 與 GPS 時間的固定差距 (秒)
+const TAI_GPS_SECS: i32 = 19;
+/// 沒有 leap-seconds.list 時使用的 TAI-UTC (2017-01-01 起)
+const FALLBACK_TAI_UTC: i32 = 37;
+const MS_PER_WEEK: f64 = 7.0 * 86400.0 * 1000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeScale {
+    /// Unix 紀元起算的 UTC ms
+    Utc,
+    /// Unix 紀元起算的 TAI ms，與 Linux CLOCK_TAI 相同
+    Tai,
+    /// GPS 紀元 (1980-01-06) 起算的 GPS 時間 ms
+    Gps,
+    /// 24 小時線性 leap smear 的 UTC (Google、AWS)
+    SmearedUtc,
+}
+
+/// 同一時刻在各時間尺度的表示
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeScales {
+    pub utc_ms: f64,
+    pub tai_ms: f64,
+    pub gps_ms: f64,
+    pub gps_week: i64,
+    pub gps_seconds_of_week: f64,
+    pub smeared_utc_ms: f64,
+    pub tai_utc: i32,
+    /// TAI-UTC 是否來自 leap-seconds.list，否則為內建的最近值
+    pub tai_utc_verified: bool,
+}
+
+fn tai_utc(utc_ms: f64) -> (i32, bool) {
+    match leap::tai_utc_at(utc_ms as i64) {
+        Some(tai_utc) => (tai_utc, true),
+        None => (FALLBACK_TAI_UTC, false),
+    }
+}
+
+fn from_utc(utc_ms: f64, scale: TimeScale) -> f64 {
+    match scale {
+        TimeScale::Utc => utc_ms,
+        TimeScale::Tai => utc_ms + tai_utc(utc_ms).0 as f64 * 1000.0,
+        TimeScale::Gps => {
+            let gps_utc = tai_utc(utc_ms).0 - TAI_GPS_SECS;
+            utc_ms - (GPS_EPOCH_UNIX_SECS * 1000) as f64 + gps_utc as f64 * 1000.0
+        }
+        TimeScale::SmearedUtc => utc_ms + leap::smear_offset_ms(utc_ms),
+    }
+}
+
+/// 與 UTC 的差距隨時間變化緩慢，以前一次換算的結果重新計算差距，幾次即收斂
+fn to_utc(time_ms: f64, scale: TimeScale) -> f64 {
+    let mut utc_ms = time_ms;
+    for _ in 0..3 {
+        utc_ms = time_ms - (from_utc(utc_ms, scale) - utc_ms);
+    }
+    utc_ms
+}
+
+pub fn scales(utc_ms: f64) -> TimeScales {
+    let (tai_utc, tai_utc_verified) = tai_utc(utc_ms);
+    let gps_ms = from_utc(utc_ms, TimeScale::Gps);
+    TimeScales {
+        utc_ms,
+        tai_ms: from_utc(utc_ms, TimeScale::Tai),
+        gps_ms,
+        gps_week: (gps_ms / MS_PER_WEEK).floor() as i64,
+        gps_seconds_of_week: gps_ms.rem_euclid(MS_PER_WEEK) / 1000.0,
+        smeared_utc_ms: from_utc(utc_ms, TimeScale::SmearedUtc),
+        tai_utc,
+        tai_utc_verified,
+    }
+}
+
+pub fn convert(time_ms: f64, from: TimeScale) -> Result<TimeScales, NtpError> {
+    if !time_ms.is_finite() {
+        return Err(NtpError::new(
+            "INVALID_TIME",
+            format!("無效的時間: {}", time_ms),
+        ));
+    }
+    Ok(scales(to_utc(time_ms, from)))
+}
+
+/// 以 offset (通常為最近一次同步的結果) 校正目前的系統時間，回傳各時間尺度的表示
+#[tauri::command]
+pub async fn get_time_scales(offset_ms: Option<f64>) -> Result<String, String> {
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64() * 1000.0)
+        .unwrap_or(0.0);
+    let utc_ms = now_ms + offset_ms.unwrap_or(0.0);
+    serde_json::to_string(&scales(utc_ms)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn convert_time_scale(time_ms: f64, from: TimeScale) -> Result<String, String> {
+    match convert(time_ms, from) {
+        Ok(scales) => serde_json::to_string(&scales).map_err(|e| e.to_string()),
+        Err(error) => {
+            println!("[TIME] ✗ {} ({})", error.error, error.code);
+            serde_json::to_string(&error).map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2017-01-01 00:00:00 UTC，最近一次插入的閏秒結束的時間
+    const LEAP_2017_MS: f64 = 1_483_228_800_000.0;
+
+    fn load_fixture() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/leap/leap-seconds.list"
+        );
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime
+            .block_on(leap::load_leap_seconds_file(Some(path.to_string())))
+            .unwrap();
+    }
+
+    #[test]
+    fn tai_and_gps_step_at_leap() {
+        load_fixture();
+
+        let before = scales(LEAP_2017_MS - 1.0);
+        let after = scales(LEAP_2017_MS);
+        assert!(before.tai_utc_verified && after.tai_utc_verified);
+        assert_eq!((before.tai_utc, after.tai_utc), (36, 37));
+        // UTC 經過 1ms，TAI 與 GPS 經過 1.001s (23:59:60 這一秒)
+        assert_eq!(after.tai_ms - before.tai_ms, 1001.0);
+        assert_eq!(after.gps_ms - before.gps_ms, 1001.0);
+        assert_eq!(
+            after.tai_ms - after.gps_ms,
+            TAI_GPS_SECS as f64 * 1000.0 + GPS_EPOCH_UNIX_SECS as f64 * 1000.0
+        );
+        assert_eq!(after.gps_week, 1930);
+        assert_eq!(after.gps_seconds_of_week, 18.0);
+    }
+
+    #[test]
+    fn conversions_round_trip_around_leap() {
+        load_fixture();
+
+        for utc_ms in [
+            LEAP_2017_MS - 6.0 * 3600.0 * 1000.0,
+            LEAP_2017_MS - 1000.0,
+            LEAP_2017_MS,
+            LEAP_2017_MS + 1000.0,
+            LEAP_2017_MS + 6.0 * 3600.0 * 1000.0,
+        ] {
+            let expected = scales(utc_ms);
+            for (scale, time_ms) in [
+                (TimeScale::Utc, expected.utc_ms),
+                (TimeScale::Tai, expected.tai_ms),
+                (TimeScale::Gps, expected.gps_ms),
+                (TimeScale::SmearedUtc, expected.smeared_utc_ms),
+            ] {
+                let converted = convert(time_ms, scale).unwrap();
+                assert!(
+                    (converted.utc_ms - utc_ms).abs() < 0.01,
+                    "{:?} {} -> {}",
+                    scale,
+                    utc_ms,
+                    converted.utc_ms
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn smeared_utc_around_leap() {
+        load_fixture();
+
+        let smear = |utc_ms: f64| scales(utc_ms).smeared_utc_ms - utc_ms;
+        assert_eq!(smear(LEAP_2017_MS - 12.0 * 3600.0 * 1000.0), 0.0);
+        assert!((smear(LEAP_2017_MS - 6.0 * 3600.0 * 1000.0) + 250.0).abs() < 1e-3);
+        assert!((smear(LEAP_2017_MS) - 500.0).abs() < 1e-3);
+        // Unix ms 只前進 1ms，但其間經過 23:59:60 這一秒，smear 時鐘連續前進約 1.001s
+        let before = scales(LEAP_2017_MS - 1.0).smeared_utc_ms;
+        let after = scales(LEAP_2017_MS).smeared_utc_ms;
+        assert!((after - before - 1001.0).abs() < 0.1, "{}", after - before);
+    }
+}
@@ -0,0 +1,296 @@
+use chrono::{DateTime, Utc};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 1900-01-01 (NTP era 0) 到 1970-01-01 的秒數
+pub const NTP_TO_UNIX_EPOCH: i64 = 2208988800;
+/// 預設的 era 判斷基準日 2024-01-01，可正確還原 1956 ~ 2092 年間的時間戳
+pub const DEFAULT_PIVOT_UNIX_SECS: i64 = 1704067200;
+
+const ERA_SECONDS: i128 = 1 << 32;
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+/// NTP 64 位元時間戳 (32 位元秒 + 32 位元小數)，不含 era 資訊
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NtpTimestamp(u64);
+
+impl NtpTimestamp {
+    pub fn seconds(self) -> u32 {
+        (self.0 >> 32) as u32
+    }
+
+    pub fn fraction(self) -> u32 {
+        self.0 as u32
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    /// 隨機的非零值，隱私模式以此取代 Transmit Timestamp 中的本地時間
+    pub fn random() -> Self {
+        NtpTimestamp(rand::random::<u64>().max(1))
+    }
+
+    pub fn read(packet: &[u8], offset: usize) -> Self {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&packet[offset..offset + 8]);
+        NtpTimestamp(u64::from_be_bytes(bytes))
+    }
+
+    pub fn write(self, packet: &mut [u8], offset: usize) {
+        packet[offset..offset + 8].copy_from_slice(&self.0.to_be_bytes());
+    }
+
+    /// 由 Unix 奈秒建立，超出 era 0 的部分自動折返
+    pub fn from_unix_nanos(unix_nanos: i128) -> Self {
+        let ntp_nanos = unix_nanos + NTP_TO_UNIX_EPOCH as i128 * NANOS_PER_SEC;
+        let seconds = ntp_nanos.div_euclid(NANOS_PER_SEC);
+        let nanos = ntp_nanos.rem_euclid(NANOS_PER_SEC);
+        // 四捨五入讓奈秒 -> NTP -> 奈秒可以無損往返
+        let fraction = ((nanos << 32) + NANOS_PER_SEC / 2) / NANOS_PER_SEC;
+        NtpTimestamp((((seconds.rem_euclid(ERA_SECONDS)) << 32) + fraction) as u64)
+    }
+
+    /// 以 pivot 為中心選擇 era，結果落在 pivot ± 68 年之內
+    pub fn to_unix_nanos(self, pivot_unix_secs: i64) -> i128 {
+        let pivot_ntp = pivot_unix_secs as i128 + NTP_TO_UNIX_EPOCH as i128;
+        let base_era = (pivot_ntp - ERA_SECONDS / 2).div_euclid(ERA_SECONDS);
+        let mut seconds = base_era * ERA_SECONDS + self.seconds() as i128;
+        if seconds < pivot_ntp - ERA_SECONDS / 2 {
+            seconds += ERA_SECONDS;
+        }
+
+        let nanos = ((self.fraction() as i128 * NANOS_PER_SEC) + (1 << 31)) >> 32;
+        (seconds - NTP_TO_UNIX_EPOCH as i128) * NANOS_PER_SEC + nanos
+    }
+
+    pub fn to_unix_ms(self) -> f64 {
+        if self.is_zero() {
+            return 0.0;
+        }
+        self.to_unix_nanos(DEFAULT_PIVOT_UNIX_SECS) as f64 / 1_000_000.0
+    }
+
+    pub fn now() -> Self {
+        Self::from(SystemTime::now())
+    }
+
+    /// 與 earlier 的差值 (奈秒)，以 64 位元有號減法計算，與 era 無關
+    pub fn diff_nanos(self, earlier: NtpTimestamp) -> i128 {
+        let diff = self.0.wrapping_sub(earlier.0) as i64 as i128;
+        (diff * NANOS_PER_SEC) >> 32
+    }
+}
+
+/// NTP 32 位元短格式 (16 位元秒 + 16 位元小數)，用於 root delay / root dispersion
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NtpShort(u32);
+
+impl NtpShort {
+    pub fn read(packet: &[u8], offset: usize) -> Self {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&packet[offset..offset + 4]);
+        NtpShort(u32::from_be_bytes(bytes))
+    }
+
+    pub fn write(self, packet: &mut [u8], offset: usize) {
+        packet[offset..offset + 4].copy_from_slice(&self.0.to_be_bytes());
+    }
+
+    pub fn from_nanos(nanos: u64) -> Self {
+        let bits = (((nanos as u128) << 16) + NANOS_PER_SEC as u128 / 2) / NANOS_PER_SEC as u128;
+        NtpShort(bits.min(u32::MAX as u128) as u32)
+    }
+
+    pub fn to_nanos(self) -> u64 {
+        ((self.0 as u128 * NANOS_PER_SEC as u128) >> 16) as u64
+    }
+
+    pub fn to_ms(self) -> f64 {
+        self.0 as f64 / 65536.0 * 1000.0
+    }
+}
+
+impl From<SystemTime> for NtpTimestamp {
+    fn from(time: SystemTime) -> Self {
+        let unix_nanos = match time.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_nanos() as i128,
+            Err(e) => -(e.duration().as_nanos() as i128),
+        };
+        NtpTimestamp::from_unix_nanos(unix_nanos)
+    }
+}
+
+/// 以預設基準日判斷 era，需要其他基準日時改用 `to_unix_nanos`
+impl From<NtpTimestamp> for SystemTime {
+    fn from(timestamp: NtpTimestamp) -> Self {
+        let unix_nanos = timestamp.to_unix_nanos(DEFAULT_PIVOT_UNIX_SECS);
+        let magnitude = Duration::new(
+            (unix_nanos.unsigned_abs() / NANOS_PER_SEC as u128) as u64,
+            (unix_nanos.unsigned_abs() % NANOS_PER_SEC as u128) as u32,
+        );
+        if unix_nanos >= 0 {
+            UNIX_EPOCH + magnitude
+        } else {
+            UNIX_EPOCH - magnitude
+        }
+    }
+}
+
+impl From<DateTime<Utc>> for NtpTimestamp {
+    fn from(time: DateTime<Utc>) -> Self {
+        let unix_nanos =
+            time.timestamp() as i128 * NANOS_PER_SEC + time.timestamp_subsec_nanos() as i128;
+        NtpTimestamp::from_unix_nanos(unix_nanos)
+    }
+}
+
+impl From<NtpTimestamp> for DateTime<Utc> {
+    fn from(timestamp: NtpTimestamp) -> Self {
+        let unix_nanos = timestamp.to_unix_nanos(DEFAULT_PIVOT_UNIX_SECS);
+        DateTime::from_timestamp(
+            unix_nanos.div_euclid(NANOS_PER_SEC) as i64,
+            unix_nanos.rem_euclid(NANOS_PER_SEC) as u32,
+        )
+        .unwrap_or_default()
+    }
+}
+
+impl From<Duration> for NtpShort {
+    fn from(duration: Duration) -> Self {
+        NtpShort::from_nanos(duration.as_nanos().min(u64::MAX as u128) as u64)
+    }
+}
+
+impl From<NtpShort> for Duration {
+    fn from(short: NtpShort) -> Self {
+        Duration::from_nanos(short.to_nanos())
+    }
+}
+
+/// 依 RFC 5905 以奈秒計算 (offset, delay)
+pub fn offset_and_delay(
+    t1: NtpTimestamp,
+    t2: NtpTimestamp,
+    t3: NtpTimestamp,
+    t4: NtpTimestamp,
+) -> (i128, i128) {
+    let offset = (t2.diff_nanos(t1) + t3.diff_nanos(t4)) / 2;
+    let delay = t4.diff_nanos(t1) - t3.diff_nanos(t2);
+    (offset, delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2036-02-07 06:28:16 UTC，NTP era 0 結束
+    const ERA1_UNIX_SECS: i64 = 2085978496;
+
+    fn from_secs(unix_secs: i64) -> NtpTimestamp {
+        NtpTimestamp::from_unix_nanos(unix_secs as i128 * NANOS_PER_SEC)
+    }
+
+    #[test]
+    fn era_rollover_2036() {
+        let last = from_secs(ERA1_UNIX_SECS - 1);
+        let first = from_secs(ERA1_UNIX_SECS);
+        assert_eq!((last.seconds(), last.fraction()), (u32::MAX, 0));
+        assert_eq!((first.seconds(), first.fraction()), (0, 0));
+
+        let pivot = DEFAULT_PIVOT_UNIX_SECS;
+        assert_eq!(
+            first.to_unix_nanos(pivot),
+            ERA1_UNIX_SECS as i128 * NANOS_PER_SEC
+        );
+        assert_eq!(
+            last.to_unix_nanos(pivot),
+            (ERA1_UNIX_SECS - 1) as i128 * NANOS_PER_SEC
+        );
+        assert_eq!(first.diff_nanos(last), NANOS_PER_SEC);
+        assert_eq!(last.diff_nanos(first), -NANOS_PER_SEC);
+    }
+
+    #[test]
+    fn pivot_selects_nearest_era() {
+        let pivot = DEFAULT_PIVOT_UNIX_SECS;
+        let half_era = (ERA_SECONDS / 2) as i64;
+
+        // pivot - 2^31 秒仍屬於 pivot 所在的範圍，再早 1 秒則視為下一個 era
+        let lowest = pivot - half_era;
+        assert_eq!(
+            from_secs(lowest).to_unix_nanos(pivot),
+            lowest as i128 * NANOS_PER_SEC
+        );
+        assert_eq!(
+            from_secs(lowest - 1).to_unix_nanos(pivot),
+            (lowest - 1) as i128 * NANOS_PER_SEC + ERA_SECONDS * NANOS_PER_SEC
+        );
+
+        let highest = pivot + half_era - 1;
+        assert_eq!(
+            from_secs(highest).to_unix_nanos(pivot),
+            highest as i128 * NANOS_PER_SEC
+        );
+
+        // 同一個時間戳依 pivot 還原為不同 era
+        let timestamp = from_secs(ERA1_UNIX_SECS + 100);
+        assert_eq!(
+            timestamp.to_unix_nanos(ERA1_UNIX_SECS),
+            (ERA1_UNIX_SECS + 100) as i128 * NANOS_PER_SEC
+        );
+        assert_eq!(
+            timestamp.to_unix_nanos(-NTP_TO_UNIX_EPOCH),
+            (100 - NTP_TO_UNIX_EPOCH) as i128 * NANOS_PER_SEC
+        );
+    }
+
+    #[test]
+    fn negative_eras() {
+        // 1899-12-31 00:00:00 UTC 屬於 era -1
+        let unix_secs = -NTP_TO_UNIX_EPOCH - 86400;
+        let timestamp = from_secs(unix_secs);
+        assert_eq!(timestamp.seconds(), u32::MAX - 86399);
+        let pivot_1850 = -3786825600;
+        assert_eq!(
+            timestamp.to_unix_nanos(pivot_1850),
+            unix_secs as i128 * NANOS_PER_SEC
+        );
+
+        // 1960 年在預設 pivot 下仍為 era 0，Unix 時間為負值
+        let unix_nanos = -315_619_200 * NANOS_PER_SEC + 250_000_000;
+        let timestamp = NtpTimestamp::from_unix_nanos(unix_nanos);
+        assert_eq!(timestamp.to_unix_nanos(DEFAULT_PIVOT_UNIX_SECS), unix_nanos);
+        assert_eq!(timestamp.fraction(), 1 << 30);
+    }
+
+    #[test]
+    fn fraction_round_trip() {
+        let base = 1_700_000_000 * NANOS_PER_SEC;
+        for (nanos, fraction) in [(0, 0), (500_000_000, 1u32 << 31), (999_999_999, 4294967292)] {
+            let timestamp = NtpTimestamp::from_unix_nanos(base + nanos);
+            assert_eq!(timestamp.fraction(), fraction, "{}ns", nanos);
+            assert_eq!(
+                timestamp.to_unix_nanos(DEFAULT_PIVOT_UNIX_SECS),
+                base + nanos
+            );
+        }
+
+        // 1 - 2^-32 秒四捨五入為下一秒整
+        let timestamp =
+            NtpTimestamp(((1_700_000_000 + NTP_TO_UNIX_EPOCH as u64) << 32) | 0xFFFF_FFFF);
+        assert_eq!(
+            timestamp.to_unix_nanos(DEFAULT_PIVOT_UNIX_SECS),
+            base + NANOS_PER_SEC
+        );
+    }
+
+    #[test]
+    fn short_format_round_trip() {
+        for nanos in [0u64, 500_000_000, 1_000_000_000, 65_535_999_984_741] {
+            let short = NtpShort::from_nanos(nanos);
+            assert!(short.to_nanos().abs_diff(nanos) <= 7630, "{}ns", nanos);
+        }
+        assert_eq!(NtpShort::from_nanos(u64::MAX), NtpShort(u32::MAX));
+    }
+}
@@ -0,0 +1,316 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+use crate::core::timestamp::NtpTimestamp;
+
+/// t1/t4 的取得來源
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampSource {
+    /// 在 send/recv 前後呼叫 SystemTime::now()
+    #[default]
+    User,
+    /// 核心軟體時間戳 (SO_TIMESTAMPING / SO_TIMESTAMPNS)
+    Kernel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelTimestamping {
+    None,
+    /// 只有接收時間戳 (SO_TIMESTAMPNS)
+    RxOnly,
+    /// 接收與發送時間戳 (SO_TIMESTAMPING)
+    RxTx,
+}
+
+/// 非阻塞讀取的一筆發送時間戳，id 為 socket 上的送出序號 (SOF_TIMESTAMPING_OPT_ID)
+#[derive(Debug, Clone, Copy)]
+pub struct TxTimestamp {
+    pub id: u32,
+    pub timestamp: NtpTimestamp,
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{KernelTimestamping, TxTimestamp};
+    use crate::core::timestamp::NtpTimestamp;
+    use std::io;
+    use std::mem;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+    use std::os::unix::io::AsRawFd;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn set_option(fd: libc::c_int, name: libc::c_int, value: libc::c_uint) -> bool {
+        unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                name,
+                &value as *const _ as *const libc::c_void,
+                mem::size_of::<libc::c_uint>() as libc::socklen_t,
+            ) == 0
+        }
+    }
+
+    pub fn enable(socket: &UdpSocket) -> KernelTimestamping {
+        let fd = socket.as_raw_fd();
+        let flags = libc::SOF_TIMESTAMPING_TX_SOFTWARE
+            | libc::SOF_TIMESTAMPING_RX_SOFTWARE
+            | libc::SOF_TIMESTAMPING_SOFTWARE
+            | libc::SOF_TIMESTAMPING_OPT_TSONLY
+            | libc::SOF_TIMESTAMPING_OPT_ID;
+
+        if set_option(fd, libc::SO_TIMESTAMPING, flags) {
+            KernelTimestamping::RxTx
+        } else if set_option(fd, libc::SO_TIMESTAMPNS, 1) {
+            KernelTimestamping::RxOnly
+        } else {
+            KernelTimestamping::None
+        }
+    }
+
+    fn timespec_to_system_time(ts: &libc::timespec) -> Option<SystemTime> {
+        if ts.tv_sec == 0 && ts.tv_nsec == 0 {
+            return None;
+        }
+        Some(UNIX_EPOCH + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+    }
+
+    fn sockaddr_to_std(addr: &libc::sockaddr_storage) -> Option<SocketAddr> {
+        match addr.ss_family as libc::c_int {
+            libc::AF_INET => {
+                let addr: libc::sockaddr_in =
+                    unsafe { *(addr as *const _ as *const libc::sockaddr_in) };
+                Some(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                    u16::from_be(addr.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                let addr: libc::sockaddr_in6 =
+                    unsafe { *(addr as *const _ as *const libc::sockaddr_in6) };
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                    u16::from_be(addr.sin6_port),
+                    addr.sin6_flowinfo,
+                    addr.sin6_scope_id,
+                )))
+            }
+            _ => None,
+        }
+    }
+
+    pub struct Received {
+        pub size: usize,
+        pub peer: Option<SocketAddr>,
+        pub timestamp: Option<SystemTime>,
+        /// error queue 中發送時間戳的序號
+        pub tx_id: Option<u32>,
+    }
+
+    /// recvmsg 並取出 SCM_TIMESTAMPING / SCM_TIMESTAMPNS 控制訊息中的時間戳
+    pub fn recv_msg(socket: &UdpSocket, buf: &mut [u8], flags: libc::c_int) -> io::Result<Received> {
+        let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let mut control = [0u64; 64];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+        msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+
+        let size = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, flags) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut timestamp = None;
+        let mut tx_id = None;
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let header = &*cmsg;
+                if header.cmsg_level == libc::SOL_SOCKET {
+                    if header.cmsg_type == libc::SCM_TIMESTAMPING {
+                        // scm_timestamping: ts[0] 為軟體時間戳
+                        let ts: [libc::timespec; 3] =
+                            std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const _);
+                        timestamp = timespec_to_system_time(&ts[0]);
+                    } else if header.cmsg_type == libc::SCM_TIMESTAMPNS {
+                        let ts: libc::timespec =
+                            std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const _);
+                        timestamp = timespec_to_system_time(&ts);
+                    }
+                } else if (header.cmsg_level == libc::SOL_IP && header.cmsg_type == libc::IP_RECVERR)
+                    || (header.cmsg_level == libc::SOL_IPV6
+                        && header.cmsg_type == libc::IPV6_RECVERR)
+                {
+                    let err: libc::sock_extended_err =
+                        std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const _);
+                    if err.ee_errno == libc::ENOMSG as u32
+                        && err.ee_origin == libc::SO_EE_ORIGIN_TIMESTAMPING
+                    {
+                        tx_id = Some(err.ee_data);
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+
+        let peer = if msg.msg_namelen > 0 {
+            sockaddr_to_std(&addr)
+        } else {
+            None
+        };
+        Ok(Received {
+            size: size as usize,
+            peer,
+            timestamp,
+            tx_id,
+        })
+    }
+
+    /// 收到回應後非阻塞讀取 error queue，取最後一筆作為剛送出封包的核心發送時間戳
+    pub fn tx_timestamp(socket: &UdpSocket) -> Option<SystemTime> {
+        let mut latest = None;
+        let mut buf = [0u8; 64];
+        while let Ok(received) = recv_msg(socket, &mut buf, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT)
+        {
+            latest = received.timestamp.or(latest);
+        }
+        latest
+    }
+
+    /// 非阻塞讀取 error queue，佇列為空時回傳 WouldBlock
+    pub fn read_tx_timestamp(socket: &UdpSocket) -> io::Result<Option<TxTimestamp>> {
+        let mut buf = [0u8; 64];
+        let received = recv_msg(socket, &mut buf, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT)?;
+        Ok(match (received.tx_id, received.timestamp) {
+            (Some(id), Some(ts)) => Some(TxTimestamp {
+                id,
+                timestamp: NtpTimestamp::from(ts),
+            }),
+            _ => None,
+        })
+    }
+}
+
+pub fn enable(socket: &UdpSocket) -> KernelTimestamping {
+    #[cfg(target_os = "linux")]
+    {
+        linux::enable(socket)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = socket;
+        KernelTimestamping::None
+    }
+}
+
+/// 取得發送時間戳，須在收到回應後呼叫，不會阻塞等待；無法取得核心時間戳時回傳 None
+pub fn tx_timestamp(socket: &UdpSocket, mode: KernelTimestamping) -> Option<NtpTimestamp> {
+    #[cfg(target_os = "linux")]
+    {
+        if mode == KernelTimestamping::RxTx {
+            return linux::tx_timestamp(socket).map(NtpTimestamp::from);
+        }
+        None
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (socket, mode);
+        None
+    }
+}
+
+/// 非阻塞讀取一筆發送時間戳，供共用 socket 依序號對應請求
+pub fn read_tx_timestamp(socket: &UdpSocket) -> io::Result<Option<TxTimestamp>> {
+    #[cfg(target_os = "linux")]
+    {
+        linux::read_tx_timestamp(socket)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = socket;
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+}
+
+/// 接收封包，並盡可能以核心接收時間戳作為 t4
+pub fn recv_from(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    mode: KernelTimestamping,
+) -> io::Result<(usize, SocketAddr, NtpTimestamp, TimestampSource)> {
+    #[cfg(target_os = "linux")]
+    {
+        if mode != KernelTimestamping::None {
+            let received = linux::recv_msg(socket, buf, 0)?;
+            let user_ts = NtpTimestamp::now();
+            let (size, peer) = (received.size, received.peer);
+            let peer = peer.ok_or_else(|| io::Error::other("無法取得回應來源位址"))?;
+            return Ok(match received.timestamp {
+                Some(ts) => (size, peer, NtpTimestamp::from(ts), TimestampSource::Kernel),
+                None => (size, peer, user_ts, TimestampSource::User),
+            });
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = mode;
+
+    let (size, peer) = socket.recv_from(buf)?;
+    Ok((size, peer, NtpTimestamp::now(), TimestampSource::User))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn loopback_receive_timestamp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mode = enable(&receiver);
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender_mode = enable(&sender);
+        let before = NtpTimestamp::now();
+        sender
+            .send_to(b"timestamp", receiver.local_addr().unwrap())
+            .unwrap();
+
+        let mut buf = [0u8; 64];
+        let (size, peer, t4, source) = recv_from(&receiver, &mut buf, mode).unwrap();
+        let after = NtpTimestamp::now();
+        assert_eq!(&buf[..size], b"timestamp");
+        assert_eq!(peer, sender.local_addr().unwrap());
+        assert!(matches!(
+            source,
+            TimestampSource::Kernel | TimestampSource::User
+        ));
+        if mode == KernelTimestamping::None {
+            assert_eq!(source, TimestampSource::User);
+        }
+        assert!(before.to_unix_ms() <= t4.to_unix_ms() + 1.0);
+        assert!(t4.to_unix_ms() <= after.to_unix_ms() + 1.0);
+
+        // 發送時間戳在封包送達後已可非阻塞讀取，且不早於送出前的時間
+        if let Some(t1) = tx_timestamp(&sender, sender_mode) {
+            assert!(before.to_unix_ms() <= t1.to_unix_ms() + 1.0);
+            assert!(t1.to_unix_ms() <= t4.to_unix_ms() + 1.0);
+        }
+    }
+}
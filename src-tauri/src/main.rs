@@ -205,7 +205,7 @@ fn main() {
                         let handle = app.clone();
                         tauri::async_runtime::spawn(async move {
                             let server = "time.exptech.com.tw".to_string();
-                            let _ = core::offset::sync_ntp_time(server).await;
+                            let _ = core::offset::sync_ntp_time(server, None).await;
                             println!("[TRAY] 同步完成");
                             let _ = handle.emit("ntp-synced", ());
                         });
@@ -242,10 +242,15 @@ fn main() {
 
             let sync_handle = app.handle().clone();
             tauri::async_runtime::spawn(async move {
+                let server = "time.exptech.com.tw".to_string();
                 loop {
-                    tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
-                    let server = "time.exptech.com.tw".to_string();
-                    match core::offset::sync_ntp_time(server).await {
+                    // 輪詢間隔依 Kiss-o'-Death 退避狀態調整
+                    tokio::time::sleep(core::kod::poll_interval(&server)).await;
+                    if core::kod::is_disabled(&server) {
+                        println!("[BG] {} 已停用，略過背景同步", server);
+                        continue;
+                    }
+                    match core::offset::sync_ntp_time(server.clone(), None).await {
                         Ok(_) => println!("[BG] 背景同步完成"),
                         Err(e) => println!("[BG] 背景同步失敗: {}", e),
                     }
@@ -265,11 +270,34 @@ fn main() {
         .invoke_handler(tauri::generate_handler![
             // Core - NTP
             core::ntp::query_ntp_udp,
+            core::ntp::query_ntp_udp_all,
+            core::engine::query_ntp_servers,
+            core::ntp::inspect_ntp_server,
+            core::ntp::get_server_options,
+            core::ntp::set_server_options,
+            core::ntp::delete_server_options,
+            // Core - Roughtime
+            core::roughtime::query_roughtime_servers,
+            // Core - RFC 868/867
+            core::legacy::query_legacy_time,
             // Core - Offset
             core::offset::adjust_time_by_offset,
             core::offset::set_system_time_ms,
             core::offset::check_time_permission,
             core::offset::sync_ntp_time,
+            // Core - Server
+            core::server::start_ntp_server,
+            core::server::stop_ntp_server,
+            core::server::get_ntp_server_status,
+            // Core - Kiss-o'-Death
+            core::kod::get_kod_states,
+            core::kod::reenable_ntp_server,
+            // Core - Leap seconds
+            core::leap::get_leap_status,
+            core::leap::load_leap_seconds_file,
+            // Core - Time scales
+            core::timescale::get_time_scales,
+            core::timescale::convert_time_scale,
             // Core - Database
             core::db::db_init,
             core::db::db_insert_record,